@@ -1,5 +1,27 @@
 use gl::types::*;
-use cgmath::{ Rad, Deg, Matrix4, PerspectiveFov, Point3, Vector3 };
+use cgmath::{ ortho, Rad, Deg, Matrix4, PerspectiveFov, Point3, Vector3, InnerSpace, SquareMatrix, Zero };
+use crate::uniform_buffer::{ Std140, Std140Writer };
+
+// The uniform block with the per frame camera data that all programs share:
+//   layout (std140) uniform Camera { mat4 View; mat4 Projection; vec3 EyePosition; float Time; };
+pub const CAMERA_BLOCK: &str = "Camera";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+  // with the camera's fovy and aspect
+  Perspective,
+  // height world units from the bottom to the top of the view, the width follows from the aspect
+  Orthographic { height: GLfloat },
+  // every world unit covers pixels_per_unit * zoom pixels of a viewport of the given size in pixels, so that
+  // the texels of sprites made for pixels_per_unit stay square and line up with the pixels of the screen.
+  // The viewport is the one the camera was built for, a window that is resized needs a new camera.
+  PixelPerfect { viewport: (u32, u32), pixels_per_unit: GLfloat, zoom: u32 }
+}
+
+// The largest whole zoom at which a reference resolution fits into the viewport, at least 1
+pub fn pixel_perfect_zoom(viewport: (u32, u32), reference: (u32, u32)) -> u32 {
+  (viewport.0 / reference.0.max(1)).min(viewport.1 / reference.1.max(1)).max(1)
+}
 
 pub struct Camera {
   pub view_matrix: Matrix4<GLfloat>,
@@ -8,28 +30,122 @@ pub struct Camera {
   pub up: Vector3<GLfloat>,
 
   pub projection_matrix: Matrix4<GLfloat>,
+  pub projection: Projection,
   pub fovy: Rad<GLfloat>,
   pub aspect: GLfloat,
   pub near: GLfloat,
   pub far: GLfloat
 }
 
+impl Camera {
+  // Recomputes the view matrix after eye, target or up changed. A pixel perfect view is moved to whole pixels,
+  // so that texels don't shimmer while the camera scrolls; call this every frame the camera moves.
+  pub fn update_view(&mut self) {
+    self.view_matrix = Matrix4::look_at(self.eye, self.target, self.up);
+    if let Projection::PixelPerfect { pixels_per_unit, zoom, .. } = self.projection {
+      let scale = pixels_per_unit * zoom as GLfloat;
+      self.view_matrix.w.x = (self.view_matrix.w.x * scale).round() / scale;
+      self.view_matrix.w.y = (self.view_matrix.w.y * scale).round() / scale;
+    }
+  }
+
+  // The view space box of an orthographic projection as left, right, bottom and top, None for a perspective one
+  pub fn ortho_bounds(&self) -> Option<[GLfloat; 4]> {
+    match self.projection {
+      Projection::Perspective => None,
+      Projection::Orthographic { height } => {
+        let (half_width, half_height) = (height * self.aspect / 2.0, height / 2.0);
+        Some([-half_width, half_width, -half_height, half_height])
+      },
+      Projection::PixelPerfect { viewport, pixels_per_unit, zoom } => {
+        let scale = pixels_per_unit * zoom as GLfloat;
+        // whole pixels left and below of the center, an odd viewport would otherwise cut pixels in half
+        let (left, bottom) = (-((viewport.0 / 2) as GLfloat) / scale, -((viewport.1 / 2) as GLfloat) / scale);
+        Some([left, left + viewport.0 as GLfloat / scale, bottom, bottom + viewport.1 as GLfloat / scale])
+      }
+    }
+  }
+
+  // Corners of the view frustum between the given distances from the eye,
+  // near plane first: bottom left, bottom right, top right, top left
+  pub fn frustum_corners(&self, near: GLfloat, far: GLfloat) -> [Point3<GLfloat>; 8] {
+    let forward = (self.target - self.eye).normalize();
+    let right = forward.cross(self.up).normalize();
+    let up = right.cross(forward);
+    let tan_half_fovy = (self.fovy.0 / 2.0).tan();
+    let mut corners = [self.eye; 8];
+    for (plane, distance) in [near, far].iter().enumerate() {
+      let [left, right_edge, bottom, top] = self.ortho_bounds().unwrap_or_else(|| {
+        let half_height = tan_half_fovy * distance;
+        let half_width = half_height * self.aspect;
+        [-half_width, half_width, -half_height, half_height]
+      });
+      let center = self.eye + forward * *distance;
+      corners[plane * 4] = center + right * left + up * bottom;
+      corners[plane * 4 + 1] = center + right * right_edge + up * bottom;
+      corners[plane * 4 + 2] = center + right * right_edge + up * top;
+      corners[plane * 4 + 3] = center + right * left + up * top;
+    }
+    corners
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraBlock {
+  pub view: Matrix4<GLfloat>,
+  pub projection: Matrix4<GLfloat>,
+  pub eye_position: Vector3<GLfloat>,
+  // seconds since the game started
+  pub time: GLfloat
+}
+
+impl CameraBlock {
+  pub fn new(camera: &Camera, time: GLfloat) -> Self {
+    CameraBlock {
+      view: camera.view_matrix,
+      projection: camera.projection_matrix,
+      eye_position: Vector3::new(camera.eye.x, camera.eye.y, camera.eye.z),
+      time
+    }
+  }
+}
+
+impl Default for CameraBlock {
+  fn default() -> Self {
+    CameraBlock { view: Matrix4::identity(), projection: Matrix4::identity(), eye_position: Vector3::zero(), time: 0.0 }
+  }
+}
+
+impl Std140 for CameraBlock {
+  fn write_std140(&self, writer: &mut Std140Writer) {
+    writer.member(&self.view).member(&self.projection).member(&self.eye_position).member(&self.time);
+  }
+}
+
 pub struct CameraBuilder {
   pub eye: Point3<GLfloat>,
   pub target: Point3<GLfloat>,
   pub up: Vector3<GLfloat>,
+  pub projection: Projection,
   pub fovy: Rad<GLfloat>,
   pub aspect: GLfloat,
   pub near: GLfloat,
   pub far: GLfloat
 }
 
+impl Default for CameraBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl CameraBuilder {
   pub fn new() -> CameraBuilder {
     CameraBuilder {
       eye: Point3::new(0.0, 0.0, -2.0),
       target: Point3::new(0.0, 0.0, 0.0),
       up: Vector3::new(0.0, 1.0, 0.0),
+      projection: Projection::Perspective,
       fovy: Rad::from( Deg(45.0) ),
       aspect: 16.0/9.0,
       near: 0.1,
@@ -79,22 +195,146 @@ impl CameraBuilder {
     self
   }
 
+  // An orthographic projection height world units high, the width follows from the aspect
+  #[allow(dead_code)]
+  pub fn with_orthographic(mut self, height: GLfloat) -> CameraBuilder {
+    self.projection = Projection::Orthographic { height };
+    self
+  }
+
+  // An orthographic projection for a viewport of width by height pixels where every world unit covers
+  // pixels_per_unit * zoom pixels, see Projection::PixelPerfect. The aspect follows from the viewport.
+  #[allow(dead_code)]
+  pub fn with_pixel_perfect(mut self, width: u32, height: u32, pixels_per_unit: GLfloat, zoom: u32) -> CameraBuilder {
+    self.projection = Projection::PixelPerfect { viewport: (width, height), pixels_per_unit, zoom: zoom.max(1) };
+    self.aspect = width as GLfloat / height.max(1) as GLfloat;
+    self
+  }
+
   pub fn build(self) -> Camera {
-    Camera {
+    let mut camera = Camera {
       eye: self.eye,
       target: self.target,
       up: self.up,
+      projection: self.projection,
       fovy: self.fovy,
       aspect: self.aspect,
       near: self.near,
       far: self.far,
-      view_matrix: Matrix4::look_at(self.eye, self.target, self.up),
+      view_matrix: Matrix4::identity(),
       projection_matrix: Matrix4::from(PerspectiveFov {
         fovy: self.fovy,
         aspect: self.aspect,
         near: self.near,
         far: self.far
       }),
+    };
+    if let Some([left, right, bottom, top]) = camera.ortho_bounds() {
+      camera.projection_matrix = ortho(left, right, bottom, top, self.near, self.far);
     }
+    camera.update_view();
+    camera
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::Vector4;
+
+  #[test]
+  fn camera_block_packs_time_after_the_eye_position() {
+    // arrange
+    let camera = CameraBuilder::new().with_eye(Point3::new(1.0, 2.0, 3.0)).build();
+    // act
+    let bytes = CameraBlock::new(&camera, 4.5).std140_bytes();
+    // assert: two matrices, then the vec3 with the float in its padding
+    assert_eq!(144, bytes.len());
+    let float_at = |offset: usize| GLfloat::from_ne_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    assert_eq!(1.0, float_at(128));
+    assert_eq!(3.0, float_at(136));
+    assert_eq!(4.5, float_at(140));
+  }
+
+  #[test]
+  fn pixel_perfect_maps_texels_to_whole_pixels() {
+    // arrange
+    let camera = CameraBuilder::new()
+      .with_eye(Point3::new(0.03, 0.0, 10.0))
+      .with_target(Point3::new(0.03, 0.0, 0.0))
+      .with_pixel_perfect(321, 180, 16.0, 2)
+      .build();
+    let view_projection = camera.projection_matrix * camera.view_matrix;
+    let to_pixels = |x: GLfloat, y: GLfloat| {
+      let clip: Vector4<GLfloat> = view_projection * Vector4::new(x, y, 0.0, 1.0);
+      ((clip.x + 1.0) / 2.0 * 321.0, (clip.y + 1.0) / 2.0 * 180.0)
+    };
+    // act
+    let (x0, y0) = to_pixels(0.0, 0.0);
+    let (x1, y1) = to_pixels(1.0 / 16.0, 1.0 / 16.0);
+    // assert: a texel is two pixels and its edges are on pixel edges, even with an odd width and a scrolled camera
+    assert!((x1 - x0 - 2.0).abs() < 1e-3 && (y1 - y0 - 2.0).abs() < 1e-3, "texel is {} by {} pixels", x1 - x0, y1 - y0);
+    assert!((x0 - x0.round()).abs() < 1e-3 && (y0 - y0.round()).abs() < 1e-3, "texel starts at {}, {}", x0, y0);
+  }
+
+  #[test]
+  fn moved_pixel_perfect_camera_snaps_to_whole_pixels() {
+    // arrange
+    let mut camera = CameraBuilder::new()
+      .with_eye(Point3::new(0.0, 0.0, 10.0))
+      .with_target(Point3::new(0.0, 0.0, 0.0))
+      .with_pixel_perfect(320, 180, 16.0, 2)
+      .build();
+    // act
+    camera.eye = Point3::new(0.01, 0.02, 10.0);
+    camera.target = Point3::new(0.01, 0.02, 0.0);
+    camera.update_view();
+    // assert: 0.01 and 0.02 are closest to 0 and 1 of the 32 pixels per unit
+    assert_eq!(0.0, camera.view_matrix.w.x);
+    assert_eq!(-1.0 / 32.0, camera.view_matrix.w.y);
+  }
+
+  #[test]
+  fn pixel_perfect_zoom_fits_the_reference_resolution() {
+    assert_eq!(4, pixel_perfect_zoom((1600, 900), (320, 200)));
+    assert_eq!(2, pixel_perfect_zoom((1280, 1024), (480, 270)));
+    assert_eq!(1, pixel_perfect_zoom((200, 100), (320, 180)));
+  }
+
+  #[test]
+  fn frustum_corners_of_an_orthographic_camera() {
+    // arrange
+    let camera = CameraBuilder::new()
+      .with_eye(Point3::new(0.0, 0.0, 10.0))
+      .with_target(Point3::new(0.0, 0.0, 0.0))
+      .with_aspect(2.0)
+      .with_orthographic(4.0)
+      .build();
+    // act
+    let corners = camera.frustum_corners(1.0, 5.0);
+    // assert
+    assert_eq!(Point3::new(-4.0, -2.0, 9.0), corners[0]);
+    assert_eq!(Point3::new(4.0, 2.0, 5.0), corners[6]);
+  }
+
+  #[test]
+  fn frustum_corners_project_to_ndc_corners() {
+    // arrange
+    let camera = CameraBuilder::new()
+      .with_eye(Point3::new(1.0, 2.0, 3.0))
+      .with_target(Point3::new(0.0, 0.0, 0.0))
+      .build();
+    let view_projection = camera.projection_matrix * camera.view_matrix;
+    // act
+    let corners = camera.frustum_corners(camera.near, camera.far);
+    // assert
+    let expected_xy = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    for (i, corner) in corners.iter().enumerate() {
+      let clip: Vector4<GLfloat> = view_projection * corner.to_homogeneous();
+      let ndc = clip.truncate() / clip.w;
+      let (x, y) = expected_xy[i % 4];
+      let z = if i < 4 { -1.0 } else { 1.0 };
+      assert!((ndc.x - x).abs() < 1e-3 && (ndc.y - y).abs() < 1e-3 && (ndc.z - z).abs() < 1e-3, "corner {} is at {:?}", i, ndc);
+    }
+  }
+}
@@ -0,0 +1,232 @@
+use gl::types::*;
+use cgmath::{ InnerSpace, Matrix4, Point3, Vector3, Vector4 };
+
+// Axis aligned bounding box in model space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+  pub min: Point3<GLfloat>,
+  pub max: Point3<GLfloat>
+}
+
+impl Aabb {
+  pub fn new(min: Point3<GLfloat>, max: Point3<GLfloat>) -> Self {
+    Aabb { min, max }
+  }
+
+  // Bounds of interleaved vertices whose first three floats are the position, None without vertices
+  pub fn from_vertices(vertices: &[GLfloat], floats_per_vertex: usize) -> Option<Aabb> {
+    if floats_per_vertex < 3 { return None; }
+    Aabb::from_positions(vertices.chunks_exact(floats_per_vertex).map(|v| Point3::new(v[0], v[1], v[2])))
+  }
+
+  pub fn from_positions<I: IntoIterator<Item = Point3<GLfloat>>>(positions: I) -> Option<Aabb> {
+    let mut positions = positions.into_iter();
+    let first = positions.next()?;
+    Some(positions.fold(Aabb::new(first, first), |aabb, p| aabb.union(&Aabb::new(p, p))))
+  }
+
+  pub fn union(&self, other: &Aabb) -> Aabb {
+    Aabb {
+      min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+      max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z))
+    }
+  }
+
+  pub fn center(&self) -> Point3<GLfloat> {
+    Point3::new((self.min.x + self.max.x) / 2.0, (self.min.y + self.max.y) / 2.0, (self.min.z + self.max.z) / 2.0)
+  }
+
+  // half the size along each axis
+  pub fn extents(&self) -> Vector3<GLfloat> {
+    (self.max - self.min) / 2.0
+  }
+
+  // The box that encloses this box after the transform
+  pub fn transformed(&self, transform: Matrix4<GLfloat>) -> Aabb {
+    let center = Point3::from_homogeneous(transform * self.center().to_homogeneous());
+    let e = self.extents();
+    let abs = |column: Vector4<GLfloat>| Vector3::new(column.x.abs(), column.y.abs(), column.z.abs());
+    let extents = abs(transform.x) * e.x + abs(transform.y) * e.y + abs(transform.z) * e.z;
+    Aabb::new(center + (-extents), center + extents)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+  pub center: Point3<GLfloat>,
+  pub radius: GLfloat
+}
+
+impl BoundingSphere {
+  pub fn new(center: Point3<GLfloat>, radius: GLfloat) -> Self {
+    BoundingSphere { center, radius }
+  }
+
+  // Encloses the box, the center is the center of the box
+  pub fn from_aabb(aabb: &Aabb) -> Self {
+    BoundingSphere::new(aabb.center(), aabb.extents().magnitude())
+  }
+
+  // The radius grows with the largest scale of the transform
+  pub fn transformed(&self, transform: Matrix4<GLfloat>) -> BoundingSphere {
+    let center = Point3::from_homogeneous(transform * self.center.to_homogeneous());
+    let scale = [transform.x, transform.y, transform.z].iter()
+      .map(|column| column.truncate().magnitude())
+      .fold(0.0, GLfloat::max);
+    BoundingSphere::new(center, self.radius * scale)
+  }
+}
+
+// Model space bounds of a mesh, the component that frustum culling tests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundingVolume {
+  Box(Aabb),
+  Sphere(BoundingSphere)
+}
+
+impl BoundingVolume {
+  pub fn transformed(&self, transform: Matrix4<GLfloat>) -> BoundingVolume {
+    match self {
+      BoundingVolume::Box(aabb) => BoundingVolume::Box(aabb.transformed(transform)),
+      BoundingVolume::Sphere(sphere) => BoundingVolume::Sphere(sphere.transformed(transform))
+    }
+  }
+}
+
+// Points with normal.dot(p) + distance >= 0 are on the inner side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+  pub normal: Vector3<GLfloat>,
+  pub distance: GLfloat
+}
+
+impl Plane {
+  // Normalizes the plane equation ax + by + cz + d = 0
+  fn from_coefficients(coefficients: Vector4<GLfloat>) -> Plane {
+    let normal = coefficients.truncate();
+    let length = normal.magnitude();
+    Plane { normal: normal / length, distance: coefficients.w / length }
+  }
+
+  pub fn signed_distance(&self, point: Point3<GLfloat>) -> GLfloat {
+    self.normal.dot(Vector3::new(point.x, point.y, point.z)) + self.distance
+  }
+}
+
+// The six planes of a view frustum with normals pointing inwards: left, right, bottom, top, near, far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+  pub planes: [Plane; 6]
+}
+
+impl Frustum {
+  // Extracts the planes in world space from projection * view (Gribb and Hartmann)
+  pub fn from_matrix(view_projection: Matrix4<GLfloat>) -> Frustum {
+    let m = view_projection;
+    let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    Frustum {
+      planes: [
+        Plane::from_coefficients(w + x),
+        Plane::from_coefficients(w - x),
+        Plane::from_coefficients(w + y),
+        Plane::from_coefficients(w - y),
+        Plane::from_coefficients(w + z),
+        Plane::from_coefficients(w - z)
+      ]
+    }
+  }
+
+  // Conservative: a box near a frustum corner can pass although it is outside
+  pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+    let center = aabb.center();
+    let extents = aabb.extents();
+    self.planes.iter().all(|plane| {
+      let radius = extents.x * plane.normal.x.abs() + extents.y * plane.normal.y.abs() + extents.z * plane.normal.z.abs();
+      plane.signed_distance(center) >= -radius
+    })
+  }
+
+  pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+    self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+  }
+
+  // Tests the model space bounds after the model transform
+  pub fn intersects(&self, bounds: &BoundingVolume, model: Matrix4<GLfloat>) -> bool {
+    match bounds.transformed(model) {
+      BoundingVolume::Box(aabb) => self.intersects_aabb(&aabb),
+      BoundingVolume::Sphere(sphere) => self.intersects_sphere(&sphere)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::{ Deg, PerspectiveFov, SquareMatrix };
+
+  // looks down -z from the origin, near 1 and far 10
+  fn frustum() -> Frustum {
+    let projection = Matrix4::from(PerspectiveFov { fovy: Deg(90.0).into(), aspect: 1.0, near: 1.0, far: 10.0 });
+    Frustum::from_matrix(projection * Matrix4::identity())
+  }
+
+  fn unit_box() -> Aabb {
+    Aabb::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5))
+  }
+
+  #[test]
+  fn aabb_from_interleaved_vertices_skips_other_attributes() {
+    // arrange: position and color
+    let vertices = [
+      -1.0, 2.0, 0.0, 9.0, 9.0, 9.0, 9.0,
+      3.0, -2.0, 0.5, -9.0, -9.0, -9.0, -9.0
+    ];
+    // act
+    let aabb = Aabb::from_vertices(&vertices, 7).expect("vertices should have bounds");
+    // assert
+    assert_eq!(Aabb::new(Point3::new(-1.0, -2.0, 0.0), Point3::new(3.0, 2.0, 0.5)), aabb);
+    assert_eq!(None, Aabb::from_vertices(&[], 7));
+  }
+
+  #[test]
+  fn transformed_aabb_encloses_rotated_box() {
+    // act
+    let aabb = unit_box().transformed(Matrix4::from_translation(Vector3::new(0.0, 0.0, -5.0)) * Matrix4::from_angle_y(Deg(45.0)));
+    // assert
+    let half_diagonal = 0.5 * 2.0f32.sqrt();
+    assert!((aabb.extents().x - half_diagonal).abs() < 1e-5);
+    assert!((aabb.extents().y - 0.5).abs() < 1e-5);
+    assert!((aabb.center().z + 5.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn sphere_scales_with_largest_axis() {
+    let sphere = BoundingSphere::from_aabb(&unit_box()).transformed(Matrix4::from_nonuniform_scale(1.0, 3.0, 2.0));
+    assert!((sphere.radius - 3.0 * 0.75f32.sqrt()).abs() < 1e-5);
+  }
+
+  #[test]
+  fn frustum_keeps_boxes_in_view() {
+    // arrange
+    let frustum = frustum();
+    let bounds = BoundingVolume::Box(unit_box());
+    let at = |x: GLfloat, z: GLfloat| Matrix4::from_translation(Vector3::new(x, 0.0, z));
+    // act & assert
+    assert!(frustum.intersects(&bounds, at(0.0, -5.0)), "in front");
+    assert!(frustum.intersects(&bounds, at(5.3, -5.0)), "crossing the right plane");
+    assert!(!frustum.intersects(&bounds, at(0.0, 5.0)), "behind the eye");
+    assert!(!frustum.intersects(&bounds, at(7.0, -5.0)), "right of the frustum");
+    assert!(!frustum.intersects(&bounds, at(0.0, -11.0)), "beyond the far plane");
+  }
+
+  #[test]
+  fn frustum_tests_spheres_against_every_plane() {
+    // arrange
+    let frustum = frustum();
+    // act & assert
+    assert!(frustum.intersects_sphere(&BoundingSphere::new(Point3::new(0.0, 0.0, -0.5), 0.6)));
+    assert!(!frustum.intersects_sphere(&BoundingSphere::new(Point3::new(0.0, 0.0, -0.5), 0.4)));
+    assert!(!frustum.intersects_sphere(&BoundingSphere::new(Point3::new(0.0, -8.0, -5.0), 2.0)));
+  }
+}
@@ -0,0 +1,241 @@
+use std::f32::consts::PI;
+use std::mem::size_of;
+use std::slice;
+use gl::types::*;
+use cgmath::{ Matrix4, Point3, Transform, Vector3 };
+use crate::camera::Camera;
+use crate::culling::{ Aabb, BoundingVolume };
+use crate::dynamic_buffer::{ BufferUsage, DynamicBuffer };
+use crate::render_state::{ BlendMode, RenderState, RenderStateCache };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use crate::vao_builder::VaoBuilder;
+use crate::vao_builder::buffer_component::BufferComponent;
+use crate::vertex::PosColor;
+
+// segments of each circle of a sphere
+const CIRCLE_SEGMENTS: usize = 32;
+
+pub const RED: [GLfloat; 4] = [1.0, 0.0, 0.0, 1.0];
+pub const GREEN: [GLfloat; 4] = [0.0, 1.0, 0.0, 1.0];
+pub const BLUE: [GLfloat; 4] = [0.0, 0.0, 1.0, 1.0];
+pub const YELLOW: [GLfloat; 4] = [1.0, 1.0, 0.0, 1.0];
+
+// Lines in world space that are collected during a frame and drawn at once, see DebugLineRenderer
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugLines {
+  // two per line
+  vertices: Vec<PosColor>
+}
+
+impl DebugLines {
+  pub fn new() -> DebugLines {
+    DebugLines::default()
+  }
+
+  pub fn clear(&mut self) {
+    self.vertices.clear();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.vertices.is_empty()
+  }
+
+  pub fn line_count(&self) -> usize {
+    self.vertices.len() / 2
+  }
+
+  pub fn vertices(&self) -> &[PosColor] {
+    &self.vertices
+  }
+
+  pub fn line(&mut self, a: Point3<GLfloat>, b: Point3<GLfloat>, color: [GLfloat; 4]) {
+    self.vertices.push(PosColor { position: a.into(), color });
+    self.vertices.push(PosColor { position: b.into(), color });
+  }
+
+  pub fn aabb(&mut self, aabb: &Aabb, color: [GLfloat; 4]) {
+    self.box_edges(&box_corners(aabb, Matrix4::from_scale(1.0)), color);
+  }
+
+  // The model space bounds of an entity with its model matrix, boxes are drawn rotated rather than refitted
+  pub fn bounds(&mut self, bounds: &BoundingVolume, model: Matrix4<GLfloat>, color: [GLfloat; 4]) {
+    match bounds {
+      BoundingVolume::Box(aabb) => self.box_edges(&box_corners(aabb, model), color),
+      BoundingVolume::Sphere(sphere) => {
+        let sphere = sphere.transformed(model);
+        self.sphere(sphere.center, sphere.radius, color);
+      }
+    }
+  }
+
+  // A circle around each axis
+  pub fn sphere(&mut self, center: Point3<GLfloat>, radius: GLfloat, color: [GLfloat; 4]) {
+    let (x, y, z) = (Vector3::unit_x() * radius, Vector3::unit_y() * radius, Vector3::unit_z() * radius);
+    self.circle(center, x, y, color);
+    self.circle(center, y, z, color);
+    self.circle(center, z, x, color);
+  }
+
+  // The x, y and z axes of the transform in red, green and blue, length is in the transform's units
+  pub fn axes(&mut self, transform: Matrix4<GLfloat>, length: GLfloat) {
+    let origin = transform.transform_point(Point3::new(0.0, 0.0, 0.0));
+    self.line(origin, transform.transform_point(Point3::new(length, 0.0, 0.0)), RED);
+    self.line(origin, transform.transform_point(Point3::new(0.0, length, 0.0)), GREEN);
+    self.line(origin, transform.transform_point(Point3::new(0.0, 0.0, length)), BLUE);
+  }
+
+  // Lines on the xz plane through center, spacing apart and half_count to each side of it
+  pub fn grid(&mut self, center: Point3<GLfloat>, spacing: GLfloat, half_count: usize, color: [GLfloat; 4]) {
+    let extent = spacing * half_count as GLfloat;
+    for i in 0..=2 * half_count {
+      let offset = i as GLfloat * spacing - extent;
+      self.line(center + Vector3::new(offset, 0.0, -extent), center + Vector3::new(offset, 0.0, extent), color);
+      self.line(center + Vector3::new(-extent, 0.0, offset), center + Vector3::new(extent, 0.0, offset), color);
+    }
+  }
+
+  // The view frustum of the camera from its near to its far plane
+  pub fn frustum(&mut self, camera: &Camera, color: [GLfloat; 4]) {
+    self.box_edges(&camera.frustum_corners(camera.near, camera.far), color);
+  }
+
+  fn circle(&mut self, center: Point3<GLfloat>, u: Vector3<GLfloat>, v: Vector3<GLfloat>, color: [GLfloat; 4]) {
+    let point = |segment: usize| {
+      let angle = segment as GLfloat / CIRCLE_SEGMENTS as GLfloat * 2.0 * PI;
+      center + u * angle.cos() + v * angle.sin()
+    };
+    for segment in 0..CIRCLE_SEGMENTS {
+      self.line(point(segment), point(segment + 1), color);
+    }
+  }
+
+  // Corners of two faces in the same winding, the first four and the last four
+  fn box_edges(&mut self, corners: &[Point3<GLfloat>; 8], color: [GLfloat; 4]) {
+    for i in 0..4 {
+      let next = (i + 1) % 4;
+      self.line(corners[i], corners[next], color);
+      self.line(corners[i + 4], corners[next + 4], color);
+      self.line(corners[i], corners[i + 4], color);
+    }
+  }
+}
+
+fn box_corners(aabb: &Aabb, transform: Matrix4<GLfloat>) -> [Point3<GLfloat>; 8] {
+  let (min, max) = (aabb.min, aabb.max);
+  [
+    Point3::new(min.x, min.y, min.z), Point3::new(max.x, min.y, min.z), Point3::new(max.x, max.y, min.z), Point3::new(min.x, max.y, min.z),
+    Point3::new(min.x, min.y, max.z), Point3::new(max.x, min.y, max.z), Point3::new(max.x, max.y, max.z), Point3::new(min.x, max.y, max.z)
+  ].map(|corner| transform.transform_point(corner))
+}
+
+// Draws DebugLines with a single GL_LINES call
+pub struct DebugLineRenderer {
+  program: ShaderProgram,
+  buffers: BufferComponent,
+  vertex_buffer: DynamicBuffer
+}
+
+impl DebugLineRenderer {
+  pub fn new() -> DebugLineRenderer {
+    let program = ShaderProgramBuilder::new()
+      .with_vertex_shader(include_str!("glsl/debug_draw/vertex.glsl"))
+      .with_fragment_shader(include_str!("glsl/debug_draw/fragment.glsl"))
+      .build();
+    let buffers = VaoBuilder::new().with_vertex::<PosColor>().build();
+    let vertex_buffer = DynamicBuffer::new(0, size_of::<PosColor>() / size_of::<GLfloat>(), BufferUsage::Stream);
+    DebugLineRenderer { program, buffers, vertex_buffer }
+  }
+
+  /// Draws into the framebuffer that is bound and returns the number of vertices. Without the depth test
+  /// the lines show through the scene, they never write depth.
+  ///
+  /// # Safety
+  /// Needs a current GL context. The program and vertex array bindings are changed, the render state goes through the cache.
+  pub unsafe fn draw(&mut self, lines: &DebugLines, view_projection: Matrix4<GLfloat>, depth_test: bool, state_cache: &mut RenderStateCache) -> usize {
+    if lines.is_empty() { return 0; }
+    // PosColor is nothing but floats
+    let floats = slice::from_raw_parts(lines.vertices.as_ptr() as *const GLfloat, lines.vertices.len() * self.vertex_buffer.floats_per_vertex());
+    self.vertex_buffer.upload(&self.buffers, floats);
+    state_cache.apply(&RenderState::new().with_depth_test(depth_test).with_depth_write(false).with_blend(BlendMode::Alpha));
+    gl::UseProgram(self.program.handle());
+    self.program.set_uniform_matrix("ViewProjection", view_projection);
+    gl::BindVertexArray(self.buffers.vao());
+    gl::DrawArrays(gl::LINES, 0, lines.vertices.len() as GLsizei);
+    gl::BindVertexArray(0);
+    gl_check!("DebugLineRenderer::draw");
+    lines.vertices.len()
+  }
+}
+
+impl Default for DebugLineRenderer {
+  fn default() -> Self {
+    DebugLineRenderer::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::{ Deg, Rad };
+  use crate::camera::CameraBuilder;
+  use crate::culling::BoundingSphere;
+
+  fn positions(lines: &DebugLines) -> Vec<[GLfloat; 3]> {
+    lines.vertices().iter().map(|vertex| vertex.position).collect()
+  }
+
+  #[test]
+  fn boxes_have_twelve_edges() {
+    // arrange
+    let mut lines = DebugLines::new();
+    let camera = CameraBuilder::new()
+      .with_eye(Point3::new(0.0, 0.0, 5.0))
+      .with_target(Point3::new(0.0, 0.0, 0.0))
+      .with_up(Vector3::new(0.0, 1.0, 0.0))
+      .with_fovy(Rad::from(Deg(60.0)))
+      .with_aspect(1.0)
+      .with_near(1.0)
+      .with_far(10.0)
+      .build();
+    // act
+    lines.aabb(&Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)), GREEN);
+    lines.frustum(&camera, RED);
+    // assert
+    assert_eq!(24, lines.line_count());
+    assert_eq!([-1.0, -1.0, -1.0], positions(&lines)[0]);
+    assert_eq!([1.0, -1.0, -1.0], positions(&lines)[1]);
+  }
+
+  #[test]
+  fn bounds_follow_the_model_matrix() {
+    // arrange
+    let mut lines = DebugLines::new();
+    let aabb = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+    let model = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0));
+    // act
+    lines.bounds(&BoundingVolume::Box(aabb), model, GREEN);
+    lines.bounds(&BoundingVolume::Sphere(BoundingSphere::new(Point3::new(0.0, 0.0, 0.0), 2.0)), model, GREEN);
+    // assert
+    assert_eq!(12 + 3 * CIRCLE_SEGMENTS, lines.line_count());
+    assert_eq!([10.0, 0.0, 0.0], positions(&lines)[0]);
+    // the first circle starts on the x axis
+    assert_eq!([12.0, 0.0, 0.0], positions(&lines)[24]);
+  }
+
+  #[test]
+  fn axes_and_grid() {
+    // arrange
+    let mut lines = DebugLines::new();
+    // act
+    lines.axes(Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)), 2.0);
+    lines.grid(Point3::new(0.0, 0.0, 0.0), 1.0, 2, BLUE);
+    // assert
+    let positions = positions(&lines);
+    assert_eq!(vec![[0.0, 1.0, 0.0], [2.0, 1.0, 0.0]], positions[0..2].to_vec());
+    assert_eq!(GREEN, lines.vertices()[2].color);
+    assert_eq!(3 + 2 * 5, lines.line_count());
+    assert_eq!([-2.0, 0.0, -2.0], positions[6]);
+    lines.clear();
+    assert!(lines.is_empty());
+  }
+}
@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::ffi::{ c_void, CStr, CString };
+use std::ptr;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use gl::types::*;
+use log::Level;
+
+// GL messages and errors are logged with this target, e.g. for filtering them out
+pub const LOG_TARGET: &str = "gl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugSeverity {
+  High,
+  Medium,
+  Low,
+  Notification
+}
+
+impl DebugSeverity {
+  pub fn from_gl(severity: GLenum) -> Self {
+    match severity {
+      gl::DEBUG_SEVERITY_HIGH => DebugSeverity::High,
+      gl::DEBUG_SEVERITY_MEDIUM => DebugSeverity::Medium,
+      gl::DEBUG_SEVERITY_LOW => DebugSeverity::Low,
+      _ => DebugSeverity::Notification
+    }
+  }
+
+  pub fn level(self) -> Level {
+    match self {
+      DebugSeverity::High => Level::Error,
+      DebugSeverity::Medium => Level::Warn,
+      DebugSeverity::Low => Level::Info,
+      DebugSeverity::Notification => Level::Debug
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugSource {
+  Api,
+  WindowSystem,
+  ShaderCompiler,
+  ThirdParty,
+  Application,
+  Other
+}
+
+impl DebugSource {
+  pub fn from_gl(source: GLenum) -> Self {
+    match source {
+      gl::DEBUG_SOURCE_API => DebugSource::Api,
+      gl::DEBUG_SOURCE_WINDOW_SYSTEM => DebugSource::WindowSystem,
+      gl::DEBUG_SOURCE_SHADER_COMPILER => DebugSource::ShaderCompiler,
+      gl::DEBUG_SOURCE_THIRD_PARTY => DebugSource::ThirdParty,
+      gl::DEBUG_SOURCE_APPLICATION => DebugSource::Application,
+      _ => DebugSource::Other
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugType {
+  Error,
+  DeprecatedBehavior,
+  UndefinedBehavior,
+  Portability,
+  Performance,
+  Marker,
+  PushGroup,
+  PopGroup,
+  Other
+}
+
+impl DebugType {
+  pub fn from_gl(message_type: GLenum) -> Self {
+    match message_type {
+      gl::DEBUG_TYPE_ERROR => DebugType::Error,
+      gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => DebugType::DeprecatedBehavior,
+      gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => DebugType::UndefinedBehavior,
+      gl::DEBUG_TYPE_PORTABILITY => DebugType::Portability,
+      gl::DEBUG_TYPE_PERFORMANCE => DebugType::Performance,
+      gl::DEBUG_TYPE_MARKER => DebugType::Marker,
+      gl::DEBUG_TYPE_PUSH_GROUP => DebugType::PushGroup,
+      gl::DEBUG_TYPE_POP_GROUP => DebugType::PopGroup,
+      _ => DebugType::Other
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugMessage {
+  pub source: DebugSource,
+  pub message_type: DebugType,
+  pub severity: DebugSeverity,
+  pub id: GLuint,
+  pub text: String
+}
+
+thread_local! {
+  // the names of the debug groups that are open, outermost first
+  static GROUPS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// The open debug groups joined with '/', e.g. "Frame/Post-process/fxaa"
+pub fn group_path() -> String {
+  GROUPS.with(|groups| groups.borrow().join("/"))
+}
+
+// Push and pop messages open and close groups, every other message is logged within the open groups
+pub fn handle_message(message: DebugMessage) {
+  match message.message_type {
+    DebugType::PushGroup => GROUPS.with(|groups| groups.borrow_mut().push(message.text)),
+    DebugType::PopGroup => { GROUPS.with(|groups| groups.borrow_mut().pop()); },
+    _ => log!(target: LOG_TARGET, message.severity.level(), "[{}] {:?} {:?} {}: {}",
+      group_path(), message.source, message.message_type, message.id, message.text)
+  }
+}
+
+extern "system" fn debug_callback(source: GLenum, message_type: GLenum, id: GLuint, severity: GLenum, length: GLsizei, message: *const GLchar, _user_param: *mut c_void) {
+  let text = unsafe {
+    if length < 0 {
+      CStr::from_ptr(message).to_string_lossy().into_owned()
+    } else {
+      String::from_utf8_lossy(std::slice::from_raw_parts(message as *const u8, length as usize)).into_owned()
+    }
+  };
+  handle_message(DebugMessage {
+    source: DebugSource::from_gl(source),
+    message_type: DebugType::from_gl(message_type),
+    severity: DebugSeverity::from_gl(severity),
+    id,
+    text
+  });
+}
+
+/// Routes the messages of a debug context (GL 4.3 or KHR_debug) into the log. The messages are synchronous,
+/// so that they arrive on the thread and within the groups of the call that caused them.
+///
+/// # Safety
+/// Needs a current GL context, the callback stays installed for as long as the context lives.
+pub unsafe fn enable_debug_output() -> Result<(), String> {
+  if !gl::DebugMessageCallback::is_loaded() {
+    return Err("glDebugMessageCallback is not available, it needs OpenGL 4.3 or KHR_debug".to_string());
+  }
+  gl::Enable(gl::DEBUG_OUTPUT);
+  gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+  gl::DebugMessageCallback(debug_callback, ptr::null());
+  gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, gl::DONT_CARE, 0, ptr::null(), gl::TRUE);
+  Ok(())
+}
+
+// Opens a debug group that is closed when the DebugGroup is dropped, messages in between are logged
+// with the group name. Does nothing without glPushDebugGroup.
+pub struct DebugGroup {
+  pushed: bool
+}
+
+impl DebugGroup {
+  pub fn new(name: &str) -> DebugGroup {
+    let pushed = gl::PushDebugGroup::is_loaded();
+    if pushed {
+      let name = CString::new(name).unwrap();
+      unsafe { gl::PushDebugGroup(gl::DEBUG_SOURCE_APPLICATION, 0, -1, name.as_ptr()); }
+    }
+    DebugGroup { pushed }
+  }
+}
+
+impl Drop for DebugGroup {
+  fn drop(&mut self) {
+    if self.pushed {
+      unsafe { gl::PopDebugGroup(); }
+    }
+  }
+}
+
+static ERROR_CHECKS: AtomicBool = AtomicBool::new(false);
+
+// Turns the glGetError checks of gl_check! on or off, they only exist in debug builds
+pub fn set_error_checks(enabled: bool) {
+  ERROR_CHECKS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn error_checks_enabled() -> bool {
+  ERROR_CHECKS.load(Ordering::Relaxed)
+}
+
+pub fn error_name(error: GLenum) -> &'static str {
+  match error {
+    gl::INVALID_ENUM => "GL_INVALID_ENUM",
+    gl::INVALID_VALUE => "GL_INVALID_VALUE",
+    gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+    gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+    gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+    gl::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW",
+    gl::STACK_OVERFLOW => "GL_STACK_OVERFLOW",
+    _ => "unknown GL error"
+  }
+}
+
+/// Logs every pending GL error as caused by the engine function and returns how many there were
+///
+/// # Safety
+/// Needs a current GL context on this thread.
+pub unsafe fn check_errors(function: &str) -> usize {
+  let mut count = 0;
+  loop {
+    let error = gl::GetError();
+    // glGetError keeps returning an error when there is no context
+    if error == gl::NO_ERROR || count == 16 { return count; }
+    error!(target: LOG_TARGET, "[{}] {} caused {}", group_path(), function, error_name(error));
+    count += 1;
+  }
+}
+
+// Checks for GL errors after an engine function, when error checks are enabled in a debug build.
+// Exported for the draw paths of games, as engine::gl_check!
+#[macro_export]
+macro_rules! gl_check {
+  ($function:expr) => {
+    #[cfg(debug_assertions)]
+    {
+      if $crate::debug_output::error_checks_enabled() {
+        let function: &str = $function;
+        #[allow(unused_unsafe)]
+        unsafe { $crate::debug_output::check_errors(function); }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Once;
+  use log::{ Log, Metadata, Record };
+  use crate::gl_mock;
+
+  struct CaptureLogger;
+
+  thread_local! {
+    static CAPTURED: RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+  }
+
+  impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool { true }
+    fn log(&self, record: &Record) {
+      CAPTURED.with(|captured| captured.borrow_mut().push((record.level(), format!("{}", record.args()))));
+    }
+    fn flush(&self) {}
+  }
+
+  static LOGGER: CaptureLogger = CaptureLogger;
+  static INIT: Once = Once::new();
+
+  fn capture() {
+    INIT.call_once(|| {
+      log::set_logger(&LOGGER).unwrap();
+      log::set_max_level(log::LevelFilter::Trace);
+    });
+    CAPTURED.with(|captured| captured.borrow_mut().clear());
+  }
+
+  fn captured() -> Vec<(Level, String)> {
+    CAPTURED.with(|captured| captured.borrow().clone())
+  }
+
+  fn message(message_type: DebugType, severity: DebugSeverity, text: &str) -> DebugMessage {
+    DebugMessage { source: DebugSource::Api, message_type, severity, id: 7, text: text.to_string() }
+  }
+
+  #[test]
+  fn messages_are_logged_within_their_groups() {
+    // arrange
+    capture();
+    // act
+    handle_message(message(DebugType::PushGroup, DebugSeverity::Notification, "Shadows"));
+    handle_message(message(DebugType::Error, DebugSeverity::High, "invalid texture"));
+    handle_message(message(DebugType::PopGroup, DebugSeverity::Notification, "Shadows"));
+    handle_message(message(DebugType::Performance, DebugSeverity::Medium, "buffer stall"));
+    // assert
+    assert_eq!(vec![
+      (Level::Error, "[Shadows] Api Error 7: invalid texture".to_string()),
+      (Level::Warn, "[] Api Performance 7: buffer stall".to_string())
+    ], captured());
+    assert_eq!("", group_path());
+  }
+
+  #[test]
+  fn gl_enums_map_to_severity_source_and_type() {
+    assert_eq!(Level::Info, DebugSeverity::from_gl(gl::DEBUG_SEVERITY_LOW).level());
+    assert_eq!(DebugSource::ShaderCompiler, DebugSource::from_gl(gl::DEBUG_SOURCE_SHADER_COMPILER));
+    assert_eq!(DebugType::UndefinedBehavior, DebugType::from_gl(gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR));
+  }
+
+  #[test]
+  fn pending_errors_name_the_engine_function() {
+    // arrange
+    capture();
+    gl_mock::install();
+    gl_mock::push_error(gl::INVALID_OPERATION);
+    gl_mock::push_error(gl::INVALID_ENUM);
+    // act
+    let count = unsafe { check_errors("VaoBuilder::build") };
+    // assert
+    assert_eq!(2, count);
+    assert_eq!(vec![
+      (Level::Error, "[] VaoBuilder::build caused GL_INVALID_OPERATION".to_string()),
+      (Level::Error, "[] VaoBuilder::build caused GL_INVALID_ENUM".to_string())
+    ], captured());
+  }
+
+  #[test]
+  fn debug_group_pops_when_dropped() {
+    gl_mock::install();
+    drop(DebugGroup::new("Forward"));
+    assert_eq!(1, gl_mock::count("glPushDebugGroup"));
+    assert_eq!(1, gl_mock::count("glPopDebugGroup"));
+  }
+}
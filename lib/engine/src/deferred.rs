@@ -0,0 +1,271 @@
+use std::cell::Cell;
+use std::mem::size_of;
+use gl::types::*;
+use cgmath::{ Matrix4, SquareMatrix, Vector2, Vector3 };
+use crate::camera::Camera;
+use crate::light::{ self, DirectionalLight, PointLight };
+use crate::post_process::{ fullscreen_program, DrawCount, FULLSCREEN_VERTICES };
+use crate::render_state::{ BlendMode, CullFace, DepthFunc, RenderState, RenderStateCache };
+use crate::debug_output::check_errors;
+use crate::render_target::{ FramebufferBuilder, RenderTarget, TextureFormat };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use crate::shadow::{ self, ShadowMap };
+use crate::texture::bind_texture_unit;
+use crate::vao_builder::VaoBuilder;
+use crate::vao_builder::attrib_parameters::AttribParameters;
+use crate::vao_builder::buffer_component::BufferComponent;
+use crate::mesh::uv_sphere;
+
+// Fragment shader outputs of the geometry pass, a program that declares GAlbedo is drawn into the G-buffer:
+//   layout (location = 0) out vec4 GAlbedo; // rgb diffuse color, a specular intensity
+//   layout (location = 1) out vec4 GNormal; // xyz world space normal, w shininess
+// Positions are reconstructed from the depth buffer.
+pub const GBUFFER_ALBEDO_OUTPUT: &str = "GAlbedo";
+
+// The G-buffer textures are bound to the units from GBUFFER_TEXTURE_UNIT upwards while lighting
+const GBUFFER_TEXTURE_UNIT: GLuint = 0;
+
+// the faces of the sphere lie inside the unit sphere, the volume is scaled up to enclose the light's radius
+const SPHERE_SEGMENTS: usize = 16;
+const SPHERE_RINGS: usize = 8;
+const SPHERE_SCALE: GLfloat = 1.1;
+
+/// # Safety
+/// Needs the current GL context the program was linked in.
+pub unsafe fn writes_gbuffer(program: &ShaderProgram) -> bool {
+  program.has_output(GBUFFER_ALBEDO_OUTPUT)
+}
+
+// Lights the G-buffer: one full-screen pass for the ambient and directional lights,
+// then a sphere volume per point light that is blended additively
+pub struct DeferredShading {
+  pub gbuffer: RenderTarget,
+  directional_program: ShaderProgram,
+  point_program: ShaderProgram,
+  sphere: BufferComponent,
+  sphere_vertex_count: GLsizei,
+  empty_vao: GLuint,
+  // writes the depth when it cannot be blitted, see copy_depth_to
+  depth_program: ShaderProgram,
+  // the framebuffer the depth was last copied into and whether blitting into it works
+  depth_blit: Cell<Option<(GLuint, bool)>>
+}
+
+impl DeferredShading {
+  pub fn new(width: GLsizei, height: GLsizei) -> Result<DeferredShading, String> {
+    let gbuffer = FramebufferBuilder::new(width, height)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .with_color_attachment(TextureFormat::Rgba16F)
+      .with_depth_attachment(TextureFormat::Depth24Stencil8)
+      .build()?;
+    let point_program = ShaderProgramBuilder::new()
+      .with_vertex_shader(include_str!("glsl/deferred/point_vertex.glsl"))
+      .with_fragment_shader(include_str!("glsl/deferred/point_fragment.glsl"))
+      .build();
+    let sphere: Vec<GLfloat> = uv_sphere(SPHERE_SEGMENTS, SPHERE_RINGS).iter().flat_map(|p| p.iter().cloned()).collect();
+    let buffers = VaoBuilder::new()
+      .with_attribute(AttribParameters::floats(3, 3, 0))
+      .build();
+    let mut empty_vao: GLuint = 0;
+    unsafe {
+      gl::BindBuffer(gl::ARRAY_BUFFER, buffers.vbo());
+      gl::BufferData(gl::ARRAY_BUFFER, (sphere.len() * size_of::<GLfloat>()) as GLsizeiptr, sphere.as_ptr() as *const GLvoid, gl::STATIC_DRAW);
+      gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+      gl::GenVertexArrays(1, &mut empty_vao);
+    }
+    Ok(DeferredShading {
+      gbuffer,
+      directional_program: fullscreen_program(include_str!("glsl/deferred/directional.glsl")),
+      point_program,
+      sphere: buffers,
+      sphere_vertex_count: (sphere.len() / 3) as GLsizei,
+      empty_vao,
+      depth_program: fullscreen_program(include_str!("glsl/deferred/depth_copy.glsl")),
+      depth_blit: Cell::new(None)
+    })
+  }
+
+  pub fn resize(&mut self, width: GLsizei, height: GLsizei) -> Result<(), String> {
+    Ok(self.gbuffer.resize(width, height)?)
+  }
+
+  /// Binds and clears the G-buffer for the geometry pass
+  ///
+  /// # Safety
+  /// Needs the current GL context the G-buffer was created in.
+  pub unsafe fn begin_geometry(&self, state_cache: &mut RenderStateCache) {
+    state_cache.apply(&RenderState::default());
+    self.gbuffer.bind();
+    // the clear color is left to the lit target
+    let zero: [GLfloat; 4] = [0.0, 0.0, 0.0, 0.0];
+    for i in 0..self.gbuffer.color_attachments.len() {
+      gl::ClearBufferfv(gl::COLOR, i as GLint, zero.as_ptr());
+    }
+    gl::Clear(gl::DEPTH_BUFFER_BIT);
+  }
+
+  /// Adds the light of every light to the framebuffer that is bound, which should have the G-buffer's size.
+  /// Returns the draws it took: one for the directional lights and one per point light volume.
+  ///
+  /// # Safety
+  /// Needs the current GL context the G-buffer was created in, the shadow maps have to be live in it.
+  pub unsafe fn draw_lighting(&self, camera: &Camera, directional_lights: &[DirectionalLight], point_lights: &[PointLight],
+    shadow_maps: &[ShadowMap], shadow_matrices: &[Matrix4<GLfloat>], state_cache: &mut RenderStateCache) -> DrawCount {
+    let view_projection = camera.projection_matrix * camera.view_matrix;
+    let inverse_view_projection = view_projection.invert().unwrap_or_else(Matrix4::identity);
+    let eye = Vector3::new(camera.eye.x, camera.eye.y, camera.eye.z);
+    let fullscreen_state = RenderState::new().with_depth_test(false).with_depth_write(false);
+    state_cache.apply(&fullscreen_state);
+    gl::UseProgram(self.directional_program.handle());
+    self.bind_gbuffer(&self.directional_program, inverse_view_projection, eye);
+    light::upload_lights(&self.directional_program, directional_lights, &[]);
+    shadow::upload_shadow_maps(&self.directional_program, shadow_maps, shadow_matrices);
+    gl::BindVertexArray(self.empty_vao);
+    gl::DrawArrays(gl::TRIANGLES, 0, FULLSCREEN_VERTICES as GLsizei);
+    let mut draws = DrawCount::default();
+    draws.add(FULLSCREEN_VERTICES);
+
+    // back faces are drawn so that the volume still covers the screen when the eye is inside of it
+    state_cache.apply(&fullscreen_state.with_blend(BlendMode::Additive).with_cull_face(Some(CullFace::Front)));
+    gl::UseProgram(self.point_program.handle());
+    self.bind_gbuffer(&self.point_program, inverse_view_projection, eye);
+    self.point_program.set_uniform_matrix("ViewProjection", view_projection);
+    self.point_program.set_uniform_if_present("ScreenSize", Vector2::new(self.gbuffer.width as GLfloat, self.gbuffer.height as GLfloat));
+    gl::BindVertexArray(self.sphere.vao());
+    for point_light in point_lights {
+      let radius = point_light.volume_radius();
+      if !radius.is_finite() || radius <= 0.0 { continue; }
+      let position = Vector3::new(point_light.position.x, point_light.position.y, point_light.position.z);
+      let attenuation = point_light.attenuation;
+      let model = Matrix4::from_translation(position) * Matrix4::from_scale(radius * SPHERE_SCALE);
+      self.point_program.set_uniform_matrix("Model", model);
+      self.point_program.set_uniform_if_present("LightPosition", position);
+      self.point_program.set_uniform_if_present("LightColor", point_light.radiance());
+      self.point_program.set_uniform_if_present("LightAttenuation", Vector3::new(attenuation.constant, attenuation.linear, attenuation.quadratic));
+      gl::DrawArrays(gl::TRIANGLES, 0, self.sphere_vertex_count);
+      draws.add(self.sphere_vertex_count as usize);
+    }
+    gl::BindVertexArray(0);
+    gl_check!("DeferredShading::draw_lighting");
+    draws
+  }
+
+  /// Copies the depth of the geometry pass into the framebuffer, so that forward drawn objects are hidden behind it.
+  /// Blitting fails with GL_INVALID_OPERATION when the depth formats differ, which the window's framebuffer may do.
+  /// The first copy into a framebuffer checks for that; when it failed, the depth is drawn with a full-screen pass
+  /// from then on. Leaves the framebuffer bound and returns the draws it took.
+  ///
+  /// # Safety
+  /// Needs the current GL context the G-buffer was created in, framebuffer has to be 0 or a live framebuffer of it.
+  pub unsafe fn copy_depth_to(&self, framebuffer: GLuint, state_cache: &mut RenderStateCache) -> DrawCount {
+    let blit = match self.depth_blit.get() {
+      Some((tested, blit)) if tested == framebuffer => {
+        if blit { self.blit_depth(framebuffer); }
+        blit
+      },
+      _ => {
+        // errors of earlier calls would be taken for a failed blit
+        check_errors("before DeferredShading::copy_depth_to");
+        self.blit_depth(framebuffer);
+        let blit = gl::GetError() != gl::INVALID_OPERATION;
+        if !blit {
+          warn!("The depth of the G-buffer cannot be blitted into framebuffer {}, it is drawn instead", framebuffer);
+        }
+        self.depth_blit.set(Some((framebuffer, blit)));
+        blit
+      }
+    };
+    if blit { return DrawCount::default(); }
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    state_cache.apply(&RenderState::new().with_depth_func(DepthFunc::Always).with_color_mask(false, false, false, false));
+    gl::UseProgram(self.depth_program.handle());
+    bind_texture_unit(GBUFFER_TEXTURE_UNIT, self.gbuffer.depth_texture().unwrap_or(0));
+    self.depth_program.set_uniform_if_present("GDepth", GBUFFER_TEXTURE_UNIT as GLint);
+    gl::BindVertexArray(self.empty_vao);
+    gl::DrawArrays(gl::TRIANGLES, 0, FULLSCREEN_VERTICES as GLsizei);
+    gl::BindVertexArray(0);
+    gl_check!("DeferredShading::copy_depth_to");
+    let mut draws = DrawCount::default();
+    draws.add(FULLSCREEN_VERTICES);
+    draws
+  }
+
+  unsafe fn blit_depth(&self, framebuffer: GLuint) {
+    let (width, height) = (self.gbuffer.width, self.gbuffer.height);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.gbuffer.fbo);
+    gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+    gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+  }
+
+  unsafe fn bind_gbuffer(&self, program: &ShaderProgram, inverse_view_projection: Matrix4<GLfloat>, eye: Vector3<GLfloat>) {
+    let textures = [
+      ("GAlbedo", self.gbuffer.color_texture(0)),
+      ("GNormal", self.gbuffer.color_texture(1)),
+      ("GDepth", self.gbuffer.depth_texture())
+    ];
+    for (i, (name, texture)) in textures.iter().enumerate() {
+      let unit = GBUFFER_TEXTURE_UNIT + i as GLuint;
+      bind_texture_unit(unit, texture.unwrap_or(0));
+      program.set_uniform_if_present(name, unit as GLint);
+    }
+    program.set_uniform_matrix("InverseViewProjection", inverse_view_projection);
+    program.set_uniform_if_present("EyePosition", eye);
+  }
+}
+
+impl Drop for DeferredShading {
+  fn drop(&mut self) {
+    if self.empty_vao != 0 {
+      unsafe { gl::DeleteVertexArrays(1, &self.empty_vao); }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn depth_is_drawn_when_it_cannot_be_blitted() {
+    // arrange
+    gl_mock::install();
+    let deferred = DeferredShading::new(64, 32).unwrap();
+    let mut state_cache = RenderStateCache::new();
+    gl_mock::set_blit_error(gl::INVALID_OPERATION);
+    // act
+    let first = unsafe { deferred.copy_depth_to(0, &mut state_cache) };
+    let second = unsafe { deferred.copy_depth_to(0, &mut state_cache) };
+    // assert: blitting is only tried once
+    assert_eq!(1, gl_mock::count("glBlitFramebuffer"));
+    assert_eq!(2, gl_mock::count("glDrawArrays"));
+    assert_eq!(DrawCount { calls: 1, vertices: 3 }, first);
+    assert_eq!(first, second);
+  }
+
+  #[test]
+  fn dropping_deletes_every_vertex_array() {
+    // arrange
+    gl_mock::install();
+    let deferred = DeferredShading::new(64, 32).unwrap();
+    // act
+    drop(deferred);
+    // assert
+    assert_eq!(0, gl_mock::live_count("vertex array"));
+  }
+
+  #[test]
+  fn depth_is_blitted_when_the_formats_match() {
+    // arrange
+    gl_mock::install();
+    let deferred = DeferredShading::new(64, 32).unwrap();
+    let mut state_cache = RenderStateCache::new();
+    // act
+    let draws = unsafe { deferred.copy_depth_to(0, &mut state_cache) };
+    // assert
+    assert_eq!(DrawCount::default(), draws);
+    assert_eq!(1, gl_mock::count("glBlitFramebuffer"));
+    assert_eq!(0, gl_mock::count("glDrawArrays"));
+  }
+}
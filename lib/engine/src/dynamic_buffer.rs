@@ -0,0 +1,201 @@
+use std::mem::{ size_of, size_of_val };
+use std::ptr;
+use gl::types::*;
+use crate::vao_builder::buffer_component::BufferComponent;
+
+// How often the contents of a buffer are expected to change, a hint for where the driver keeps it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+  // uploaded once
+  Static,
+  // changed now and then, drawn many times in between
+  Dynamic,
+  // changed about every frame
+  Stream
+}
+
+impl BufferUsage {
+  pub fn gl_enum(self) -> GLenum {
+    match self {
+      BufferUsage::Static => gl::STATIC_DRAW,
+      BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+      BufferUsage::Stream => gl::STREAM_DRAW
+    }
+  }
+}
+
+// The capacity for at least required floats, doubling the current capacity to keep reallocations rare
+fn grown_capacity(capacity: usize, required: usize) -> usize {
+  if required <= capacity { capacity } else { required.max(capacity * 2) }
+}
+
+// A vertex buffer of a BufferComponent whose contents change after the first upload. Tracks how many floats
+// it holds and how many fit, so that it only reallocates when the data outgrows it. The component owns the buffer.
+#[derive(Debug)]
+pub struct DynamicBuffer {
+  // the index of the vertex buffer in the component, see BufferComponent::vbo_at
+  pub buffer: usize,
+  pub usage: BufferUsage,
+  floats_per_vertex: usize,
+  // both in floats
+  capacity: usize,
+  len: usize
+}
+
+impl DynamicBuffer {
+  // Takes over a vertex buffer of a component that has no storage yet
+  pub fn new(buffer: usize, floats_per_vertex: usize, usage: BufferUsage) -> Self {
+    DynamicBuffer { buffer, usage, floats_per_vertex, capacity: 0, len: 0 }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn floats_per_vertex(&self) -> usize {
+    self.floats_per_vertex
+  }
+
+  pub fn vertex_count(&self) -> GLsizei {
+    (self.len / self.floats_per_vertex.max(1)) as GLsizei
+  }
+
+  /// Replaces the contents. The storage is orphaned first, so that the driver can hand out fresh memory
+  /// instead of waiting for draws that still read the old contents.
+  ///
+  /// # Safety
+  /// Needs the current GL context the buffers were created in.
+  pub unsafe fn upload(&mut self, buffers: &BufferComponent, data: &[GLfloat]) {
+    self.capacity = grown_capacity(self.capacity, data.len());
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffers.vbo_at(self.buffer));
+    gl::BufferData(gl::ARRAY_BUFFER, (self.capacity * size_of::<GLfloat>()) as GLsizeiptr, ptr::null(), self.usage.gl_enum());
+    gl::BufferSubData(gl::ARRAY_BUFFER, 0, size_of_val(data) as GLsizeiptr, data.as_ptr() as *const GLvoid);
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    gl_check!("DynamicBuffer::upload");
+    self.len = data.len();
+  }
+
+  /// Overwrites part of the contents, starting offset floats in. Writing past the end appends,
+  /// but there may be no gap between the current contents and the data.
+  ///
+  /// # Safety
+  /// Needs the current GL context the buffers were created in.
+  pub unsafe fn update(&mut self, buffers: &BufferComponent, offset: usize, data: &[GLfloat]) -> Result<(), String> {
+    if offset > self.len {
+      return Err(format!("Update at float {} would leave a gap after the {} floats in the buffer", offset, self.len));
+    }
+    let end = offset + data.len();
+    let vbo = buffers.vbo_at(self.buffer);
+    if end > self.capacity {
+      self.grow(vbo, grown_capacity(self.capacity, end));
+    }
+    gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+    gl::BufferSubData(gl::ARRAY_BUFFER, (offset * size_of::<GLfloat>()) as GLintptr, size_of_val(data) as GLsizeiptr, data.as_ptr() as *const GLvoid);
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    gl_check!("DynamicBuffer::update");
+    self.len = self.len.max(end);
+    Ok(())
+  }
+
+  // Reallocates the storage and keeps the contents, the copy stays on the GPU
+  unsafe fn grow(&mut self, vbo: GLuint, capacity: usize) {
+    let mut copy: GLuint = 0;
+    let bytes = (self.len * size_of::<GLfloat>()) as GLsizeiptr;
+    gl::GenBuffers(1, &mut copy);
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, copy);
+    gl::BufferData(gl::COPY_WRITE_BUFFER, bytes, ptr::null(), gl::STREAM_COPY);
+    gl::BindBuffer(gl::COPY_READ_BUFFER, vbo);
+    gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0, bytes);
+    gl::BufferData(gl::COPY_READ_BUFFER, (capacity * size_of::<GLfloat>()) as GLsizeiptr, ptr::null(), self.usage.gl_enum());
+    gl::CopyBufferSubData(gl::COPY_WRITE_BUFFER, gl::COPY_READ_BUFFER, 0, 0, bytes);
+    gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+    gl::DeleteBuffers(1, &copy);
+    self.capacity = capacity;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+  use crate::vao_builder::VaoBuilder;
+
+  #[test]
+  fn capacity_doubles_until_the_data_fits() {
+    assert_eq!(16, grown_capacity(16, 10));
+    assert_eq!(32, grown_capacity(16, 17));
+    assert_eq!(100, grown_capacity(16, 100));
+    assert_eq!(7, grown_capacity(0, 7));
+  }
+
+  #[test]
+  fn upload_orphans_and_keeps_the_capacity() {
+    // arrange
+    gl_mock::install();
+    let buffers = VaoBuilder::new().build();
+    let mut buffer = DynamicBuffer::new(0, 3, BufferUsage::Stream);
+    unsafe { buffer.upload(&buffers, &[0.0; 9]); }
+    gl_mock::clear_calls();
+    // act
+    unsafe { buffer.upload(&buffers, &[0.0; 6]); }
+    // assert: the storage is respecified at the old size before the smaller upload
+    assert_eq!(vec![gl::ARRAY_BUFFER as i64, 36, gl::STREAM_DRAW as i64], gl_mock::calls_to("glBufferData")[0].args);
+    assert_eq!(vec![gl::ARRAY_BUFFER as i64, 0, 24], gl_mock::calls_to("glBufferSubData")[0].args);
+    assert_eq!(9, buffer.capacity());
+    assert_eq!(2, buffer.vertex_count());
+  }
+
+  #[test]
+  fn update_in_place_does_not_reallocate() {
+    // arrange
+    gl_mock::install();
+    let buffers = VaoBuilder::new().build();
+    let mut buffer = DynamicBuffer::new(0, 3, BufferUsage::Dynamic);
+    unsafe { buffer.upload(&buffers, &[0.0; 9]); }
+    gl_mock::clear_calls();
+    // act
+    let result = unsafe { buffer.update(&buffers, 3, &[1.0; 3]) };
+    // assert
+    assert_eq!(Ok(()), result);
+    assert_eq!(0, gl_mock::count("glBufferData"));
+    assert_eq!(vec![gl::ARRAY_BUFFER as i64, 12, 12], gl_mock::calls_to("glBufferSubData")[0].args);
+    assert_eq!(3, buffer.vertex_count());
+  }
+
+  #[test]
+  fn appending_grows_and_keeps_the_contents() {
+    // arrange
+    gl_mock::install();
+    let buffers = VaoBuilder::new().build();
+    let mut buffer = DynamicBuffer::new(0, 3, BufferUsage::Dynamic);
+    unsafe { buffer.upload(&buffers, &[0.0; 6]); }
+    gl_mock::clear_calls();
+    // act
+    let result = unsafe { buffer.update(&buffers, 6, &[1.0; 3]) };
+    // assert
+    assert_eq!(Ok(()), result);
+    assert_eq!(12, buffer.capacity());
+    assert_eq!(3, buffer.vertex_count());
+    assert_eq!(2, gl_mock::count("glCopyBufferSubData"));
+    assert_eq!(1, gl_mock::live_count("buffer"), "the temporary copy is deleted");
+  }
+
+  #[test]
+  fn update_rejects_gaps() {
+    gl_mock::install();
+    let buffers = VaoBuilder::new().build();
+    let mut buffer = DynamicBuffer::new(0, 3, BufferUsage::Dynamic);
+    gl_mock::clear_calls();
+    assert!(unsafe { buffer.update(&buffers, 3, &[1.0; 3]) }.is_err());
+    assert_eq!(0, gl_mock::calls().len());
+  }
+}
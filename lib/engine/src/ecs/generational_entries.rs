@@ -14,25 +14,24 @@ impl<T> GenerationalEntries<T> {
       value,
       generation: generational_index.generation()
     });
-    if index < self.0.len() {
-      self.0[index] = new_entry;
-    } else {
-      self.0.push(new_entry)
+    if index >= self.0.len() {
+      self.0.resize_with(index + 1, || None);
     }
+    self.0[index] = new_entry;
   }
 
   pub fn get(&self, generational_index: GenerationalIndex) -> Option<&T> {
     if generational_index.index() >= self.0.len() { return None; }
     let entry = self.0[generational_index.index()].as_ref()?;
     if entry.generation != generational_index.generation() { return None; }
-    return Some(&entry.value);
+    Some(&entry.value)
   }
 
   #[allow(dead_code)]
   pub fn get_mut(&mut self, generational_index: GenerationalIndex) -> Option<&mut T> {
-    let entry = self.0[generational_index.index()].as_mut()?;
+    let entry = self.0.get_mut(generational_index.index())?.as_mut()?;
     if entry.generation != generational_index.generation() { return None; }
-    return Some(&mut entry.value);
+    Some(&mut entry.value)
   }
 }
 
@@ -99,7 +98,21 @@ mod tests {
     assert!(result_opt.is_none());
   }
 
-    #[test]
+  #[test]
+  fn set_sparse_entry() {
+    // arrange
+    let mut allocator = GenerationalIndexAllocator::default();
+    let gi_a = allocator.allocate();
+    let gi_b = allocator.allocate();
+    let mut entries = GenerationalEntries::<u32>::default();
+    // act
+    entries.set(gi_b, 7);
+    // assert
+    assert!(entries.get(gi_a).is_none());
+    assert_eq!(Some(&7), entries.get(gi_b));
+  }
+
+  #[test]
   fn reallocated_index_should_get_none() {
     // arrange
     let mut allocator = GenerationalIndexAllocator::default();
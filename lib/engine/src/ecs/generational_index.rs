@@ -61,12 +61,12 @@ impl GenerationalIndexAllocator {
       }
       return was_live
     }
-    return false;
+    false
   }
 
   #[allow(dead_code)]
   pub fn is_live(&self, generational_index: GenerationalIndex) -> bool {
-    return self.entries[generational_index.index].is_live;
+    self.entries[generational_index.index].is_live
   }
 }
 
@@ -113,7 +113,7 @@ mod tests {
     let result = allocator.deallocate(generational_index);
     // assert
     assert!(!allocator.is_live(generational_index));
-    assert_eq!(result, true);
+    assert!(result);
   }
 
   #[test]
@@ -122,7 +122,7 @@ mod tests {
     let mut allocator = GenerationalIndexAllocator::default();
     // act
     let generational_index = allocator.allocate();
-    let result = allocator.deallocate(generational_index);
+    allocator.deallocate(generational_index);
     let generational_index = allocator.allocate();
     // assert
     assert!(allocator.is_live(generational_index));
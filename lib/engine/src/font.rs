@@ -0,0 +1,287 @@
+use gl::types::*;
+use crate::texture::{ Filter, Texture, TextureBuilder, Wrap };
+
+// The bundled font covers printable ASCII with 5x8 pixel glyphs, the last row is below the baseline
+pub const FIRST_CHAR: char = ' ';
+pub const LAST_CHAR: char = '~';
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 8;
+// in font pixels, leaves a column between glyphs and two rows between lines
+const ADVANCE: GLfloat = 6.0;
+const LINE_HEIGHT: GLfloat = 10.0;
+// drawn for characters the font doesn't have
+const FALLBACK_CHAR: char = '?';
+const ATLAS_COLUMNS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AtlasKind {
+  // coverage, looks best at whole multiples of the font size
+  Bitmap,
+  // signed distance to the glyph outline, stays sharp when scaled or seen at an angle in world space.
+  // spread is how far from the outline the distance is kept, in font pixels
+  Sdf { spread: GLfloat }
+}
+
+// Where a character is in the atlas and where its quad goes, in font pixels from the pen at the top of the line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+  pub offset: [GLfloat; 2],
+  // zero for characters without pixels, like space
+  pub size: [GLfloat; 2],
+  pub uv_min: [GLfloat; 2],
+  pub uv_max: [GLfloat; 2],
+  pub advance: GLfloat
+}
+
+// The glyphs packed into a single channel image. It is built on the CPU, so that text can be laid out without a context.
+pub struct FontAtlas {
+  pub kind: AtlasKind,
+  pub width: usize,
+  pub height: usize,
+  // a byte per texel, the first row is at v = 0
+  pub texels: Vec<u8>,
+  pub line_height: GLfloat,
+  glyphs: Vec<Glyph>
+}
+
+impl FontAtlas {
+  pub fn bitmap() -> FontAtlas {
+    FontAtlas::build(AtlasKind::Bitmap, 1, 1)
+  }
+
+  // texels_per_pixel is the resolution of the distance field per font pixel
+  pub fn sdf(texels_per_pixel: usize, spread: GLfloat) -> FontAtlas {
+    let padding = (spread * texels_per_pixel as GLfloat).ceil() as usize + 1;
+    FontAtlas::build(AtlasKind::Sdf { spread }, texels_per_pixel.max(1), padding)
+  }
+
+  // Every glyph gets a cell of scale texels per font pixel with padding texels around it
+  fn build(kind: AtlasKind, scale: usize, padding: usize) -> FontAtlas {
+    let cell_width = GLYPH_WIDTH * scale + 2 * padding;
+    let cell_height = GLYPH_HEIGHT * scale + 2 * padding;
+    let (width, height) = (ATLAS_COLUMNS * cell_width, GLYPHS.len().div_ceil(ATLAS_COLUMNS) * cell_height);
+    let mut texels = vec![0; width * height];
+    let mut glyphs = Vec::with_capacity(GLYPHS.len());
+    for (index, rows) in GLYPHS.iter().enumerate() {
+      let (cell_x, cell_y) = ((index % ATLAS_COLUMNS) * cell_width, (index / ATLAS_COLUMNS) * cell_height);
+      for y in 0..cell_height {
+        for x in 0..cell_width {
+          // the texel center in font pixels
+          let pixel_x = (x as GLfloat + 0.5 - padding as GLfloat) / scale as GLfloat;
+          let pixel_y = (y as GLfloat + 0.5 - padding as GLfloat) / scale as GLfloat;
+          texels[(cell_y + y) * width + cell_x + x] = match kind {
+            AtlasKind::Bitmap => if is_set(rows, pixel_x.floor() as i32, pixel_y.floor() as i32) { 255 } else { 0 },
+            AtlasKind::Sdf { spread } => {
+              let distance = signed_distance(rows, pixel_x, pixel_y);
+              ((0.5 + distance / (2.0 * spread)).clamp(0.0, 1.0) * 255.0).round() as u8
+            }
+          };
+        }
+      }
+      // distance field quads also cover the padding, the smooth edge and outlines fall into it
+      let margin = match kind { AtlasKind::Bitmap => 0, AtlasKind::Sdf { .. } => padding };
+      let margin_pixels = margin as GLfloat / scale as GLfloat;
+      let empty = rows.iter().all(|row| *row == 0);
+      let (u0, v0) = (cell_x + padding - margin, cell_y + padding - margin);
+      let (u1, v1) = (cell_x + padding + GLYPH_WIDTH * scale + margin, cell_y + padding + GLYPH_HEIGHT * scale + margin);
+      glyphs.push(Glyph {
+        offset: [-margin_pixels, (LINE_HEIGHT - GLYPH_HEIGHT as GLfloat) / 2.0 - margin_pixels],
+        size: if empty { [0.0, 0.0] } else { [GLYPH_WIDTH as GLfloat + 2.0 * margin_pixels, GLYPH_HEIGHT as GLfloat + 2.0 * margin_pixels] },
+        uv_min: [u0 as GLfloat / width as GLfloat, v0 as GLfloat / height as GLfloat],
+        uv_max: [u1 as GLfloat / width as GLfloat, v1 as GLfloat / height as GLfloat],
+        advance: ADVANCE
+      });
+    }
+    FontAtlas { kind, width, height, texels, line_height: LINE_HEIGHT, glyphs }
+  }
+
+  pub fn glyph(&self, c: char) -> &Glyph {
+    let index = glyph_index(c).or_else(|| glyph_index(FALLBACK_CHAR)).unwrap();
+    &self.glyphs[index]
+  }
+
+  // The texel is repeated in every channel, the texture builder only takes RGBA
+  pub fn create_texture(&self) -> Result<Texture, String> {
+    let pixels = self.texels.iter().flat_map(|texel| [*texel; 4]).collect();
+    let filter = match self.kind { AtlasKind::Bitmap => Filter::Nearest, AtlasKind::Sdf { .. } => Filter::Linear };
+    TextureBuilder::from_rgba(self.width as u32, self.height as u32, pixels)
+      .with_wrap(Wrap::ClampToEdge)
+      .with_filter(filter, filter)
+      .with_mipmaps(false)
+      .build()
+  }
+}
+
+fn glyph_index(c: char) -> Option<usize> {
+  if (FIRST_CHAR..=LAST_CHAR).contains(&c) { Some(c as usize - FIRST_CHAR as usize) } else { None }
+}
+
+fn is_set(rows: &[u8; GLYPH_HEIGHT], x: i32, y: i32) -> bool {
+  x >= 0 && y >= 0 && (x as usize) < GLYPH_WIDTH && (y as usize) < GLYPH_HEIGHT && rows[y as usize] & (0x10 >> x) != 0
+}
+
+// Distance in font pixels from the point to the nearest pixel of the other kind, positive inside the glyph.
+// Pixels are unit squares, the ring around the glyph counts as outside.
+fn signed_distance(rows: &[u8; GLYPH_HEIGHT], x: GLfloat, y: GLfloat) -> GLfloat {
+  let inside = is_set(rows, x.floor() as i32, y.floor() as i32);
+  let mut nearest = GLfloat::MAX;
+  for pixel_y in -1..=GLYPH_HEIGHT as i32 {
+    for pixel_x in -1..=GLYPH_WIDTH as i32 {
+      if is_set(rows, pixel_x, pixel_y) == inside { continue; }
+      let dx = (pixel_x as GLfloat - x).max(x - (pixel_x + 1) as GLfloat).max(0.0);
+      let dy = (pixel_y as GLfloat - y).max(y - (pixel_y + 1) as GLfloat).max(0.0);
+      nearest = nearest.min((dx * dx + dy * dy).sqrt());
+    }
+  }
+  if inside { nearest } else { -nearest }
+}
+
+// A row per byte, top to bottom, bit 4 is the leftmost pixel
+const GLYPHS: [[u8; GLYPH_HEIGHT]; 95] = [
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+  [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04, 0x00], // '!'
+  [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+  [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A, 0x00], // '#'
+  [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04, 0x00], // '$'
+  [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03, 0x00], // '%'
+  [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D, 0x00], // '&'
+  [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+  [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02, 0x00], // '('
+  [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08, 0x00], // ')'
+  [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00, 0x00], // '*'
+  [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00, 0x00], // '+'
+  [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08, 0x00], // ','
+  [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00, 0x00], // '-'
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+  [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00, 0x00], // '/'
+  [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E, 0x00], // '0'
+  [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E, 0x00], // '1'
+  [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F, 0x00], // '2'
+  [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E, 0x00], // '3'
+  [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02, 0x00], // '4'
+  [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E, 0x00], // '5'
+  [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E, 0x00], // '6'
+  [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08, 0x00], // '7'
+  [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E, 0x00], // '8'
+  [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C, 0x00], // '9'
+  [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00, 0x00], // ':'
+  [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08, 0x00], // ';'
+  [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02, 0x00], // '<'
+  [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00, 0x00], // '='
+  [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08, 0x00], // '>'
+  [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00], // '?'
+  [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E, 0x00], // '@'
+  [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11, 0x00], // 'A'
+  [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E, 0x00], // 'B'
+  [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E, 0x00], // 'C'
+  [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C, 0x00], // 'D'
+  [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F, 0x00], // 'E'
+  [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10, 0x00], // 'F'
+  [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F, 0x00], // 'G'
+  [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11, 0x00], // 'H'
+  [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E, 0x00], // 'I'
+  [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C, 0x00], // 'J'
+  [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11, 0x00], // 'K'
+  [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F, 0x00], // 'L'
+  [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11, 0x00], // 'M'
+  [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x00], // 'N'
+  [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E, 0x00], // 'O'
+  [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10, 0x00], // 'P'
+  [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D, 0x00], // 'Q'
+  [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11, 0x00], // 'R'
+  [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E, 0x00], // 'S'
+  [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // 'T'
+  [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E, 0x00], // 'U'
+  [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04, 0x00], // 'V'
+  [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A, 0x00], // 'W'
+  [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11, 0x00], // 'X'
+  [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x00], // 'Y'
+  [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F, 0x00], // 'Z'
+  [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E, 0x00], // '['
+  [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00, 0x00], // '\\'
+  [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E, 0x00], // ']'
+  [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+  [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // '_'
+  [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+  [0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F, 0x00], // 'a'
+  [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1E, 0x00], // 'b'
+  [0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E, 0x00], // 'c'
+  [0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F, 0x00], // 'd'
+  [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E, 0x00], // 'e'
+  [0x06, 0x09, 0x08, 0x1C, 0x08, 0x08, 0x08, 0x00], // 'f'
+  [0x00, 0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x0E], // 'g'
+  [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00], // 'h'
+  [0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x0E, 0x00], // 'i'
+  [0x02, 0x00, 0x06, 0x02, 0x02, 0x02, 0x12, 0x0C], // 'j'
+  [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12, 0x00], // 'k'
+  [0x0C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E, 0x00], // 'l'
+  [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11, 0x00], // 'm'
+  [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00], // 'n'
+  [0x00, 0x00, 0x0E, 0x11, 0x11, 0x11, 0x0E, 0x00], // 'o'
+  [0x00, 0x00, 0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10], // 'p'
+  [0x00, 0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x01], // 'q'
+  [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10, 0x00], // 'r'
+  [0x00, 0x00, 0x0F, 0x10, 0x0E, 0x01, 0x1E, 0x00], // 's'
+  [0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06, 0x00], // 't'
+  [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0D, 0x00], // 'u'
+  [0x00, 0x00, 0x11, 0x11, 0x11, 0x0A, 0x04, 0x00], // 'v'
+  [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0A, 0x00], // 'w'
+  [0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x00], // 'x'
+  [0x00, 0x00, 0x11, 0x11, 0x11, 0x0F, 0x01, 0x0E], // 'y'
+  [0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F, 0x00], // 'z'
+  [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02, 0x00], // '{'
+  [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // '|'
+  [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08, 0x00], // '}'
+  [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00, 0x00], // '~'
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn texel(atlas: &FontAtlas, u: GLfloat, v: GLfloat) -> u8 {
+    atlas.texels[(v * atlas.height as GLfloat) as usize * atlas.width + (u * atlas.width as GLfloat) as usize]
+  }
+
+  #[test]
+  fn bitmap_glyphs_point_at_their_pixels() {
+    // arrange
+    let atlas = FontAtlas::bitmap();
+    // act
+    let glyph = atlas.glyph('T');
+    // assert
+    assert_eq!([5.0, 8.0], glyph.size);
+    assert_eq!(6.0, glyph.advance);
+    // the top left pixel of T is set, the one below it isn't
+    let (u, v) = (glyph.uv_min[0], glyph.uv_min[1]);
+    let (texel_u, texel_v) = (1.0 / atlas.width as GLfloat, 1.0 / atlas.height as GLfloat);
+    assert_eq!(255, texel(&atlas, u + 0.5 * texel_u, v + 0.5 * texel_v));
+    assert_eq!(0, texel(&atlas, u + 0.5 * texel_u, v + 1.5 * texel_v));
+  }
+
+  #[test]
+  fn unknown_characters_fall_back_and_space_has_no_quad() {
+    let atlas = FontAtlas::bitmap();
+    assert_eq!(atlas.glyph('?'), atlas.glyph('\u{e9}'));
+    assert_eq!([0.0, 0.0], atlas.glyph(' ').size);
+    assert_eq!(6.0, atlas.glyph(' ').advance);
+  }
+
+  #[test]
+  fn distance_field_is_half_on_the_outline() {
+    // arrange
+    let rows = GLYPHS[glyph_index('|').unwrap()];
+    // act
+    let center = signed_distance(&rows, 2.5, 3.5);
+    let edge = signed_distance(&rows, 3.0, 3.5);
+    let outside = signed_distance(&rows, 4.5, 3.5);
+    let atlas = FontAtlas::sdf(4, 1.0);
+    // assert
+    assert_eq!(0.5, center);
+    assert_eq!(0.0, edge);
+    assert_eq!(-1.5, outside);
+    let glyph = atlas.glyph('|');
+    assert!(glyph.size[0] > 5.0 && glyph.offset[0] < 0.0);
+    assert!(glyph.uv_min[0] >= 0.0 && glyph.uv_max[1] <= 1.0);
+  }
+}
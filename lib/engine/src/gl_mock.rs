@@ -0,0 +1,323 @@
+// A fake OpenGL implementation for unit tests.
+// install() loads the mock functions into the gl crate; every call is recorded per test thread,
+// object names are handed out from a counter and the live objects of each kind are tracked.
+// GL functions that are not mocked here panic with "function was not loaded" when called.
+#![allow(dead_code)]
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::Once;
+use gl::types::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlCall {
+  pub name: &'static str,
+  pub args: Vec<i64>
+}
+
+pub struct MockGl {
+  next_name: GLuint,
+  pub calls: Vec<GlCall>,
+  pub live_objects: HashMap<&'static str, HashSet<GLuint>>,
+  pub framebuffer_status: GLenum,
+  pub integers: HashMap<GLenum, GLint>,
+  // uniform blocks every program declares, by index
+  pub uniform_blocks: Vec<String>,
+  // active attributes every program declares as name, location, type and array size
+  pub attributes: Vec<(String, GLint, GLenum, GLint)>,
+  // returned by glGetError, oldest first
+  pub errors: Vec<GLenum>,
+  // results of the queries that are available, by query name
+  pub query_results: HashMap<GLuint, GLuint64>,
+  // raised by every glBlitFramebuffer, NO_ERROR for none
+  pub blit_error: GLenum
+}
+
+impl Default for MockGl {
+  fn default() -> Self {
+    let mut integers = HashMap::new();
+    integers.insert(gl::MAX_COLOR_ATTACHMENTS, 8);
+    integers.insert(gl::MAX_DRAW_BUFFERS, 8);
+    MockGl {
+      next_name: 0,
+      calls: Vec::new(),
+      live_objects: HashMap::new(),
+      framebuffer_status: gl::FRAMEBUFFER_COMPLETE,
+      integers,
+      uniform_blocks: Vec::new(),
+      attributes: Vec::new(),
+      errors: Vec::new(),
+      query_results: HashMap::new(),
+      blit_error: gl::NO_ERROR
+    }
+  }
+}
+
+thread_local! {
+  static STATE: RefCell<MockGl> = RefCell::new(MockGl::default());
+}
+
+static LOAD: Once = Once::new();
+
+// Loads the mock into the gl crate and resets the state of the current thread
+pub fn install() {
+  LOAD.call_once(|| gl::load_with(lookup));
+  STATE.with(|state| *state.borrow_mut() = MockGl::default());
+}
+
+pub fn with_state<R, F: FnOnce(&mut MockGl) -> R>(f: F) -> R {
+  STATE.with(|state| f(&mut state.borrow_mut()))
+}
+
+pub fn calls() -> Vec<GlCall> {
+  with_state(|state| state.calls.clone())
+}
+
+pub fn calls_to(name: &str) -> Vec<GlCall> {
+  with_state(|state| state.calls.iter().filter(|call| call.name == name).cloned().collect())
+}
+
+pub fn count(name: &str) -> usize {
+  calls_to(name).len()
+}
+
+pub fn clear_calls() {
+  with_state(|state| state.calls.clear());
+}
+
+pub fn live_count(kind: &str) -> usize {
+  with_state(|state| state.live_objects.get(kind).map_or(0, |names| names.len()))
+}
+
+pub fn set_framebuffer_status(status: GLenum) {
+  with_state(|state| state.framebuffer_status = status);
+}
+
+pub fn set_uniform_blocks(names: &[&str]) {
+  with_state(|state| state.uniform_blocks = names.iter().map(|name| name.to_string()).collect());
+}
+
+pub fn push_error(error: GLenum) {
+  with_state(|state| state.errors.push(error));
+}
+
+pub fn set_attributes(attributes: &[(&str, GLint, GLenum, GLint)]) {
+  with_state(|state| state.attributes = attributes.iter().map(|(name, location, gl_type, size)| (name.to_string(), *location, *gl_type, *size)).collect());
+}
+
+pub fn set_blit_error(error: GLenum) {
+  with_state(|state| state.blit_error = error);
+}
+
+pub fn set_query_result(query: GLuint, result: GLuint64) {
+  with_state(|state| state.query_results.insert(query, result));
+}
+
+fn record(name: &'static str, args: Vec<i64>) {
+  with_state(|state| state.calls.push(GlCall { name, args }));
+}
+
+unsafe fn generate(name: &'static str, kind: &'static str, n: GLsizei, names: *mut GLuint) {
+  let mut generated = Vec::new();
+  with_state(|state| {
+    for i in 0..n as usize {
+      state.next_name += 1;
+      *names.add(i) = state.next_name;
+      state.live_objects.entry(kind).or_default().insert(state.next_name);
+      generated.push(state.next_name as i64);
+    }
+  });
+  record(name, generated);
+}
+
+fn create(name: &'static str, kind: &'static str) -> GLuint {
+  let object = with_state(|state| {
+    state.next_name += 1;
+    state.live_objects.entry(kind).or_default().insert(state.next_name);
+    state.next_name
+  });
+  record(name, vec![object as i64]);
+  object
+}
+
+fn destroy(name: &'static str, kind: &'static str, object: GLuint) {
+  with_state(|state| if let Some(live) = state.live_objects.get_mut(kind) { live.remove(&object); });
+  record(name, vec![object as i64]);
+}
+
+unsafe fn delete(name: &'static str, kind: &'static str, n: GLsizei, names: *const GLuint) {
+  let mut deleted = Vec::new();
+  with_state(|state| {
+    for i in 0..n as usize {
+      let object = *names.add(i);
+      if let Some(live) = state.live_objects.get_mut(kind) { live.remove(&object); }
+      deleted.push(object as i64);
+    }
+  });
+  record(name, deleted);
+}
+
+macro_rules! mock_gl {
+  ($($symbol:literal => fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)? $body:block)*) => {
+    $(
+      #[allow(clippy::not_unsafe_ptr_arg_deref)]
+      extern "system" fn $name($($arg: $ty),*) $(-> $ret)? { #[allow(unused_unsafe)] unsafe { $body } }
+    )*
+
+    fn lookup(symbol: &'static str) -> *const c_void {
+      match symbol {
+        $($symbol => $name as *const c_void,)*
+        _ => ptr::null()
+      }
+    }
+  }
+}
+
+mock_gl! {
+  "glGenFramebuffers" => fn gen_framebuffers(n: GLsizei, names: *mut GLuint) { generate("glGenFramebuffers", "framebuffer", n, names) }
+  "glDeleteFramebuffers" => fn delete_framebuffers(n: GLsizei, names: *const GLuint) { delete("glDeleteFramebuffers", "framebuffer", n, names) }
+  "glBindFramebuffer" => fn bind_framebuffer(target: GLenum, framebuffer: GLuint) { record("glBindFramebuffer", vec![target as i64, framebuffer as i64]) }
+  "glCheckFramebufferStatus" => fn check_framebuffer_status(target: GLenum) -> GLenum {
+    record("glCheckFramebufferStatus", vec![target as i64]);
+    with_state(|state| state.framebuffer_status)
+  }
+  "glFramebufferTexture2D" => fn framebuffer_texture_2d(target: GLenum, attachment: GLenum, textarget: GLenum, texture: GLuint, level: GLint) {
+    record("glFramebufferTexture2D", vec![target as i64, attachment as i64, textarget as i64, texture as i64, level as i64])
+  }
+  "glDrawBuffers" => fn draw_buffers(n: GLsizei, bufs: *const GLenum) {
+    record("glDrawBuffers", (0..n as usize).map(|i| *bufs.add(i) as i64).collect())
+  }
+  "glBlitFramebuffer" => fn blit_framebuffer(src_x0: GLint, src_y0: GLint, src_x1: GLint, src_y1: GLint, dst_x0: GLint, dst_y0: GLint, dst_x1: GLint, dst_y1: GLint, mask: GLbitfield, filter: GLenum) {
+    record("glBlitFramebuffer", vec![src_x0 as i64, src_y0 as i64, src_x1 as i64, src_y1 as i64, dst_x0 as i64, dst_y0 as i64, dst_x1 as i64, dst_y1 as i64, mask as i64, filter as i64]);
+    with_state(|state| if state.blit_error != gl::NO_ERROR { state.errors.push(state.blit_error); });
+  }
+  "glDrawBuffer" => fn draw_buffer(buf: GLenum) { record("glDrawBuffer", vec![buf as i64]) }
+  "glReadBuffer" => fn read_buffer(src: GLenum) { record("glReadBuffer", vec![src as i64]) }
+  "glGenTextures" => fn gen_textures(n: GLsizei, names: *mut GLuint) { generate("glGenTextures", "texture", n, names) }
+  "glDeleteTextures" => fn delete_textures(n: GLsizei, names: *const GLuint) { delete("glDeleteTextures", "texture", n, names) }
+  "glBindTexture" => fn bind_texture(target: GLenum, texture: GLuint) { record("glBindTexture", vec![target as i64, texture as i64]) }
+  "glActiveTexture" => fn active_texture(texture: GLenum) { record("glActiveTexture", vec![texture as i64]) }
+  "glTexImage2D" => fn tex_image_2d(target: GLenum, level: GLint, internal_format: GLint, width: GLsizei, height: GLsizei, border: GLint, format: GLenum, data_type: GLenum, _pixels: *const c_void) {
+    record("glTexImage2D", vec![target as i64, level as i64, internal_format as i64, width as i64, height as i64, border as i64, format as i64, data_type as i64])
+  }
+  "glPixelStorei" => fn pixel_store_i(pname: GLenum, param: GLint) { record("glPixelStorei", vec![pname as i64, param as i64]) }
+  "glTexParameteri" => fn tex_parameter_i(target: GLenum, pname: GLenum, param: GLint) { record("glTexParameteri", vec![target as i64, pname as i64, param as i64]) }
+  "glTexParameterfv" => fn tex_parameter_fv(target: GLenum, pname: GLenum, _params: *const GLfloat) { record("glTexParameterfv", vec![target as i64, pname as i64]) }
+  "glGetIntegerv" => fn get_integerv(pname: GLenum, data: *mut GLint) {
+    record("glGetIntegerv", vec![pname as i64]);
+    *data = with_state(|state| state.integers.get(&pname).cloned().unwrap_or(0));
+  }
+  "glViewport" => fn viewport(x: GLint, y: GLint, width: GLsizei, height: GLsizei) { record("glViewport", vec![x as i64, y as i64, width as i64, height as i64]) }
+  "glClear" => fn clear(mask: GLbitfield) { record("glClear", vec![mask as i64]) }
+  "glEnable" => fn enable(capability: GLenum) { record("glEnable", vec![capability as i64]) }
+  "glDisable" => fn disable(capability: GLenum) { record("glDisable", vec![capability as i64]) }
+  "glDepthMask" => fn depth_mask(flag: GLboolean) { record("glDepthMask", vec![flag as i64]) }
+  "glDepthFunc" => fn depth_func(func: GLenum) { record("glDepthFunc", vec![func as i64]) }
+  "glCullFace" => fn cull_face(mode: GLenum) { record("glCullFace", vec![mode as i64]) }
+  "glBlendFunc" => fn blend_func(source: GLenum, destination: GLenum) { record("glBlendFunc", vec![source as i64, destination as i64]) }
+  "glPolygonMode" => fn polygon_mode(face: GLenum, mode: GLenum) { record("glPolygonMode", vec![face as i64, mode as i64]) }
+  // floats are recorded as their bits
+  "glPolygonOffset" => fn polygon_offset(factor: GLfloat, units: GLfloat) { record("glPolygonOffset", vec![factor.to_bits() as i64, units.to_bits() as i64]) }
+  "glPointSize" => fn point_size(size: GLfloat) { record("glPointSize", vec![size.to_bits() as i64]) }
+  "glScissor" => fn scissor(x: GLint, y: GLint, width: GLsizei, height: GLsizei) { record("glScissor", vec![x as i64, y as i64, width as i64, height as i64]) }
+  "glGenVertexArrays" => fn gen_vertex_arrays(n: GLsizei, names: *mut GLuint) { generate("glGenVertexArrays", "vertex array", n, names) }
+  "glBindVertexArray" => fn bind_vertex_array(array: GLuint) { record("glBindVertexArray", vec![array as i64]) }
+  "glGenBuffers" => fn gen_buffers(n: GLsizei, names: *mut GLuint) { generate("glGenBuffers", "buffer", n, names) }
+  "glBindBuffer" => fn bind_buffer(target: GLenum, buffer: GLuint) { record("glBindBuffer", vec![target as i64, buffer as i64]) }
+  "glBufferData" => fn buffer_data(target: GLenum, size: GLsizeiptr, _data: *const c_void, usage: GLenum) { record("glBufferData", vec![target as i64, size as i64, usage as i64]) }
+  "glDeleteBuffers" => fn delete_buffers(n: GLsizei, names: *const GLuint) { delete("glDeleteBuffers", "buffer", n, names) }
+  "glBufferSubData" => fn buffer_sub_data(target: GLenum, offset: GLintptr, size: GLsizeiptr, _data: *const c_void) { record("glBufferSubData", vec![target as i64, offset as i64, size as i64]) }
+  "glCopyBufferSubData" => fn copy_buffer_sub_data(read_target: GLenum, write_target: GLenum, read_offset: GLintptr, write_offset: GLintptr, size: GLsizeiptr) {
+    record("glCopyBufferSubData", vec![read_target as i64, write_target as i64, read_offset as i64, write_offset as i64, size as i64])
+  }
+  "glEnableVertexAttribArray" => fn enable_vertex_attrib_array(index: GLuint) { record("glEnableVertexAttribArray", vec![index as i64]) }
+  "glVertexAttribPointer" => fn vertex_attrib_pointer(index: GLuint, size: GLint, data_type: GLenum, normalized: GLboolean, stride: GLsizei, pointer: *const c_void) {
+    record("glVertexAttribPointer", vec![index as i64, size as i64, data_type as i64, normalized as i64, stride as i64, pointer as i64])
+  }
+  "glVertexAttribIPointer" => fn vertex_attrib_i_pointer(index: GLuint, size: GLint, data_type: GLenum, stride: GLsizei, pointer: *const c_void) {
+    record("glVertexAttribIPointer", vec![index as i64, size as i64, data_type as i64, stride as i64, pointer as i64])
+  }
+  "glVertexAttribDivisor" => fn vertex_attrib_divisor(index: GLuint, divisor: GLuint) { record("glVertexAttribDivisor", vec![index as i64, divisor as i64]) }
+  "glDrawArrays" => fn draw_arrays(mode: GLenum, first: GLint, count: GLsizei) { record("glDrawArrays", vec![mode as i64, first as i64, count as i64]) }
+  "glDrawArraysInstanced" => fn draw_arrays_instanced(mode: GLenum, first: GLint, count: GLsizei, instance_count: GLsizei) {
+    record("glDrawArraysInstanced", vec![mode as i64, first as i64, count as i64, instance_count as i64])
+  }
+  "glDrawElementsInstanced" => fn draw_elements_instanced(mode: GLenum, count: GLsizei, data_type: GLenum, indices: *const c_void, instance_count: GLsizei) {
+    record("glDrawElementsInstanced", vec![mode as i64, count as i64, data_type as i64, indices as i64, instance_count as i64])
+  }
+  "glDeleteVertexArrays" => fn delete_vertex_arrays(n: GLsizei, names: *const GLuint) { delete("glDeleteVertexArrays", "vertex array", n, names) }
+  "glCreateProgram" => fn create_program() -> GLuint { create("glCreateProgram", "program") }
+  "glDeleteProgram" => fn delete_program(program: GLuint) { destroy("glDeleteProgram", "program", program) }
+  "glCreateShader" => fn create_shader(shader_type: GLenum) -> GLuint { let _ = shader_type; create("glCreateShader", "shader") }
+  "glDeleteShader" => fn delete_shader(shader: GLuint) { destroy("glDeleteShader", "shader", shader) }
+  "glShaderSource" => fn shader_source(shader: GLuint, _count: GLsizei, _string: *const *const GLchar, _length: *const GLint) { record("glShaderSource", vec![shader as i64]) }
+  "glCompileShader" => fn compile_shader(shader: GLuint) { record("glCompileShader", vec![shader as i64]) }
+  "glAttachShader" => fn attach_shader(program: GLuint, shader: GLuint) { record("glAttachShader", vec![program as i64, shader as i64]) }
+  "glDetachShader" => fn detach_shader(program: GLuint, shader: GLuint) { record("glDetachShader", vec![program as i64, shader as i64]) }
+  "glLinkProgram" => fn link_program(program: GLuint) { record("glLinkProgram", vec![program as i64]) }
+  "glUseProgram" => fn use_program(program: GLuint) { record("glUseProgram", vec![program as i64]) }
+  // compiling, linking and validating always succeed
+  "glGetShaderiv" => fn get_shaderiv(shader: GLuint, pname: GLenum, params: *mut GLint) { record("glGetShaderiv", vec![shader as i64, pname as i64]); *params = 1; }
+  "glGetProgramiv" => fn get_programiv(program: GLuint, pname: GLenum, params: *mut GLint) {
+    record("glGetProgramiv", vec![program as i64, pname as i64]);
+    *params = with_state(|state| match pname {
+      gl::ACTIVE_ATTRIBUTES => state.attributes.len() as GLint,
+      gl::ACTIVE_ATTRIBUTE_MAX_LENGTH => state.attributes.iter().map(|attribute| attribute.0.len() as GLint + 1).max().unwrap_or(0),
+      _ => 1
+    });
+  }
+  "glGetError" => fn get_error() -> GLenum {
+    with_state(|state| if state.errors.is_empty() { gl::NO_ERROR } else { state.errors.remove(0) })
+  }
+  "glPushDebugGroup" => fn push_debug_group(source: GLenum, id: GLuint, _length: GLsizei, _message: *const GLchar) {
+    record("glPushDebugGroup", vec![source as i64, id as i64])
+  }
+  "glPopDebugGroup" => fn pop_debug_group() { record("glPopDebugGroup", vec![]) }
+  "glGetActiveAttrib" => fn get_active_attrib(program: GLuint, index: GLuint, buf_size: GLsizei, length: *mut GLsizei, size: *mut GLint, gl_type: *mut GLenum, name: *mut GLchar) {
+    record("glGetActiveAttrib", vec![program as i64, index as i64]);
+    let (attribute_name, _, attribute_type, attribute_size) = with_state(|state| state.attributes[index as usize].clone());
+    let written = attribute_name.len().min(buf_size as usize - 1);
+    ptr::copy_nonoverlapping(attribute_name.as_ptr() as *const GLchar, name, written);
+    *name.add(written) = 0;
+    *length = written as GLsizei;
+    *size = attribute_size;
+    *gl_type = attribute_type;
+  }
+  "glGetAttribLocation" => fn get_attrib_location(program: GLuint, name: *const GLchar) -> GLint {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    record("glGetAttribLocation", vec![program as i64]);
+    with_state(|state| state.attributes.iter().find(|attribute| attribute.0 == name).map_or(-1, |attribute| attribute.1))
+  }
+  // programs have no plain uniforms
+  "glGetUniformLocation" => fn get_uniform_location(program: GLuint, _name: *const GLchar) -> GLint {
+    record("glGetUniformLocation", vec![program as i64]);
+    -1
+  }
+  "glBindBufferBase" => fn bind_buffer_base(target: GLenum, index: GLuint, buffer: GLuint) { record("glBindBufferBase", vec![target as i64, index as i64, buffer as i64]) }
+  "glGetUniformBlockIndex" => fn get_uniform_block_index(program: GLuint, name: *const GLchar) -> GLuint {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    record("glGetUniformBlockIndex", vec![program as i64]);
+    with_state(|state| state.uniform_blocks.iter().position(|block| *block == name).map_or(gl::INVALID_INDEX, |index| index as GLuint))
+  }
+  "glUniformBlockBinding" => fn uniform_block_binding(program: GLuint, index: GLuint, binding: GLuint) {
+    record("glUniformBlockBinding", vec![program as i64, index as i64, binding as i64])
+  }
+  "glGenQueries" => fn gen_queries(n: GLsizei, names: *mut GLuint) { generate("glGenQueries", "query", n, names) }
+  "glDeleteQueries" => fn delete_queries(n: GLsizei, names: *const GLuint) { delete("glDeleteQueries", "query", n, names) }
+  "glBeginQuery" => fn begin_query(target: GLenum, query: GLuint) { record("glBeginQuery", vec![target as i64, query as i64]) }
+  "glEndQuery" => fn end_query(target: GLenum) { record("glEndQuery", vec![target as i64]) }
+  // a query is available once set_query_result gave it a result
+  "glGetQueryObjectiv" => fn get_query_objectiv(query: GLuint, pname: GLenum, params: *mut GLint) {
+    record("glGetQueryObjectiv", vec![query as i64, pname as i64]);
+    *params = with_state(|state| state.query_results.contains_key(&query) as GLint);
+  }
+  "glGetQueryObjectui64v" => fn get_query_objectui64v(query: GLuint, pname: GLenum, params: *mut GLuint64) {
+    record("glGetQueryObjectui64v", vec![query as i64, pname as i64]);
+    *params = with_state(|state| state.query_results.get(&query).cloned().unwrap_or(0));
+  }
+  "glColorMask" => fn color_mask(red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean) {
+    record("glColorMask", vec![red as i64, green as i64, blue as i64, alpha as i64])
+  }
+}
@@ -0,0 +1,9 @@
+#version 450
+
+in vec4 Color;
+out vec4 FragmentColor;
+
+void main()
+{
+    FragmentColor = Color;
+}
@@ -0,0 +1,16 @@
+#version 450
+
+// World space debug lines, see engine::debug_draw
+
+layout (location = 0) in vec3 VertexPosition;
+layout (location = 1) in vec4 VertexColor;
+
+out vec4 Color;
+
+uniform mat4 ViewProjection;
+
+void main()
+{
+    Color = VertexColor;
+    gl_Position = ViewProjection * vec4(VertexPosition, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Writes the depth of the G-buffer, drawn with the full-screen triangle when blitting the depth is not possible
+
+in vec2 UV;
+
+uniform sampler2D GDepth;
+
+void main()
+{
+    gl_FragDepth = texture(GDepth, UV).r;
+}
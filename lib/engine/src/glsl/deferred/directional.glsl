@@ -0,0 +1,82 @@
+#version 450
+
+// Ambient and directional light for every pixel of the G-buffer, drawn with the full-screen triangle
+
+// must match MAX_DIRECTIONAL_LIGHTS in engine::light and MAX_SHADOW_MAPS in engine::shadow
+#define MAX_DIRECTIONAL_LIGHTS 4
+#define MAX_SHADOW_MAPS 2
+
+struct DirectionalLight {
+    vec3 Direction;
+    vec3 Color;
+    int ShadowMap; // index into ShadowMaps, -1 if the light casts no shadows
+};
+
+uniform DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
+uniform int DirectionalLightCount;
+uniform sampler2DShadow ShadowMaps[MAX_SHADOW_MAPS];
+uniform mat4 ShadowMatrices[MAX_SHADOW_MAPS];
+uniform vec3 EyePosition;
+uniform vec3 AmbientColor = vec3(0.05);
+
+uniform sampler2D GAlbedo;
+uniform sampler2D GNormal;
+uniform sampler2D GDepth;
+uniform mat4 InverseViewProjection;
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+vec3 phong(vec3 albedo, float specularIntensity, float shininess, vec3 normal, vec3 toLight, vec3 toEye, vec3 radiance)
+{
+    float diffuse = max(dot(normal, toLight), 0.0);
+    float specular = 0.0;
+    if (diffuse > 0.0) {
+        vec3 reflected = reflect(-toLight, normal);
+        specular = pow(max(dot(reflected, toEye), 0.0), shininess);
+    }
+    return radiance * (albedo * diffuse + vec3(specularIntensity * specular));
+}
+
+float shadowFactor(int shadowMap, vec3 worldPosition, vec3 normal, vec3 toLight)
+{
+    vec4 lightSpace = ShadowMatrices[shadowMap] * vec4(worldPosition, 1.0);
+    vec3 projected = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (projected.z > 1.0) {
+        return 1.0;
+    }
+    float bias = max(0.004 * (1.0 - dot(normal, toLight)), 0.0005);
+    vec2 texelSize = 1.0 / vec2(textureSize(ShadowMaps[shadowMap], 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(ShadowMaps[shadowMap], vec3(projected.xy + vec2(x, y) * texelSize, projected.z - bias));
+        }
+    }
+    return lit / 9.0;
+}
+
+void main()
+{
+    float depth = texture(GDepth, UV).r;
+    if (depth >= 1.0) {
+        // nothing was drawn here, keep the clear color
+        discard;
+    }
+    vec4 world = InverseViewProjection * vec4(vec3(UV, depth) * 2.0 - 1.0, 1.0);
+    vec3 worldPosition = world.xyz / world.w;
+    vec4 albedo = texture(GAlbedo, UV);
+    vec4 normalShininess = texture(GNormal, UV);
+    vec3 normal = normalize(normalShininess.xyz);
+    vec3 toEye = normalize(EyePosition - worldPosition);
+    vec3 color = AmbientColor * albedo.rgb;
+    for (int i = 0; i < DirectionalLightCount; i++) {
+        vec3 toLight = -DirectionalLights[i].Direction;
+        float shadow = 1.0;
+        if (DirectionalLights[i].ShadowMap >= 0) {
+            shadow = shadowFactor(DirectionalLights[i].ShadowMap, worldPosition, normal, toLight);
+        }
+        color += shadow * phong(albedo.rgb, albedo.a, normalShininess.w, normal, toLight, toEye, DirectionalLights[i].Color);
+    }
+    FragmentColor = vec4(color, 1.0);
+}
@@ -0,0 +1,43 @@
+#version 450
+
+// Adds the light of one point light to the pixels covered by its volume
+
+uniform vec3 LightPosition;
+uniform vec3 LightColor;
+uniform vec3 LightAttenuation; // constant, linear, quadratic
+uniform vec3 EyePosition;
+
+uniform sampler2D GAlbedo;
+uniform sampler2D GNormal;
+uniform sampler2D GDepth;
+uniform mat4 InverseViewProjection;
+uniform vec2 ScreenSize;
+
+out vec4 FragmentColor;
+
+void main()
+{
+    vec2 uv = gl_FragCoord.xy / ScreenSize;
+    float depth = texture(GDepth, uv).r;
+    if (depth >= 1.0) {
+        discard;
+    }
+    vec4 world = InverseViewProjection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+    vec3 worldPosition = world.xyz / world.w;
+    vec4 albedo = texture(GAlbedo, uv);
+    vec4 normalShininess = texture(GNormal, uv);
+    vec3 normal = normalize(normalShininess.xyz);
+
+    vec3 toLight = LightPosition - worldPosition;
+    float distance = length(toLight);
+    toLight /= distance;
+    float attenuation = 1.0 / (LightAttenuation.x + LightAttenuation.y * distance + LightAttenuation.z * distance * distance);
+    float diffuse = max(dot(normal, toLight), 0.0);
+    float specular = 0.0;
+    if (diffuse > 0.0) {
+        vec3 toEye = normalize(EyePosition - worldPosition);
+        specular = pow(max(dot(reflect(-toLight, normal), toEye), 0.0), normalShininess.w);
+    }
+    vec3 color = LightColor * attenuation * (albedo.rgb * diffuse + vec3(albedo.a * specular));
+    FragmentColor = vec4(color, 1.0);
+}
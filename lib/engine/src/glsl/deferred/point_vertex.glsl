@@ -0,0 +1,13 @@
+#version 450
+
+// A sphere around the point light that encloses every pixel it lights
+
+layout (location = 0) in vec3 VertexPosition;
+
+uniform mat4 Model;
+uniform mat4 ViewProjection;
+
+void main()
+{
+    gl_Position = ViewProjection * Model * vec4(VertexPosition, 1.0);
+}
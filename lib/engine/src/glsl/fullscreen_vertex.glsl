@@ -0,0 +1,11 @@
+#version 450
+
+// A single triangle that covers the screen, generated from gl_VertexID without vertex buffers
+
+out vec2 UV;
+
+void main()
+{
+    UV = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    gl_Position = vec4(UV * 2.0 - 1.0, 0.0, 1.0);
+}
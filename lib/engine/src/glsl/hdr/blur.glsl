@@ -0,0 +1,24 @@
+#version 450
+
+// One direction of a separable 9 tap Gaussian blur, using linear filtering to fetch two taps at once
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+// size of a texel along the blur direction, e.g. (1 / width, 0) for the horizontal pass
+uniform vec2 Direction;
+
+const float Offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float Weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main()
+{
+    vec3 color = texture(Source, UV).rgb * Weights[0];
+    for (int i = 1; i < 3; i++)
+    {
+        color += texture(Source, UV + Direction * Offsets[i]).rgb * Weights[i];
+        color += texture(Source, UV - Direction * Offsets[i]).rgb * Weights[i];
+    }
+    FragmentColor = vec4(color, 1.0);
+}
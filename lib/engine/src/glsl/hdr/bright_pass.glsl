@@ -0,0 +1,23 @@
+#version 450
+
+// Downsamples to half resolution and keeps the part of the color above the threshold
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+uniform vec2 TexelSize;
+uniform float Threshold = 1.0;
+
+void main()
+{
+    // box filter over the 2x2 source texels that fold into this texel
+    vec3 color = 0.25 * (
+        texture(Source, UV + TexelSize * vec2(-0.5, -0.5)).rgb +
+        texture(Source, UV + TexelSize * vec2(0.5, -0.5)).rgb +
+        texture(Source, UV + TexelSize * vec2(-0.5, 0.5)).rgb +
+        texture(Source, UV + TexelSize * vec2(0.5, 0.5)).rgb);
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - Threshold, 0.0) / max(brightness, 0.0001);
+    FragmentColor = vec4(color * contribution, 1.0);
+}
@@ -0,0 +1,48 @@
+#version 450
+
+// Composites the bloom levels over the HDR scene, applies the exposure and tonemaps to [0, 1]
+
+#define MAX_BLOOM_LEVELS 6
+#define TONEMAP_NONE 0
+#define TONEMAP_REINHARD 1
+#define TONEMAP_ACES 2
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+uniform sampler2D BloomLevels[MAX_BLOOM_LEVELS];
+uniform int BloomLevelCount = 0;
+uniform float BloomIntensity = 0.04;
+uniform float Exposure = 1.0;
+uniform int Tonemap = TONEMAP_ACES;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec3 color = texture(Source, UV).rgb;
+    if (BloomLevelCount > 0)
+    {
+        vec3 bloom = vec3(0.0);
+        for (int i = 0; i < BloomLevelCount; i++)
+        {
+            bloom += texture(BloomLevels[i], UV).rgb;
+        }
+        color = mix(color, bloom / float(BloomLevelCount), BloomIntensity);
+    }
+    color *= Exposure;
+    if (Tonemap == TONEMAP_REINHARD)
+    {
+        color = color / (1.0 + color);
+    }
+    else if (Tonemap == TONEMAP_ACES)
+    {
+        color = aces(color);
+    }
+    FragmentColor = vec4(color, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+in vec2 UV;
+in vec4 Tint;
+out vec4 FragmentColor;
+
+uniform sampler2D Sprite;
+
+void main()
+{
+    vec4 color = texture(Sprite, UV) * Tint;
+    // fully transparent texels don't hide what is behind them
+    if (color.a <= 0.0) {
+        discard;
+    }
+    FragmentColor = color;
+}
@@ -0,0 +1,19 @@
+#version 450
+
+// Sprite quads in world space, see engine::sprite
+
+layout (location = 0) in vec3 VertexPosition;
+layout (location = 1) in vec2 VertexUv;
+layout (location = 2) in vec4 VertexTint;
+
+out vec2 UV;
+out vec4 Tint;
+
+uniform mat4 ViewProjection;
+
+void main()
+{
+    UV = VertexUv;
+    Tint = VertexTint;
+    gl_Position = ViewProjection * vec4(VertexPosition, 1.0);
+}
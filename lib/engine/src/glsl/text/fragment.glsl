@@ -0,0 +1,25 @@
+#version 450
+
+// Bitmap atlases hold the coverage, distance field atlases the distance to the outline with 0.5 on it
+
+in vec2 UV;
+in vec4 Color;
+out vec4 FragmentColor;
+
+uniform sampler2D Atlas;
+uniform bool DistanceField = false;
+
+void main()
+{
+    float value = texture(Atlas, UV).a;
+    float coverage = value;
+    if (DistanceField) {
+        // an edge about a pixel wide at any scale
+        float edge = max(fwidth(value) * 0.75, 0.0001);
+        coverage = smoothstep(0.5 - edge, 0.5 + edge, value);
+    }
+    if (coverage <= 0.0) {
+        discard;
+    }
+    FragmentColor = vec4(Color.rgb, Color.a * coverage);
+}
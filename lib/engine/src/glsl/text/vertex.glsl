@@ -0,0 +1,17 @@
+#version 450
+
+// Glyph quads whose corners are already in clip space, see engine::text
+
+layout (location = 0) in vec4 VertexPosition;
+layout (location = 1) in vec2 VertexUv;
+layout (location = 2) in vec4 VertexColor;
+
+out vec2 UV;
+out vec4 Color;
+
+void main()
+{
+    UV = VertexUv;
+    Color = VertexColor;
+    gl_Position = VertexPosition;
+}
@@ -0,0 +1,215 @@
+use gl::types::*;
+use cgmath::Vector2;
+use crate::post_process::{ draw_fullscreen, fullscreen_program, DrawCount, FULLSCREEN_VERTICES, SOURCE_TEXTURE_UNIT };
+use crate::render_target::{ FramebufferBuilder, RenderTarget, TextureFormat };
+use crate::shader_program::ShaderProgram;
+use crate::texture::bind_texture_unit;
+use crate::debug_output::DebugGroup;
+
+// Must match the size of the BloomLevels array in the resolve shader
+pub const MAX_BLOOM_LEVELS: usize = 6;
+
+// Operator that maps HDR colors to [0, 1], the values match the resolve shader
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tonemap {
+  // colors above 1 are clipped
+  None = 0,
+  Reinhard = 1,
+  Aces = 2
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+  // colors with a channel brighter than the threshold bloom
+  pub threshold: GLfloat,
+  // how much of the blurred bright parts is mixed into the scene
+  pub intensity: GLfloat,
+  // each level has half the resolution of the previous one, the first has half the screen resolution
+  pub levels: usize
+}
+
+impl Default for BloomSettings {
+  fn default() -> Self {
+    BloomSettings { threshold: 1.0, intensity: 0.04, levels: 5 }
+  }
+}
+
+// The scene is drawn into a floating point target and tonemapped before the post-process passes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HdrSettings {
+  pub exposure: GLfloat,
+  pub tonemap: Tonemap,
+  pub bloom: Option<BloomSettings>
+}
+
+impl Default for HdrSettings {
+  fn default() -> Self {
+    HdrSettings { exposure: 1.0, tonemap: Tonemap::Aces, bloom: None }
+  }
+}
+
+impl HdrSettings {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_exposure(mut self, exposure: GLfloat) -> Self {
+    self.exposure = exposure;
+    self
+  }
+
+  pub fn with_tonemap(mut self, tonemap: Tonemap) -> Self {
+    self.tonemap = tonemap;
+    self
+  }
+
+  pub fn with_bloom(mut self, bloom: BloomSettings) -> Self {
+    self.bloom = Some(bloom);
+    self
+  }
+}
+
+// Sizes of the bloom levels, halving from half the screen size and never smaller than a texel
+fn bloom_level_sizes(width: GLsizei, height: GLsizei, levels: usize) -> Vec<(GLsizei, GLsizei)> {
+  (1..=levels.min(MAX_BLOOM_LEVELS))
+    .map(|level| ((width >> level).max(1), (height >> level).max(1)))
+    .collect()
+}
+
+// A bloom level is blurred horizontally from blurred into scratch and vertically back into blurred
+struct BloomLevel {
+  blurred: RenderTarget,
+  scratch: RenderTarget
+}
+
+fn build_bloom_levels(width: GLsizei, height: GLsizei, levels: usize) -> Result<Vec<BloomLevel>, String> {
+  let mut bloom_levels = Vec::new();
+  for (level_width, level_height) in bloom_level_sizes(width, height, levels) {
+    let target = || FramebufferBuilder::new(level_width, level_height).with_color_attachment(TextureFormat::Rgba16F).build();
+    bloom_levels.push(BloomLevel { blurred: target()?, scratch: target()? });
+  }
+  Ok(bloom_levels)
+}
+
+pub struct HdrStage {
+  pub settings: HdrSettings,
+  bright_pass: ShaderProgram,
+  blur: ShaderProgram,
+  resolve: ShaderProgram,
+  bloom_levels: Vec<BloomLevel>
+}
+
+impl HdrStage {
+  pub fn new(width: GLsizei, height: GLsizei, settings: HdrSettings) -> Result<HdrStage, String> {
+    let levels = settings.bloom.map_or(0, |bloom| bloom.levels);
+    Ok(HdrStage {
+      settings,
+      bright_pass: fullscreen_program(include_str!("glsl/hdr/bright_pass.glsl")),
+      blur: fullscreen_program(include_str!("glsl/hdr/blur.glsl")),
+      resolve: fullscreen_program(include_str!("glsl/hdr/resolve.glsl")),
+      bloom_levels: build_bloom_levels(width, height, levels)?
+    })
+  }
+
+  // The format of the target the scene is drawn into
+  pub fn scene_format() -> TextureFormat {
+    TextureFormat::Rgba16F
+  }
+
+  pub fn resize(&mut self, width: GLsizei, height: GLsizei) -> Result<(), String> {
+    let sizes = bloom_level_sizes(width, height, self.bloom_levels.len());
+    for (level, (level_width, level_height)) in self.bloom_levels.iter_mut().zip(sizes) {
+      level.blurred.resize(level_width, level_height)?;
+      level.scratch.resize(level_width, level_height)?;
+    }
+    Ok(())
+  }
+
+  /// Runs the bloom chain on the scene and resolves it into the currently bound framebuffer,
+  /// bind_output is called once the bloom levels are done. Expects a bound vertex array, returns the draws it took.
+  ///
+  /// # Safety
+  /// Needs the current GL context the chain was created in and a bound vertex array for the full-screen triangle.
+  pub unsafe fn apply<F: FnOnce()>(&self, scene: &RenderTarget, bind_output: F) -> DrawCount {
+    let _group = DebugGroup::new("HDR");
+    let mut draws = DrawCount::default();
+    if let Some(bloom) = self.settings.bloom {
+      self.draw_bloom(scene, bloom, &mut draws);
+    }
+    bind_output();
+    gl::UseProgram(self.resolve.handle());
+    for (i, level) in self.bloom_levels.iter().enumerate() {
+      let unit = SOURCE_TEXTURE_UNIT + 1 + i as GLuint;
+      bind_texture_unit(unit, level.blurred.color_texture(0).unwrap_or(0));
+      self.resolve.set_uniform_if_present(&format!("BloomLevels[{}]", i), unit as GLint);
+    }
+    let bloom_intensity = self.settings.bloom.map_or(0.0, |bloom| bloom.intensity);
+    self.resolve.set_uniform_if_present("BloomLevelCount", self.bloom_levels.len() as GLint);
+    self.resolve.set_uniform_if_present("BloomIntensity", bloom_intensity);
+    self.resolve.set_uniform_if_present("Exposure", self.settings.exposure);
+    self.resolve.set_uniform_if_present("Tonemap", self.settings.tonemap as GLint);
+    draw_fullscreen(&self.resolve, scene);
+    draws.add(FULLSCREEN_VERTICES);
+    gl_check!("HdrStage::apply");
+    draws
+  }
+
+  // Bright pass into the first level, each further level downsamples the previous one;
+  // every level is blurred in place with a horizontal and a vertical pass
+  unsafe fn draw_bloom(&self, scene: &RenderTarget, bloom: BloomSettings, draws: &mut DrawCount) {
+    let mut source = scene;
+    for (i, level) in self.bloom_levels.iter().enumerate() {
+      level.blurred.bind();
+      gl::UseProgram(self.bright_pass.handle());
+      // the later levels only downsample
+      let threshold = if i == 0 { bloom.threshold } else { 0.0 };
+      self.bright_pass.set_uniform_if_present("Threshold", threshold);
+      draw_fullscreen(&self.bright_pass, source);
+
+      let texel = Vector2::new(1.0 / level.blurred.width as GLfloat, 1.0 / level.blurred.height as GLfloat);
+      gl::UseProgram(self.blur.handle());
+      level.scratch.bind();
+      self.blur.set_uniform_if_present("Direction", Vector2::new(texel.x, 0.0));
+      draw_fullscreen(&self.blur, &level.blurred);
+      level.blurred.bind();
+      self.blur.set_uniform_if_present("Direction", Vector2::new(0.0, texel.y));
+      draw_fullscreen(&self.blur, &level.scratch);
+      // bright pass and the two blur directions
+      for _ in 0..3 {
+        draws.add(FULLSCREEN_VERTICES);
+      }
+      source = &level.blurred;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bloom_levels_halve_from_half_resolution() {
+    let sizes = bloom_level_sizes(1600, 900, 4);
+    assert_eq!(vec![(800, 450), (400, 225), (200, 112), (100, 56)], sizes);
+  }
+
+  #[test]
+  fn bloom_levels_are_limited() {
+    // arrange
+    let levels = MAX_BLOOM_LEVELS + 4;
+    // act
+    let sizes = bloom_level_sizes(64, 8, levels);
+    // assert
+    assert_eq!(MAX_BLOOM_LEVELS, sizes.len());
+    assert_eq!((1, 1), sizes[MAX_BLOOM_LEVELS - 1]);
+  }
+
+  #[test]
+  fn settings_default_to_aces_without_bloom() {
+    let settings = HdrSettings::new().with_exposure(1.5).with_bloom(BloomSettings::default());
+    assert_eq!(Tonemap::Aces, settings.tonemap);
+    assert_eq!(1.5, settings.exposure);
+    assert_eq!(Some(5), settings.bloom.map(|bloom| bloom.levels));
+    assert_eq!(None, HdrSettings::default().bloom);
+  }
+}
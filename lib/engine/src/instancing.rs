@@ -0,0 +1,167 @@
+use std::mem::size_of;
+use std::ptr;
+use gl::types::*;
+use cgmath::Matrix4;
+use crate::shader_program::ShaderProgram;
+use crate::vao_builder::setup_attribute;
+use crate::vao_builder::attrib_data::AttribData;
+use crate::vao_builder::attrib_parameters::AttribParameters;
+
+// Vertex shaders that declare this mat4 input take the model matrix per instance instead of the Model uniform:
+//   layout (location = 2) in mat4 InstanceModel;
+// The location follows the vertex attributes, see VaoBuilder::with_instance_matrix.
+pub const INSTANCE_MODEL_ATTRIBUTE: &str = "InstanceModel";
+
+/// # Safety
+/// Needs the current GL context the program was linked in.
+pub unsafe fn is_instanced(program: &ShaderProgram) -> bool {
+  program.has_attribute(INSTANCE_MODEL_ATTRIBUTE)
+}
+
+/// The location of the first column of the InstanceModel input, None for programs without one
+///
+/// # Safety
+/// Needs the current GL context the program was linked in.
+pub unsafe fn instance_model_location(program: &ShaderProgram) -> Option<GLuint> {
+  program.attribute_location(INSTANCE_MODEL_ATTRIBUTE)
+}
+
+// The matrices column by column, as the instance matrix attributes expect them
+pub fn instance_data(model_matrices: &[Matrix4<GLfloat>]) -> Vec<GLfloat> {
+  model_matrices.iter().flat_map(|matrix| {
+    let columns: &[GLfloat; 16] = matrix.as_ref();
+    columns.iter().cloned()
+  }).collect()
+}
+
+// The buffer is orphaned every frame so the upload does not wait for the previous draw
+unsafe fn upload_instances(instance_vbo: GLuint, model_matrices: &[Matrix4<GLfloat>]) {
+  let data = instance_data(model_matrices);
+  gl::BindBuffer(gl::ARRAY_BUFFER, instance_vbo);
+  gl::BufferData(gl::ARRAY_BUFFER, (data.len() * size_of::<GLfloat>()) as GLsizeiptr, data.as_ptr() as *const GLvoid, gl::STREAM_DRAW);
+  gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+}
+
+/// Uploads one model matrix per instance and draws the vertex array once per matrix
+///
+/// # Safety
+/// A program has to be in use, vao and instance_vbo have to be live objects of the current GL context.
+pub unsafe fn draw_arrays_instanced(vao: GLuint, instance_vbo: GLuint, mode: GLenum, vertex_count: GLsizei, model_matrices: &[Matrix4<GLfloat>]) {
+  upload_instances(instance_vbo, model_matrices);
+  gl::BindVertexArray(vao);
+  gl::DrawArraysInstanced(mode, 0, vertex_count, model_matrices.len() as GLsizei);
+  gl_check!("instancing::draw_arrays_instanced");
+}
+
+/// Like draw_arrays_instanced for a vertex array with an index buffer of 32 bit indices
+///
+/// # Safety
+/// A program has to be in use, vao and instance_vbo have to be live objects of the current GL context.
+pub unsafe fn draw_elements_instanced(vao: GLuint, instance_vbo: GLuint, mode: GLenum, index_count: GLsizei, model_matrices: &[Matrix4<GLfloat>]) {
+  upload_instances(instance_vbo, model_matrices);
+  gl::BindVertexArray(vao);
+  gl::DrawElementsInstanced(mode, index_count, gl::UNSIGNED_INT, ptr::null(), model_matrices.len() as GLsizei);
+  gl_check!("instancing::draw_elements_instanced");
+}
+
+// An instance buffer that is not part of any vertex array, for meshes that were built without one.
+// attach points a vertex array's instance matrix at it, so that any mesh can be drawn instanced.
+pub struct InstanceBuffer {
+  handle: GLuint
+}
+
+impl InstanceBuffer {
+  pub fn new() -> InstanceBuffer {
+    let mut handle: GLuint = 0;
+    unsafe { gl::GenBuffers(1, &mut handle); }
+    InstanceBuffer { handle }
+  }
+
+  pub fn handle(&self) -> GLuint {
+    self.handle
+  }
+
+  /// Makes the vertex array read a mat4 per instance from this buffer, at location and the three after it
+  ///
+  /// # Safety
+  /// vao has to be a live vertex array of the current GL context the buffer was created in.
+  pub unsafe fn attach(&self, vao: GLuint, location: GLuint) {
+    gl::BindVertexArray(vao);
+    gl::BindBuffer(gl::ARRAY_BUFFER, self.handle);
+    for column in 0..4 {
+      setup_attribute(AttribData { location: location + column as GLuint, params: AttribParameters::floats(4, 16, column * 4).per_instance(1) });
+    }
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    gl::BindVertexArray(0);
+  }
+}
+
+impl Default for InstanceBuffer {
+  fn default() -> Self {
+    InstanceBuffer::new()
+  }
+}
+
+impl Drop for InstanceBuffer {
+  fn drop(&mut self) {
+    unsafe { gl::DeleteBuffers(1, &self.handle); }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::Vector3;
+  use crate::gl_mock;
+
+  #[test]
+  fn instance_data_is_column_major() {
+    // arrange
+    let translation = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0));
+    // act
+    let data = instance_data(&[Matrix4::from_scale(2.0), translation]);
+    // assert
+    assert_eq!(32, data.len());
+    assert_eq!(2.0, data[0]);
+    assert_eq!(&[1.0, 2.0, 3.0, 1.0], &data[28..32]);
+  }
+
+  #[test]
+  fn draws_every_instance_with_one_call() {
+    // arrange
+    gl_mock::install();
+    let model_matrices = vec![Matrix4::from_scale(1.0); 3];
+    // act
+    unsafe { draw_arrays_instanced(1, 2, gl::TRIANGLES, 6, &model_matrices); }
+    // assert
+    assert_eq!(vec![gl::ARRAY_BUFFER as i64, 3 * 64, gl::STREAM_DRAW as i64], gl_mock::calls_to("glBufferData")[0].args);
+    assert_eq!(vec![gl::TRIANGLES as i64, 0, 6, 3], gl_mock::calls_to("glDrawArraysInstanced")[0].args);
+  }
+
+  #[test]
+  fn indexed_meshes_are_drawn_with_their_elements() {
+    // arrange
+    gl_mock::install();
+    // act
+    unsafe { draw_elements_instanced(1, 2, gl::TRIANGLES, 6, &[Matrix4::from_scale(1.0); 2]); }
+    // assert
+    assert_eq!(vec![gl::TRIANGLES as i64, 6, gl::UNSIGNED_INT as i64, 0, 2], gl_mock::calls_to("glDrawElementsInstanced")[0].args);
+  }
+
+  #[test]
+  fn attached_buffer_feeds_the_instance_matrix() {
+    // arrange
+    gl_mock::install();
+    let buffer = InstanceBuffer::new();
+    // act
+    unsafe { buffer.attach(3, 2); }
+    // assert: four columns of four floats, 64 bytes per instance
+    let pointers: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribPointer").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![2, 4, gl::FLOAT as i64, 0, 64, 0], pointers[0]);
+    assert_eq!(vec![5, 4, gl::FLOAT as i64, 0, 64, 48], pointers[3]);
+    assert_eq!(4, gl_mock::count("glVertexAttribDivisor"));
+    assert_eq!(vec![gl::ARRAY_BUFFER as i64, buffer.handle() as i64], gl_mock::calls_to("glBindBuffer")[0].args);
+    drop(buffer);
+    assert_eq!(0, gl_mock::live_count("buffer"));
+  }
+}
@@ -1,7 +1,37 @@
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+pub mod debug_output;
+
 pub mod vao_builder;
 pub mod camera;
 pub mod shader_program;
 pub mod ecs;
+pub mod material;
+pub mod texture;
+pub mod light;
+pub mod mesh;
+pub mod shadow;
+pub mod render_target;
+pub mod render_state;
+pub mod post_process;
+pub mod hdr;
+pub mod deferred;
+pub mod culling;
+pub mod instancing;
+pub mod dynamic_buffer;
+pub mod uniform_buffer;
+pub mod vertex;
+pub mod logging;
+pub mod profiler;
+pub mod font;
+pub mod text;
+pub mod debug_draw;
+pub mod sprite;
+
+#[cfg(test)]
+mod gl_mock;
 
 #[cfg(test)]
 mod tests {
@@ -9,7 +39,7 @@ mod tests {
 
     #[test]
     fn can_create_vao_builder() {
-        let builder = VaoBuilder::new();
+        let _builder = VaoBuilder::new();
         assert_eq!(2 + 2, 4);
     }
 }
@@ -0,0 +1,189 @@
+use gl::types::*;
+use cgmath::{ InnerSpace, Point3, Vector3 };
+use crate::shader_program::ShaderProgram;
+use crate::shadow::MAX_SHADOW_MAPS;
+
+// These must match the array sizes in the lit shaders
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+  // direction in which the light travels
+  pub direction: Vector3<GLfloat>,
+  pub color: Vector3<GLfloat>,
+  pub intensity: GLfloat,
+  pub casts_shadows: bool
+}
+
+impl DirectionalLight {
+  pub fn new(direction: Vector3<GLfloat>, color: Vector3<GLfloat>, intensity: GLfloat) -> Self {
+    DirectionalLight { direction: direction.normalize(), color, intensity, casts_shadows: false }
+  }
+
+  pub fn with_shadows(mut self) -> Self {
+    self.casts_shadows = true;
+    self
+  }
+
+  pub fn radiance(&self) -> Vector3<GLfloat> {
+    self.color * self.intensity
+  }
+}
+
+// Attenuation factor is 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+  pub constant: GLfloat,
+  pub linear: GLfloat,
+  pub quadratic: GLfloat
+}
+
+impl Attenuation {
+  // Falls off to roughly 1% of the intensity at the given range
+  pub fn for_range(range: GLfloat) -> Self {
+    Attenuation {
+      constant: 1.0,
+      linear: 4.5 / range,
+      quadratic: 75.0 / (range * range)
+    }
+  }
+
+  pub fn factor(&self, distance: GLfloat) -> GLfloat {
+    1.0 / (self.constant + self.linear * distance + self.quadratic * distance * distance)
+  }
+
+  // Distance at which the factor drops to the cutoff, infinite when it never does
+  pub fn range(&self, cutoff: GLfloat) -> GLfloat {
+    // solve quadratic * d^2 + linear * d + constant - 1 / cutoff = 0 for the positive root
+    let c = self.constant - 1.0 / cutoff;
+    if c >= 0.0 { return 0.0; }
+    if self.quadratic > 0.0 {
+      (-self.linear + (self.linear * self.linear - 4.0 * self.quadratic * c).sqrt()) / (2.0 * self.quadratic)
+    } else if self.linear > 0.0 {
+      -c / self.linear
+    } else {
+      GLfloat::INFINITY
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+  pub position: Point3<GLfloat>,
+  pub color: Vector3<GLfloat>,
+  pub intensity: GLfloat,
+  pub attenuation: Attenuation
+}
+
+impl PointLight {
+  pub fn new(position: Point3<GLfloat>, color: Vector3<GLfloat>, intensity: GLfloat, range: GLfloat) -> Self {
+    PointLight { position, color, intensity, attenuation: Attenuation::for_range(range) }
+  }
+
+  pub fn radiance(&self) -> Vector3<GLfloat> {
+    self.color * self.intensity
+  }
+
+  // Radius of the sphere outside of which the light adds less than 1/256 to any color channel
+  pub fn volume_radius(&self) -> GLfloat {
+    let radiance = self.radiance();
+    let brightest = radiance.x.max(radiance.y).max(radiance.z);
+    if brightest <= 0.0 { return 0.0; }
+    self.attenuation.range(1.0 / (256.0 * brightest))
+  }
+}
+
+// Indices of the directional lights that get a shadow map; shadow map n belongs to the n-th entry
+pub fn shadow_casters(directional_lights: &[DirectionalLight]) -> Vec<usize> {
+  directional_lights.iter()
+    .take(MAX_DIRECTIONAL_LIGHTS)
+    .enumerate()
+    .filter(|(_, light)| light.casts_shadows)
+    .map(|(i, _)| i)
+    .take(MAX_SHADOW_MAPS)
+    .collect()
+}
+
+/// Uploads the lights to the DirectionalLights/PointLights uniform arrays of the program.
+/// Lights beyond MAX_DIRECTIONAL_LIGHTS and MAX_POINT_LIGHTS are ignored.
+///
+/// # Safety
+/// The program has to be in use in the current GL context.
+pub unsafe fn upload_lights(program: &ShaderProgram, directional_lights: &[DirectionalLight], point_lights: &[PointLight]) {
+  let directional_count = directional_lights.len().min(MAX_DIRECTIONAL_LIGHTS);
+  let shadow_casters = shadow_casters(directional_lights);
+  for (i, light) in directional_lights.iter().take(directional_count).enumerate() {
+    let shadow_map = shadow_casters.iter().position(|caster| *caster == i).map_or(-1, |n| n as GLint);
+    program.set_uniform_if_present(&format!("DirectionalLights[{}].Direction", i), light.direction);
+    program.set_uniform_if_present(&format!("DirectionalLights[{}].Color", i), light.radiance());
+    program.set_uniform_if_present(&format!("DirectionalLights[{}].ShadowMap", i), shadow_map);
+  }
+  program.set_uniform_if_present("DirectionalLightCount", directional_count as GLint);
+
+  let point_count = point_lights.len().min(MAX_POINT_LIGHTS);
+  for (i, light) in point_lights.iter().take(point_count).enumerate() {
+    let attenuation = Vector3::new(light.attenuation.constant, light.attenuation.linear, light.attenuation.quadratic);
+    program.set_uniform_if_present(&format!("PointLights[{}].Position", i), Vector3::new(light.position.x, light.position.y, light.position.z));
+    program.set_uniform_if_present(&format!("PointLights[{}].Color", i), light.radiance());
+    program.set_uniform_if_present(&format!("PointLights[{}].Attenuation", i), attenuation);
+  }
+  program.set_uniform_if_present("PointLightCount", point_count as GLint);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn attenuation_is_one_at_the_light() {
+    let attenuation = Attenuation::for_range(10.0);
+    assert_eq!(1.0, attenuation.factor(0.0));
+  }
+
+  #[test]
+  fn attenuation_is_about_one_percent_at_range() {
+    let attenuation = Attenuation::for_range(10.0);
+    let factor = attenuation.factor(10.0);
+    assert!(factor > 0.005 && factor < 0.02, "factor was {}", factor);
+  }
+
+  #[test]
+  fn range_inverts_factor() {
+    // arrange
+    let attenuation = Attenuation::for_range(10.0);
+    let cutoff = attenuation.factor(10.0);
+    // act
+    let range = attenuation.range(cutoff);
+    // assert
+    assert!((range - 10.0).abs() < 1e-3, "range was {}", range);
+    assert_eq!(GLfloat::INFINITY, Attenuation { constant: 1.0, linear: 0.0, quadratic: 0.0 }.range(0.5));
+  }
+
+  #[test]
+  fn brighter_point_lights_reach_further() {
+    let dim = PointLight::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 0.5, 5.0);
+    let bright = PointLight { intensity: 4.0, ..dim };
+    assert!(bright.volume_radius() > dim.volume_radius());
+    assert!(dim.volume_radius() > 5.0);
+  }
+
+  #[test]
+  fn directional_light_direction_is_normalized() {
+    let light = DirectionalLight::new(Vector3::new(0.0, -2.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 0.5);
+    assert_eq!(Vector3::new(0.0, -1.0, 0.0), light.direction);
+    assert_eq!(Vector3::new(0.5, 0.5, 0.5), light.radiance());
+  }
+
+  #[test]
+  fn shadow_casters_are_limited_to_max_shadow_maps() {
+    // arrange
+    let light = DirectionalLight::new(Vector3::new(0.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 1.0);
+    let lights = [light, light.with_shadows(), light.with_shadows(), light.with_shadows()];
+    // act
+    let casters = shadow_casters(&lights);
+    // assert
+    assert_eq!(vec![1, 2], casters);
+    assert_eq!(MAX_SHADOW_MAPS, casters.len());
+  }
+}
@@ -0,0 +1,28 @@
+use log::{ Log, Metadata, Record };
+pub use log::LevelFilter;
+
+// Writes records to stderr as "[LEVEL target] message"
+struct StderrLogger;
+
+impl Log for StderrLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &Record) {
+    if self.enabled(record.metadata()) {
+      eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// Installs the stderr logger, keeps the logger that is already installed if there is one
+pub fn init(max_level: LevelFilter) {
+  if log::set_logger(&LOGGER).is_ok() {
+    log::set_max_level(max_level);
+  }
+}
@@ -0,0 +1,210 @@
+use gl::types::*;
+use cgmath::{ Matrix4, Vector2, Vector3, Vector4 };
+use crate::shader_program::ShaderProgram;
+use crate::texture::{ Texture, bind_texture_unit };
+pub use crate::render_state::BlendMode;
+use crate::render_state::RenderState;
+
+// Index of a shader program in the list of programs owned by the game state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ProgramId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UniformValue {
+  Float(GLfloat),
+  Int(GLint),
+  Vec2(Vector2<GLfloat>),
+  Vec3(Vector3<GLfloat>),
+  Vec4(Vector4<GLfloat>),
+  Mat4(Matrix4<GLfloat>),
+  // a 2D texture bound to the given texture unit, the sampler uniform is set to that unit
+  Texture2D { unit: GLuint, texture: GLuint }
+}
+
+// Named uniform values that are uploaded to a program, used by materials and post-process passes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UniformValues {
+  values: Vec<(String, UniformValue)>
+}
+
+impl UniformValues {
+  pub fn new() -> Self {
+    UniformValues { values: Vec::new() }
+  }
+
+  // Sets a uniform value, replacing the previous value of a uniform with the same name
+  pub fn set(&mut self, name: &str, value: UniformValue) {
+    match self.values.iter_mut().find(|(n, _)| n == name) {
+      Some(entry) => entry.1 = value,
+      None => self.values.push((name.to_string(), value))
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&UniformValue> {
+    self.values.iter().find(|(n, _)| n == name).map(|(_, value)| value)
+  }
+
+  pub fn as_slice(&self) -> &[(String, UniformValue)] {
+    &self.values
+  }
+
+  /// Uploads the uniform values; the program is expected to be in use. Values of uniforms the program does
+  /// not have, e.g. because the compiler optimized them away, are skipped.
+  ///
+  /// # Safety
+  /// The program has to be in use in the current GL context, texture handles have to be live textures of it.
+  pub unsafe fn apply(&self, program: &ShaderProgram) {
+    for (name, value) in &self.values {
+      match *value {
+        UniformValue::Float(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Int(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Vec2(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Vec3(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Vec4(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Mat4(v) => program.set_uniform_if_present(name, v),
+        UniformValue::Texture2D { unit, texture } => if program.has_uniform(name) {
+          bind_texture_unit(unit, texture);
+          program.set_uniform_if_present(name, unit as GLint);
+        }
+      }
+    }
+  }
+}
+
+// Material
+
+// A material references one of the shader programs and carries its own uniform values,
+// which are uploaded every time an entity with this material is drawn.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Material {
+  pub program: ProgramId,
+  pub render_state: RenderState,
+  uniforms: UniformValues
+}
+
+impl Material {
+  pub fn new(program: ProgramId) -> Self {
+    Material {
+      program,
+      render_state: RenderState::default(),
+      uniforms: UniformValues::new()
+    }
+  }
+
+  // Sets a uniform value, replacing the previous value of a uniform with the same name
+  pub fn with_uniform(mut self, name: &str, value: UniformValue) -> Self {
+    self.set_uniform(name, value);
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_render_state(mut self, render_state: RenderState) -> Self {
+    self.render_state = render_state;
+    self
+  }
+
+  // See RenderState::with_blend
+  #[allow(dead_code)]
+  pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+    self.render_state = self.render_state.with_blend(blend_mode);
+    self
+  }
+
+  pub fn blend_mode(&self) -> BlendMode {
+    self.render_state.blend
+  }
+
+  #[allow(dead_code)]
+  pub fn with_float(self, name: &str, value: GLfloat) -> Self {
+    self.with_uniform(name, UniformValue::Float(value))
+  }
+
+  #[allow(dead_code)]
+  pub fn with_color(self, name: &str, rgba: [GLfloat; 4]) -> Self {
+    self.with_uniform(name, UniformValue::Vec4(Vector4::new(rgba[0], rgba[1], rgba[2], rgba[3])))
+  }
+
+  // Samples the texture through the given texture unit
+  #[allow(dead_code)]
+  pub fn with_texture(self, name: &str, unit: GLuint, texture: &Texture) -> Self {
+    self.with_uniform(name, UniformValue::Texture2D { unit, texture: texture.handle })
+  }
+
+  pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+    self.uniforms.set(name, value);
+  }
+
+  pub fn get_uniform(&self, name: &str) -> Option<&UniformValue> {
+    self.uniforms.get(name)
+  }
+
+  pub fn uniforms(&self) -> &[(String, UniformValue)] {
+    self.uniforms.as_slice()
+  }
+
+  /// Uploads the uniform values; the program is expected to be in use.
+  ///
+  /// # Safety
+  /// The program has to be in use in the current GL context, texture handles have to be live textures of it.
+  pub unsafe fn apply(&self, program: &ShaderProgram) {
+    self.uniforms.apply(program);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+  use crate::shader_program::ShaderProgramBuilder;
+
+  #[test]
+  fn with_uniform_replaces_value_with_same_name() {
+    // arrange
+    let material = Material::new(ProgramId(1))
+      .with_float("Size", 0.1)
+      .with_color("Tint", [1.0, 0.0, 0.0, 1.0]);
+    // act
+    let material = material.with_float("Size", 0.5);
+    // assert
+    assert_eq!(ProgramId(1), material.program);
+    assert_eq!(2, material.uniforms().len());
+    assert_eq!(Some(&UniformValue::Float(0.5)), material.get_uniform("Size"));
+    assert_eq!(Some(&UniformValue::Vec4(Vector4::new(1.0, 0.0, 0.0, 1.0))), material.get_uniform("Tint"));
+  }
+
+  #[test]
+  fn only_opaque_materials_disable_blending() {
+    // arrange
+    let material = Material::new(ProgramId(0));
+    // act
+    let transparent = material.clone().with_blend_mode(BlendMode::Premultiplied);
+    // assert
+    assert!(!material.blend_mode().is_transparent());
+    assert_eq!(None, material.blend_mode().blend_factors());
+    assert!(transparent.blend_mode().is_transparent());
+    assert!(!transparent.render_state.depth_write);
+    assert_eq!(Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)), transparent.blend_mode().blend_factors());
+    assert_eq!(Some((gl::SRC_ALPHA, gl::ONE)), BlendMode::Additive.blend_factors());
+  }
+
+  #[test]
+  fn unknown_uniform_is_none() {
+    let material = Material::new(ProgramId(0));
+    assert!(material.get_uniform("Size").is_none());
+  }
+
+  #[test]
+  fn uniforms_missing_from_the_program_are_skipped() {
+    // arrange
+    gl_mock::install();
+    let program = ShaderProgramBuilder::new().build();
+    let material = Material::new(ProgramId(0))
+      .with_float("Size", 0.1)
+      .with_texture("Diffuse", 2, &Texture { handle: 7, width: 1, height: 1 });
+    // act
+    unsafe { material.apply(&program) };
+    // assert
+    assert_eq!(2, gl_mock::count("glGetUniformLocation"));
+    assert_eq!(0, gl_mock::count("glBindTexture"));
+  }
+}
@@ -0,0 +1,144 @@
+use gl::types::*;
+use cgmath::{ InnerSpace, Vector3 };
+
+// Smooth per-vertex normals for an indexed triangle list: the area weighted average
+// of the normals of all triangles that share the vertex.
+pub fn smooth_normals(positions: &[[GLfloat; 3]], indices: &[u32]) -> Vec<[GLfloat; 3]> {
+  let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+  for triangle in indices.chunks(3) {
+    if triangle.len() < 3 { break; }
+    let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+    let pa = Vector3::from(positions[a]);
+    let pb = Vector3::from(positions[b]);
+    let pc = Vector3::from(positions[c]);
+    // the length of the cross product is twice the triangle area
+    let face_normal = (pb - pa).cross(pc - pa);
+    normals[a] += face_normal;
+    normals[b] += face_normal;
+    normals[c] += face_normal;
+  }
+  normals.into_iter()
+    .map(|n| if n.magnitude2() > 0.0 { n.normalize().into() } else { [0.0, 0.0, 0.0] })
+    .collect()
+}
+
+// Unrolls an indexed mesh into a flat vertex list with interleaved position and normal
+pub fn interleave_position_normal(positions: &[[GLfloat; 3]], normals: &[[GLfloat; 3]], indices: &[u32]) -> Vec<GLfloat> {
+  let mut vertices = Vec::with_capacity(indices.len() * 6);
+  for index in indices {
+    vertices.extend_from_slice(&positions[*index as usize]);
+    vertices.extend_from_slice(&normals[*index as usize]);
+  }
+  vertices
+}
+
+// Triangle list of a unit sphere with the given number of segments around the y axis and rings from pole to pole.
+// The vertices lie on the sphere, so the faces are slightly inside of it.
+pub fn uv_sphere(segments: usize, rings: usize) -> Vec<[GLfloat; 3]> {
+  let point = |segment: usize, ring: usize| {
+    let theta = std::f32::consts::PI * ring as GLfloat / rings as GLfloat;
+    let phi = 2.0 * std::f32::consts::PI * segment as GLfloat / segments as GLfloat;
+    [theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()]
+  };
+  let mut positions = Vec::with_capacity(segments * rings * 6);
+  for ring in 0..rings {
+    for segment in 0..segments {
+      let (top_left, top_right) = (point(segment, ring), point(segment + 1, ring));
+      let (bottom_left, bottom_right) = (point(segment, ring + 1), point(segment + 1, ring + 1));
+      // counter clockwise seen from outside
+      if ring > 0 {
+        positions.extend_from_slice(&[top_left, top_right, bottom_left]);
+      }
+      if ring + 1 < rings {
+        positions.extend_from_slice(&[top_right, bottom_right, bottom_left]);
+      }
+    }
+  }
+  positions
+}
+
+// Two triangles of a square on the xz plane at height y, half_size from the center to its edges,
+// with interleaved position and normal. Faces up, counter clockwise seen from above.
+pub fn ground_plane(half_size: GLfloat, y: GLfloat) -> Vec<GLfloat> {
+  let s = half_size;
+  vec![
+    // X    Y   Z       NX   NY   NZ
+    -s, y, -s,    0.0, 1.0, 0.0,
+     s, y,  s,    0.0, 1.0, 0.0,
+     s, y, -s,    0.0, 1.0, 0.0,
+    -s, y, -s,    0.0, 1.0, 0.0,
+    -s, y,  s,    0.0, 1.0, 0.0,
+     s, y,  s,    0.0, 1.0, 0.0,
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normals_of_a_quad_in_the_xy_plane_point_to_z() {
+    // arrange
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+    let indices = [0, 1, 2, 0, 2, 3];
+    // act
+    let normals = smooth_normals(&positions, &indices);
+    // assert
+    for normal in normals {
+      assert_eq!([0.0, 0.0, 1.0], normal);
+    }
+  }
+
+  #[test]
+  fn shared_vertex_averages_face_normals() {
+    // arrange: two triangles folded at a right angle along the x axis
+    let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    let indices = [0, 1, 2, 0, 3, 1];
+    // act
+    let normals = smooth_normals(&positions, &indices);
+    // assert
+    let expected = Vector3::new(0.0, 1.0, 1.0).normalize();
+    let actual = Vector3::from(normals[0]);
+    assert!((actual - expected).magnitude() < 1e-6);
+  }
+
+  #[test]
+  fn uv_sphere_vertices_are_on_the_unit_sphere() {
+    // act
+    let positions = uv_sphere(8, 4);
+    // assert: the pole rings have one triangle per segment, the others two
+    assert_eq!((8 + 8 + 2 * 8 * 2) * 3, positions.len());
+    for position in positions.iter() {
+      assert!((Vector3::from(*position).magnitude() - 1.0).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn uv_sphere_faces_point_outward() {
+    let positions = uv_sphere(8, 4);
+    for triangle in positions.chunks(3) {
+      let (a, b, c) = (Vector3::from(triangle[0]), Vector3::from(triangle[1]), Vector3::from(triangle[2]));
+      let normal = (b - a).cross(c - a);
+      assert!(normal.dot(a + b + c) > 0.0);
+    }
+  }
+
+  #[test]
+  fn ground_plane_faces_up() {
+    let vertices = ground_plane(1.5, -0.35);
+    for triangle in vertices.chunks(18) {
+      let corner = |i: usize| Vector3::new(triangle[i * 6], triangle[i * 6 + 1], triangle[i * 6 + 2]);
+      let normal = (corner(1) - corner(0)).cross(corner(2) - corner(0));
+      assert!(normal.y > 0.0);
+      assert_eq!(-0.35, corner(2).y);
+    }
+  }
+
+  #[test]
+  fn interleave_unrolls_indices() {
+    let positions = [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+    let normals = [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0]];
+    let vertices = interleave_position_normal(&positions, &normals, &[1, 0]);
+    assert_eq!(vec![4.0, 5.0, 6.0, 0.0, 1.0, 0.0, 1.0, 2.0, 3.0, 0.0, 0.0, 1.0], vertices);
+  }
+}
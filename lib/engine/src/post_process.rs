@@ -0,0 +1,296 @@
+use gl::types::*;
+use cgmath::Vector2;
+use crate::hdr::{ HdrSettings, HdrStage };
+use crate::material::{ UniformValue, UniformValues };
+use crate::render_state::{ RenderState, RenderStateCache };
+use crate::render_target::{ bind_default_framebuffer, FramebufferBuilder, RenderTarget, TextureFormat };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use crate::texture::bind_texture_unit;
+use crate::debug_output::DebugGroup;
+
+// The output of the previous pass is bound to this texture unit and the Source sampler,
+// textures in the uniforms of a pass should use the units above it.
+pub const SOURCE_TEXTURE_UNIT: GLuint = 0;
+
+// Vertices of the full-screen triangle
+pub const FULLSCREEN_VERTICES: usize = 3;
+
+// The draw calls a pass made and the vertices they submitted, for the frame statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawCount {
+  pub calls: usize,
+  pub vertices: usize
+}
+
+impl DrawCount {
+  pub fn add(&mut self, vertices: usize) {
+    self.calls += 1;
+    self.vertices += vertices;
+  }
+}
+
+// Describes a full-screen fragment shader pass. The shader receives UV from the full-screen vertex shader,
+// samples the previous pass through `uniform sampler2D Source` and may declare `uniform vec2 TexelSize`.
+#[derive(Clone, Debug)]
+pub struct PostProcessEffect {
+  pub name: String,
+  pub fragment_glsl: String,
+  pub uniforms: UniformValues
+}
+
+impl PostProcessEffect {
+  pub fn new(name: &str, fragment_glsl: &str) -> Self {
+    PostProcessEffect {
+      name: name.to_string(),
+      fragment_glsl: fragment_glsl.to_string(),
+      uniforms: UniformValues::new()
+    }
+  }
+
+  pub fn with_uniform(mut self, name: &str, value: UniformValue) -> Self {
+    self.uniforms.set(name, value);
+    self
+  }
+
+  pub fn with_float(self, name: &str, value: GLfloat) -> Self {
+    self.with_uniform(name, UniformValue::Float(value))
+  }
+}
+
+pub struct PostProcessPass {
+  pub name: String,
+  pub program: ShaderProgram,
+  pub uniforms: UniformValues
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PassInput {
+  Scene,
+  Intermediate(usize)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PassOutput {
+  Intermediate(usize),
+  Screen
+}
+
+// Where each pass reads from and writes to: the first pass reads the (tonemapped) scene, the last pass
+// writes to the window and the passes in between ping-pong between two intermediate targets.
+fn pass_chain(pass_count: usize) -> Vec<(PassInput, PassOutput)> {
+  (0..pass_count).map(|i| {
+    let input = if i == 0 { PassInput::Scene } else { PassInput::Intermediate((i - 1) % 2) };
+    let output = if i + 1 == pass_count { PassOutput::Screen } else { PassOutput::Intermediate(i % 2) };
+    (input, output)
+  }).collect()
+}
+
+fn intermediate_target_count(pass_count: usize) -> usize {
+  pass_count.saturating_sub(1).min(2)
+}
+
+pub(crate) fn fullscreen_program(fragment_glsl: &str) -> ShaderProgram {
+  ShaderProgramBuilder::new()
+    .with_vertex_shader(include_str!("glsl/fullscreen_vertex.glsl"))
+    .with_fragment_shader(fragment_glsl)
+    .build()
+}
+
+// Draws the full-screen triangle with the program in use, sampling the color of the source target
+pub(crate) unsafe fn draw_fullscreen(program: &ShaderProgram, source: &RenderTarget) {
+  bind_texture_unit(SOURCE_TEXTURE_UNIT, source.color_texture(0).unwrap_or(0));
+  program.set_uniform_if_present("Source", SOURCE_TEXTURE_UNIT as GLint);
+  program.set_uniform_if_present("TexelSize", Vector2::new(1.0 / source.width as GLfloat, 1.0 / source.height as GLfloat));
+  gl::DrawArrays(gl::TRIANGLES, 0, FULLSCREEN_VERTICES as GLsizei);
+}
+
+// Renders the scene into an offscreen target and then runs the passes in order,
+// the last pass writes to the window's framebuffer.
+// With HDR the scene target has a floating point format and is tonemapped first.
+pub struct PostProcessStack {
+  scene: RenderTarget,
+  hdr: Option<HdrStage>,
+  // the tonemapped scene, only needed with HDR and passes
+  resolved: Option<RenderTarget>,
+  intermediates: Vec<RenderTarget>,
+  passes: Vec<PostProcessPass>,
+  // core profile needs a bound vertex array even when there are no attributes
+  empty_vao: GLuint
+}
+
+impl PostProcessStack {
+  pub fn new(width: GLsizei, height: GLsizei, hdr: Option<HdrSettings>, effects: &[PostProcessEffect]) -> Result<PostProcessStack, String> {
+    let scene_format = if hdr.is_some() { HdrStage::scene_format() } else { TextureFormat::Rgba8 };
+    let scene = FramebufferBuilder::new(width, height)
+      .with_color_attachment(scene_format)
+      // same as the G-buffer so that the deferred path can blit its depth into it
+      .with_depth_attachment(TextureFormat::Depth24Stencil8)
+      .build()?;
+    let hdr = match hdr {
+      Some(settings) => Some(HdrStage::new(width, height, settings)?),
+      None => None
+    };
+    let resolved = if hdr.is_some() && !effects.is_empty() {
+      Some(FramebufferBuilder::new(width, height).with_color_attachment(TextureFormat::Rgba8).build()?)
+    } else {
+      None
+    };
+    let mut intermediates = Vec::new();
+    for _ in 0..intermediate_target_count(effects.len()) {
+      intermediates.push(FramebufferBuilder::new(width, height).with_color_attachment(TextureFormat::Rgba8).build()?);
+    }
+    let passes = effects.iter().map(|effect| PostProcessPass {
+      name: effect.name.clone(),
+      program: fullscreen_program(&effect.fragment_glsl),
+      uniforms: effect.uniforms.clone()
+    }).collect();
+    let mut empty_vao: GLuint = 0;
+    unsafe { gl::GenVertexArrays(1, &mut empty_vao); }
+    Ok(PostProcessStack { scene, hdr, resolved, intermediates, passes, empty_vao })
+  }
+
+  // The target the scene should be drawn into
+  pub fn scene_target(&self) -> &RenderTarget {
+    &self.scene
+  }
+
+  pub fn passes(&self) -> &[PostProcessPass] {
+    &self.passes
+  }
+
+  // For changing the uniforms of a pass at runtime
+  pub fn pass_mut(&mut self, name: &str) -> Option<&mut PostProcessPass> {
+    self.passes.iter_mut().find(|pass| pass.name == name)
+  }
+
+  // For changing the exposure, tonemapping and bloom intensity at runtime
+  pub fn hdr_settings_mut(&mut self) -> Option<&mut HdrSettings> {
+    self.hdr.as_mut().map(|hdr| &mut hdr.settings)
+  }
+
+  pub fn resize(&mut self, width: GLsizei, height: GLsizei) -> Result<(), String> {
+    self.scene.resize(width, height)?;
+    if let Some(hdr) = self.hdr.as_mut() {
+      hdr.resize(width, height)?;
+    }
+    for target in self.resolved.iter_mut().chain(self.intermediates.iter_mut()) {
+      target.resize(width, height)?;
+    }
+    Ok(())
+  }
+
+  /// Runs every pass, leaves the window's framebuffer bound and returns the draws it took
+  ///
+  /// # Safety
+  /// Needs the current GL context the stack was created in, the cache has to track that context.
+  pub unsafe fn apply(&self, state_cache: &mut RenderStateCache) -> DrawCount {
+    let (width, height) = (self.scene.width, self.scene.height);
+    // also keeps the scissor test from clipping the blit
+    state_cache.apply(&RenderState::new().with_depth_test(false).with_depth_write(false));
+    if self.hdr.is_none() && self.passes.is_empty() {
+      gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.scene.fbo);
+      gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+      gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+      bind_default_framebuffer(width, height);
+      return DrawCount::default();
+    }
+    gl::BindVertexArray(self.empty_vao);
+    let mut draws = DrawCount::default();
+    let mut first_source = &self.scene;
+    if let Some(hdr) = &self.hdr {
+      draws = hdr.apply(&self.scene, || match &self.resolved {
+        Some(resolved) => resolved.bind(),
+        None => bind_default_framebuffer(width, height)
+      });
+      if let Some(resolved) = &self.resolved {
+        first_source = resolved;
+      }
+    }
+    for (pass, (input, output)) in self.passes.iter().zip(pass_chain(self.passes.len())) {
+      let source = match input {
+        PassInput::Scene => first_source,
+        PassInput::Intermediate(i) => &self.intermediates[i]
+      };
+      match output {
+        PassOutput::Intermediate(i) => self.intermediates[i].bind(),
+        PassOutput::Screen => bind_default_framebuffer(width, height)
+      }
+      let _group = DebugGroup::new(&pass.name);
+      gl::UseProgram(pass.program.handle());
+      pass.uniforms.apply(&pass.program);
+      draw_fullscreen(&pass.program, source);
+      draws.add(FULLSCREEN_VERTICES);
+      gl_check!("PostProcessStack::apply");
+    }
+    gl::BindVertexArray(0);
+    draws
+  }
+}
+
+impl Drop for PostProcessStack {
+  fn drop(&mut self) {
+    if self.empty_vao != 0 {
+      unsafe { gl::DeleteVertexArrays(1, &self.empty_vao); }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn single_pass_reads_scene_and_writes_screen() {
+    assert_eq!(vec![(PassInput::Scene, PassOutput::Screen)], pass_chain(1));
+    assert_eq!(0, intermediate_target_count(1));
+  }
+
+  #[test]
+  fn passes_ping_pong_between_two_intermediate_targets() {
+    // act
+    let chain = pass_chain(4);
+    // assert
+    assert_eq!(vec![
+      (PassInput::Scene, PassOutput::Intermediate(0)),
+      (PassInput::Intermediate(0), PassOutput::Intermediate(1)),
+      (PassInput::Intermediate(1), PassOutput::Intermediate(0)),
+      (PassInput::Intermediate(0), PassOutput::Screen)
+    ], chain);
+    assert_eq!(2, intermediate_target_count(4));
+    assert_eq!(1, intermediate_target_count(2));
+  }
+
+  #[test]
+  fn every_pass_is_a_full_screen_draw() {
+    // arrange
+    gl_mock::install();
+    let effects = [PostProcessEffect::new("invert", ""), PostProcessEffect::new("vignette", "")];
+    let stack = PostProcessStack::new(64, 32, None, &effects).unwrap();
+    // act
+    let draws = unsafe { stack.apply(&mut RenderStateCache::new()) };
+    // assert
+    assert_eq!(DrawCount { calls: 2, vertices: 6 }, draws);
+    assert_eq!(2, gl_mock::count("glDrawArrays"));
+  }
+
+  #[test]
+  fn dropping_deletes_the_vertex_array() {
+    // arrange
+    gl_mock::install();
+    let stack = PostProcessStack::new(64, 32, None, &[PostProcessEffect::new("invert", "")]).unwrap();
+    // act
+    drop(stack);
+    // assert
+    assert_eq!(0, gl_mock::live_count("vertex array"));
+  }
+
+  #[test]
+  fn effect_uniforms_replace_by_name() {
+    let effect = PostProcessEffect::new("vignette", "")
+      .with_float("Strength", 0.5)
+      .with_float("Strength", 0.8);
+    assert_eq!(1, effect.uniforms.as_slice().len());
+    assert_eq!(Some(&UniformValue::Float(0.8)), effect.uniforms.get("Strength"));
+  }
+}
@@ -0,0 +1,453 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{ self, Write };
+use std::ops::Range;
+use std::time::{ Duration, Instant };
+use gl::types::*;
+
+// One timed scope of a frame, start is relative to the creation of the profiler
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeRecord {
+  pub name: &'static str,
+  // how many scopes enclose this one
+  pub depth: usize,
+  pub start: Duration,
+  pub cpu_time: Duration,
+  // the GPU timer is read a few frames later, None until then and for CPU only scopes
+  pub gpu_time: Option<Duration>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+  pub index: u64,
+  pub start: Duration,
+  pub duration: Duration,
+  pub scopes: Vec<ScopeRecord>
+}
+
+// Over the kept frames, a scope that runs several times in a frame counts with its sum
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeStats {
+  pub name: &'static str,
+  // frames the scope ran in
+  pub frames: usize,
+  pub cpu_average: Duration,
+  pub cpu_max: Duration,
+  // over the frames whose GPU times have arrived
+  pub gpu_average: Option<Duration>
+}
+
+// A GL_TIME_ELAPSED query that was ended but not read yet
+struct PendingQuery {
+  query: GLuint,
+  frame: u64,
+  scope: usize
+}
+
+struct ProfilerState {
+  current: Option<FrameRecord>,
+  // indices of the open scopes of the current frame
+  open: Vec<usize>,
+  // time elapsed queries can't nest, GPU scopes within a timed one are only timed on the CPU
+  gpu_query_active: bool,
+  frames: VecDeque<FrameRecord>,
+  next_frame: u64,
+  free_queries: Vec<GLuint>,
+  // in the order they were ended, which is the order the GPU finishes them
+  pending: VecDeque<PendingQuery>
+}
+
+// Scoped CPU and GPU timers for the frames between begin_frame and end_frame. The last history_frames
+// frames are kept for statistics and trace export. GPU times are read back without waiting for the GPU,
+// so they are filled into frames that have already ended.
+pub struct Profiler {
+  enabled: bool,
+  gpu_timing: bool,
+  history_frames: usize,
+  epoch: Instant,
+  state: RefCell<ProfilerState>
+}
+
+// Closes its scope when dropped
+pub struct ProfileScope<'a> {
+  profiler: &'a Profiler,
+  // frame index and scope index, None when nothing is recorded
+  scope: Option<(u64, usize)>,
+  gpu_query: Option<GLuint>
+}
+
+impl Drop for ProfileScope<'_> {
+  fn drop(&mut self) {
+    if let Some((frame, scope)) = self.scope {
+      self.profiler.close_scope(frame, scope, self.gpu_query);
+    }
+  }
+}
+
+impl Profiler {
+  // GPU timing needs glBeginQuery (OpenGL 3.3), without it GPU scopes are only timed on the CPU
+  pub fn new(history_frames: usize, gpu_timing: bool) -> Profiler {
+    Profiler {
+      enabled: true,
+      gpu_timing: gpu_timing && gl::BeginQuery::is_loaded(),
+      history_frames,
+      epoch: Instant::now(),
+      state: RefCell::new(ProfilerState {
+        current: None,
+        open: Vec::new(),
+        gpu_query_active: false,
+        frames: VecDeque::new(),
+        next_frame: 0,
+        free_queries: Vec::new(),
+        pending: VecDeque::new()
+      })
+    }
+  }
+
+  // Records nothing, scopes cost a branch
+  pub fn disabled() -> Profiler {
+    let mut profiler = Profiler::new(0, false);
+    profiler.enabled = false;
+    profiler
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.enabled
+  }
+
+  // Reads the GPU times that are available and starts recording a frame, ends the previous one if it is still open
+  pub fn begin_frame(&self) {
+    if !self.enabled { return; }
+    if self.gpu_timing {
+      unsafe { self.collect_gpu_times(); }
+    }
+    let mut state = self.state.borrow_mut();
+    self.finish_frame(&mut state);
+    state.current = Some(FrameRecord { index: state.next_frame, start: self.epoch.elapsed(), duration: Duration::default(), scopes: Vec::new() });
+    state.next_frame += 1;
+  }
+
+  pub fn end_frame(&self) {
+    if !self.enabled { return; }
+    self.finish_frame(&mut self.state.borrow_mut());
+  }
+
+  // Times the scope on the CPU until the returned ProfileScope is dropped
+  pub fn scope(&self, name: &'static str) -> ProfileScope<'_> {
+    self.open_scope(name, false)
+  }
+
+  // Also times the GL commands issued within the scope on the GPU, unless an enclosing scope already does
+  pub fn gpu_scope(&self, name: &'static str) -> ProfileScope<'_> {
+    self.open_scope(name, true)
+  }
+
+  // Copies of the kept frames, oldest first
+  pub fn frames(&self) -> Vec<FrameRecord> {
+    self.state.borrow().frames.iter().cloned().collect()
+  }
+
+  // Indices of the kept frames
+  pub fn frame_range(&self) -> Range<u64> {
+    let state = self.state.borrow();
+    match (state.frames.front(), state.frames.back()) {
+      (Some(first), Some(last)) => first.index..last.index + 1,
+      _ => 0..0
+    }
+  }
+
+  pub fn frame_stats(&self) -> Option<ScopeStats> {
+    let state = self.state.borrow();
+    let durations: Vec<Duration> = state.frames.iter().map(|frame| frame.duration).collect();
+    let frames = durations.len();
+    if frames == 0 { return None; }
+    Some(ScopeStats {
+      name: "Frame",
+      frames,
+      cpu_average: durations.iter().sum::<Duration>() / frames as u32,
+      cpu_max: durations.iter().max().cloned().unwrap_or_default(),
+      gpu_average: None
+    })
+  }
+
+  pub fn stats(&self, name: &str) -> Option<ScopeStats> {
+    let state = self.state.borrow();
+    let mut static_name = None;
+    let mut cpu_times = Vec::new();
+    let mut gpu_times = Vec::new();
+    for frame in &state.frames {
+      let scopes: Vec<&ScopeRecord> = frame.scopes.iter().filter(|scope| scope.name == name).collect();
+      if scopes.is_empty() { continue; }
+      static_name = Some(scopes[0].name);
+      cpu_times.push(scopes.iter().map(|scope| scope.cpu_time).sum::<Duration>());
+      let gpu_time: Vec<Duration> = scopes.iter().filter_map(|scope| scope.gpu_time).collect();
+      if !gpu_time.is_empty() {
+        gpu_times.push(gpu_time.iter().sum::<Duration>());
+      }
+    }
+    let name = static_name?;
+    Some(ScopeStats {
+      name,
+      frames: cpu_times.len(),
+      cpu_average: cpu_times.iter().sum::<Duration>() / cpu_times.len() as u32,
+      cpu_max: cpu_times.iter().max().cloned().unwrap_or_default(),
+      gpu_average: if gpu_times.is_empty() { None } else { Some(gpu_times.iter().sum::<Duration>() / gpu_times.len() as u32) }
+    })
+  }
+
+  // The frame followed by every scope, in the order the scopes first ran
+  pub fn summary(&self) -> Vec<ScopeStats> {
+    let mut names: Vec<&'static str> = Vec::new();
+    for frame in &self.state.borrow().frames {
+      for scope in &frame.scopes {
+        if !names.contains(&scope.name) { names.push(scope.name); }
+      }
+    }
+    self.frame_stats().into_iter()
+      .chain(names.iter().filter_map(|name| self.stats(name)))
+      .collect()
+  }
+
+  // Writes the kept frames within the range in Chrome's trace event format, for chrome://tracing or Perfetto.
+  // CPU scopes are on thread 1 and GPU scopes on thread 2. The GPU timers only measure durations,
+  // a GPU scope is placed at its CPU start or right after the previous GPU scope, whichever is later.
+  pub fn write_chrome_trace<W: Write>(&self, frames: Range<u64>, writer: &mut W) -> io::Result<()> {
+    let state = self.state.borrow();
+    let mut events = vec![
+      "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}}".to_string(),
+      "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}}".to_string()
+    ];
+    let mut gpu_end = Duration::default();
+    for frame in state.frames.iter().filter(|frame| frames.contains(&frame.index)) {
+      events.push(trace_event(&format!("Frame {}", frame.index), "frame", frame.start, frame.duration, 1));
+      for scope in &frame.scopes {
+        events.push(trace_event(scope.name, "cpu", scope.start, scope.cpu_time, 1));
+        if let Some(gpu_time) = scope.gpu_time {
+          let start = scope.start.max(gpu_end);
+          gpu_end = start + gpu_time;
+          events.push(trace_event(scope.name, "gpu", start, gpu_time, 2));
+        }
+      }
+    }
+    writeln!(writer, "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}", events.join(",\n"))
+  }
+
+  fn finish_frame(&self, state: &mut ProfilerState) {
+    if let Some(mut frame) = state.current.take() {
+      frame.duration = self.epoch.elapsed() - frame.start;
+      state.open.clear();
+      state.frames.push_back(frame);
+      while state.frames.len() > self.history_frames {
+        state.frames.pop_front();
+      }
+    }
+  }
+
+  fn open_scope(&self, name: &'static str, gpu: bool) -> ProfileScope<'_> {
+    let mut state = self.state.borrow_mut();
+    let depth = state.open.len();
+    let start = self.epoch.elapsed();
+    let (frame, scope) = match &mut state.current {
+      Some(frame) if self.enabled => {
+        frame.scopes.push(ScopeRecord { name, depth, start, cpu_time: Duration::default(), gpu_time: None });
+        (frame.index, frame.scopes.len() - 1)
+      },
+      _ => return ProfileScope { profiler: self, scope: None, gpu_query: None }
+    };
+    state.open.push(scope);
+    let gpu_query = if gpu && self.gpu_timing && !state.gpu_query_active {
+      let query = state.free_queries.pop().unwrap_or_else(|| {
+        let mut query = 0;
+        unsafe { gl::GenQueries(1, &mut query); }
+        query
+      });
+      unsafe { gl::BeginQuery(gl::TIME_ELAPSED, query); }
+      state.gpu_query_active = true;
+      Some(query)
+    } else {
+      None
+    };
+    ProfileScope { profiler: self, scope: Some((frame, scope)), gpu_query }
+  }
+
+  fn close_scope(&self, frame: u64, scope: usize, gpu_query: Option<GLuint>) {
+    let mut state = self.state.borrow_mut();
+    let state = &mut *state;
+    if let Some(query) = gpu_query {
+      unsafe { gl::EndQuery(gl::TIME_ELAPSED); }
+      state.gpu_query_active = false;
+      state.pending.push_back(PendingQuery { query, frame, scope });
+    }
+    let end = self.epoch.elapsed();
+    // a scope that outlives its frame keeps a cpu time of zero
+    if let Some(current) = state.current.as_mut().filter(|current| current.index == frame) {
+      let record = &mut current.scopes[scope];
+      record.cpu_time = end - record.start;
+      state.open.retain(|open| *open != scope);
+    }
+  }
+
+  // Reads the finished queries without stalling, stops at the first one the GPU is still working on
+  unsafe fn collect_gpu_times(&self) {
+    let mut state = self.state.borrow_mut();
+    while let Some(pending) = state.pending.front() {
+      let mut available = 0;
+      gl::GetQueryObjectiv(pending.query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+      if available == 0 { break; }
+      let mut nanoseconds: GLuint64 = 0;
+      gl::GetQueryObjectui64v(pending.query, gl::QUERY_RESULT, &mut nanoseconds);
+      let PendingQuery { query, frame, scope } = state.pending.pop_front().unwrap();
+      // the frame may have left the history already
+      if let Some(record) = state.frames.iter_mut().find(|record| record.index == frame) {
+        record.scopes[scope].gpu_time = Some(Duration::from_nanos(nanoseconds));
+      }
+      state.free_queries.push(query);
+    }
+  }
+}
+
+impl Drop for Profiler {
+  fn drop(&mut self) {
+    let state = self.state.get_mut();
+    let queries: Vec<GLuint> = state.free_queries.iter().cloned().chain(state.pending.iter().map(|pending| pending.query)).collect();
+    if !queries.is_empty() {
+      unsafe { gl::DeleteQueries(queries.len() as GLsizei, queries.as_ptr()); }
+    }
+  }
+}
+
+fn trace_event(name: &str, category: &str, start: Duration, duration: Duration, thread: u32) -> String {
+  format!("{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":{}}}",
+    escape_json(name), category, start.as_secs_f64() * 1e6, duration.as_secs_f64() * 1e6, thread)
+}
+
+fn escape_json(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c => escaped.push(c)
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn scopes_nest_within_a_frame() {
+    // arrange
+    let profiler = Profiler::new(4, false);
+    // act
+    profiler.begin_frame();
+    {
+      let _draw = profiler.scope("Draw");
+      let _shadows = profiler.scope("Shadows");
+    }
+    let _outside_of_draw = profiler.scope("Swap buffers");
+    drop(_outside_of_draw);
+    profiler.end_frame();
+    let _after_frame = profiler.scope("ignored");
+    // assert
+    let frames = profiler.frames();
+    assert_eq!(1, frames.len());
+    let depths: Vec<(&str, usize)> = frames[0].scopes.iter().map(|scope| (scope.name, scope.depth)).collect();
+    assert_eq!(vec![("Draw", 0), ("Shadows", 1), ("Swap buffers", 0)], depths);
+    assert!(frames[0].scopes[1].start >= frames[0].scopes[0].start);
+    assert!(frames[0].scopes[0].cpu_time >= frames[0].scopes[1].cpu_time);
+  }
+
+  #[test]
+  fn keeps_the_last_frames_of_the_history() {
+    // arrange
+    let profiler = Profiler::new(3, false);
+    // act
+    for _ in 0..5 {
+      profiler.begin_frame();
+      drop(profiler.scope("Update"));
+      drop(profiler.scope("Update"));
+      profiler.end_frame();
+    }
+    // assert
+    assert_eq!(2..5, profiler.frame_range());
+    let stats = profiler.stats("Update").unwrap();
+    assert_eq!(3, stats.frames);
+    assert_eq!(None, stats.gpu_average);
+    let names: Vec<&str> = profiler.summary().iter().map(|stats| stats.name).collect();
+    assert_eq!(vec!["Frame", "Update"], names);
+  }
+
+  #[test]
+  fn gpu_times_are_filled_in_when_available() {
+    // arrange
+    gl_mock::install();
+    let profiler = Profiler::new(4, true);
+    profiler.begin_frame();
+    {
+      let _lighting = profiler.gpu_scope("Lighting");
+      // already timed by the enclosing scope
+      let _point_lights = profiler.gpu_scope("Point lights");
+    }
+    profiler.end_frame();
+    let query = gl_mock::calls_to("glBeginQuery")[0].args[1] as GLuint;
+    // act
+    profiler.begin_frame();
+    let before = profiler.frames()[0].scopes[0].gpu_time;
+    gl_mock::set_query_result(query, 2_000_000);
+    profiler.begin_frame();
+    // assert
+    assert_eq!(None, before);
+    assert_eq!(1, gl_mock::count("glBeginQuery"));
+    assert_eq!(1, gl_mock::count("glEndQuery"));
+    let scopes = &profiler.frames()[0].scopes;
+    assert_eq!(Some(Duration::from_millis(2)), scopes[0].gpu_time);
+    assert_eq!(None, scopes[1].gpu_time);
+    assert_eq!(Some(Duration::from_millis(2)), profiler.stats("Lighting").unwrap().gpu_average);
+  }
+
+  #[test]
+  fn queries_are_reused_and_deleted_with_the_profiler() {
+    // arrange
+    gl_mock::install();
+    let profiler = Profiler::new(4, true);
+    // act
+    for _ in 0..3 {
+      profiler.begin_frame();
+      drop(profiler.gpu_scope("Forward"));
+      profiler.end_frame();
+      let query = gl_mock::calls_to("glBeginQuery").last().unwrap().args[1] as GLuint;
+      gl_mock::set_query_result(query, 1000);
+    }
+    profiler.begin_frame();
+    let generated = gl_mock::count("glGenQueries");
+    drop(profiler);
+    // assert
+    assert_eq!(1, generated);
+    assert_eq!(0, gl_mock::live_count("query"));
+  }
+
+  #[test]
+  fn chrome_trace_has_complete_events_for_frames_in_range() {
+    // arrange
+    let profiler = Profiler::new(4, false);
+    for _ in 0..3 {
+      profiler.begin_frame();
+      drop(profiler.scope("Post \"fx\""));
+      profiler.end_frame();
+    }
+    let mut trace = Vec::new();
+    // act
+    profiler.write_chrome_trace(1..2, &mut trace).unwrap();
+    // assert
+    let trace = String::from_utf8(trace).unwrap();
+    assert!(trace.starts_with("{\"traceEvents\":["));
+    assert!(trace.contains("\"name\":\"Frame 1\",\"cat\":\"frame\",\"ph\":\"X\""));
+    assert!(!trace.contains("Frame 0") && !trace.contains("Frame 2"));
+    assert_eq!(1, trace.matches("\"name\":\"Post \\\"fx\\\"\",\"cat\":\"cpu\"").count());
+  }
+}
@@ -0,0 +1,358 @@
+use gl::types::*;
+
+// How the fragments of a material are combined with the framebuffer. Transparent materials are drawn
+// after the opaque ones, back to front and without writing depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BlendMode {
+  #[default]
+  Opaque,
+  // color * alpha + destination * (1 - alpha)
+  Alpha,
+  // color * alpha + destination
+  Additive,
+  // the color is already multiplied by alpha: color + destination * (1 - alpha)
+  Premultiplied
+}
+
+impl BlendMode {
+  pub fn is_transparent(self) -> bool {
+    self != BlendMode::Opaque
+  }
+
+  // source and destination factor for glBlendFunc, None when blending is disabled
+  pub fn blend_factors(self) -> Option<(GLenum, GLenum)> {
+    match self {
+      BlendMode::Opaque => None,
+      BlendMode::Alpha => Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)),
+      BlendMode::Additive => Some((gl::SRC_ALPHA, gl::ONE)),
+      BlendMode::Premultiplied => Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA))
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthFunc {
+  Never,
+  Less,
+  Equal,
+  LessEqual,
+  Greater,
+  NotEqual,
+  GreaterEqual,
+  Always
+}
+
+impl DepthFunc {
+  pub fn gl_enum(self) -> GLenum {
+    match self {
+      DepthFunc::Never => gl::NEVER,
+      DepthFunc::Less => gl::LESS,
+      DepthFunc::Equal => gl::EQUAL,
+      DepthFunc::LessEqual => gl::LEQUAL,
+      DepthFunc::Greater => gl::GREATER,
+      DepthFunc::NotEqual => gl::NOTEQUAL,
+      DepthFunc::GreaterEqual => gl::GEQUAL,
+      DepthFunc::Always => gl::ALWAYS
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullFace {
+  Front,
+  Back,
+  FrontAndBack
+}
+
+impl CullFace {
+  pub fn gl_enum(self) -> GLenum {
+    match self {
+      CullFace::Front => gl::FRONT,
+      CullFace::Back => gl::BACK,
+      CullFace::FrontAndBack => gl::FRONT_AND_BACK
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolygonMode {
+  Fill,
+  Line,
+  Point
+}
+
+impl PolygonMode {
+  pub fn gl_enum(self) -> GLenum {
+    match self {
+      PolygonMode::Fill => gl::FILL,
+      PolygonMode::Line => gl::LINE,
+      PolygonMode::Point => gl::POINT
+    }
+  }
+}
+
+// The fixed function state for drawing a material or a pass, applied through a RenderStateCache
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderState {
+  pub depth_test: bool,
+  pub depth_write: bool,
+  pub depth_func: DepthFunc,
+  // faces that are culled, None draws both sides
+  pub cull_face: Option<CullFace>,
+  pub blend: BlendMode,
+  pub polygon_mode: PolygonMode,
+  // factor and units for glPolygonOffset, applied to filled polygons
+  pub polygon_offset: Option<(GLfloat, GLfloat)>,
+  pub point_size: GLfloat,
+  // x, y, width and height of the scissor box, None disables the scissor test
+  pub scissor: Option<[GLint; 4]>,
+  pub color_mask: [bool; 4]
+}
+
+impl Default for RenderState {
+  // The OpenGL defaults, except that the depth test is enabled
+  fn default() -> Self {
+    RenderState {
+      depth_test: true,
+      depth_write: true,
+      depth_func: DepthFunc::Less,
+      cull_face: None,
+      blend: BlendMode::Opaque,
+      polygon_mode: PolygonMode::Fill,
+      polygon_offset: None,
+      point_size: 1.0,
+      scissor: None,
+      color_mask: [true, true, true, true]
+    }
+  }
+}
+
+impl RenderState {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+    self.depth_test = depth_test;
+    self
+  }
+
+  pub fn with_depth_write(mut self, depth_write: bool) -> Self {
+    self.depth_write = depth_write;
+    self
+  }
+
+  pub fn with_depth_func(mut self, depth_func: DepthFunc) -> Self {
+    self.depth_func = depth_func;
+    self
+  }
+
+  pub fn with_cull_face(mut self, cull_face: Option<CullFace>) -> Self {
+    self.cull_face = cull_face;
+    self
+  }
+
+  // Transparent blend modes also turn off depth writes, so that transparent entities do not hide each other
+  pub fn with_blend(mut self, blend: BlendMode) -> Self {
+    self.blend = blend;
+    self.depth_write = !blend.is_transparent();
+    self
+  }
+
+  pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+    self.polygon_mode = polygon_mode;
+    self
+  }
+
+  pub fn with_polygon_offset(mut self, factor: GLfloat, units: GLfloat) -> Self {
+    self.polygon_offset = Some((factor, units));
+    self
+  }
+
+  pub fn with_point_size(mut self, point_size: GLfloat) -> Self {
+    self.point_size = point_size;
+    self
+  }
+
+  pub fn with_scissor(mut self, x: GLint, y: GLint, width: GLint, height: GLint) -> Self {
+    self.scissor = Some([x, y, width, height]);
+    self
+  }
+
+  pub fn with_color_mask(mut self, red: bool, green: bool, blue: bool, alpha: bool) -> Self {
+    self.color_mask = [red, green, blue, alpha];
+    self
+  }
+}
+
+// Remembers the state that was applied last and only issues the GL calls for what differs
+#[derive(Default)]
+pub struct RenderStateCache {
+  current: Option<RenderState>
+}
+
+impl RenderStateCache {
+  pub fn new() -> Self {
+    RenderStateCache { current: None }
+  }
+
+  // The next apply sets every part of the state, e.g. after code outside of the cache changed it
+  pub fn invalidate(&mut self) {
+    self.current = None;
+  }
+
+  pub fn current(&self) -> Option<&RenderState> {
+    self.current.as_ref()
+  }
+
+  /// # Safety
+  /// Needs a current GL context whose state nothing but this cache changed since the last apply, see invalidate.
+  pub unsafe fn apply(&mut self, state: &RenderState) {
+    let previous = self.current;
+    let changed = |field: &dyn Fn(&RenderState) -> bool| previous.as_ref().is_none_or(field);
+
+    if changed(&|p| p.depth_test != state.depth_test) {
+      set_capability(gl::DEPTH_TEST, state.depth_test);
+    }
+    if changed(&|p| p.depth_write != state.depth_write) {
+      gl::DepthMask(gl_bool(state.depth_write));
+    }
+    if changed(&|p| p.depth_func != state.depth_func) {
+      gl::DepthFunc(state.depth_func.gl_enum());
+    }
+    if changed(&|p| p.cull_face.is_some() != state.cull_face.is_some()) {
+      set_capability(gl::CULL_FACE, state.cull_face.is_some());
+    }
+    if let Some(face) = state.cull_face {
+      if changed(&|p| p.cull_face != state.cull_face) { gl::CullFace(face.gl_enum()); }
+    }
+    let blend_factors = state.blend.blend_factors();
+    if changed(&|p| p.blend.blend_factors().is_some() != blend_factors.is_some()) {
+      set_capability(gl::BLEND, blend_factors.is_some());
+    }
+    if let Some((source, destination)) = blend_factors {
+      if changed(&|p| p.blend.blend_factors() != blend_factors) { gl::BlendFunc(source, destination); }
+    }
+    if changed(&|p| p.polygon_mode != state.polygon_mode) {
+      gl::PolygonMode(gl::FRONT_AND_BACK, state.polygon_mode.gl_enum());
+    }
+    if changed(&|p| p.polygon_offset.is_some() != state.polygon_offset.is_some()) {
+      set_capability(gl::POLYGON_OFFSET_FILL, state.polygon_offset.is_some());
+    }
+    if let Some((factor, units)) = state.polygon_offset {
+      if changed(&|p| p.polygon_offset != state.polygon_offset) { gl::PolygonOffset(factor, units); }
+    }
+    if changed(&|p| p.point_size != state.point_size) {
+      gl::PointSize(state.point_size);
+    }
+    if changed(&|p| p.scissor.is_some() != state.scissor.is_some()) {
+      set_capability(gl::SCISSOR_TEST, state.scissor.is_some());
+    }
+    if let Some([x, y, width, height]) = state.scissor {
+      if changed(&|p| p.scissor != state.scissor) { gl::Scissor(x, y, width, height); }
+    }
+    if changed(&|p| p.color_mask != state.color_mask) {
+      let [red, green, blue, alpha] = state.color_mask;
+      gl::ColorMask(gl_bool(red), gl_bool(green), gl_bool(blue), gl_bool(alpha));
+    }
+    self.current = Some(*state);
+  }
+}
+
+unsafe fn set_capability(capability: GLenum, enabled: bool) {
+  if enabled { gl::Enable(capability) } else { gl::Disable(capability) }
+}
+
+fn gl_bool(value: bool) -> GLboolean {
+  if value { gl::TRUE } else { gl::FALSE }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn first_apply_sets_all_state() {
+    // arrange
+    gl_mock::install();
+    let mut cache = RenderStateCache::new();
+    // act
+    unsafe { cache.apply(&RenderState::default()); }
+    // assert
+    assert_eq!(vec![gl::DEPTH_TEST as i64], gl_mock::calls_to("glEnable")[0].args);
+    let disabled: Vec<i64> = gl_mock::calls_to("glDisable").iter().map(|call| call.args[0]).collect();
+    assert_eq!(vec![gl::CULL_FACE as i64, gl::BLEND as i64, gl::POLYGON_OFFSET_FILL as i64, gl::SCISSOR_TEST as i64], disabled);
+    for name in ["glDepthMask", "glDepthFunc", "glPolygonMode", "glPointSize", "glColorMask"].iter() {
+      assert_eq!(1, gl_mock::count(name), "{} should be called once", name);
+    }
+    assert_eq!(0, gl_mock::count("glBlendFunc"));
+  }
+
+  #[test]
+  fn same_state_issues_no_calls() {
+    // arrange
+    gl_mock::install();
+    let mut cache = RenderStateCache::new();
+    let state = RenderState::new().with_point_size(20.0).with_cull_face(Some(CullFace::Back));
+    unsafe { cache.apply(&state); }
+    gl_mock::clear_calls();
+    // act
+    unsafe { cache.apply(&state); }
+    // assert
+    assert!(gl_mock::calls().is_empty());
+  }
+
+  #[test]
+  fn only_changed_state_is_issued() {
+    // arrange
+    gl_mock::install();
+    let mut cache = RenderStateCache::new();
+    unsafe { cache.apply(&RenderState::default()); }
+    gl_mock::clear_calls();
+    // act
+    unsafe { cache.apply(&RenderState::new().with_blend(BlendMode::Alpha)); }
+    // assert
+    let calls: Vec<&str> = gl_mock::calls().iter().map(|call| call.name).collect();
+    assert_eq!(vec!["glDepthMask", "glEnable", "glBlendFunc"], calls);
+    assert_eq!(vec![gl::BLEND as i64], gl_mock::calls_to("glEnable")[0].args);
+    assert_eq!(vec![gl::SRC_ALPHA as i64, gl::ONE_MINUS_SRC_ALPHA as i64], gl_mock::calls_to("glBlendFunc")[0].args);
+  }
+
+  #[test]
+  fn switching_blend_factors_keeps_blending_enabled() {
+    // arrange
+    gl_mock::install();
+    let mut cache = RenderStateCache::new();
+    unsafe { cache.apply(&RenderState::new().with_blend(BlendMode::Alpha)); }
+    gl_mock::clear_calls();
+    // act
+    unsafe { cache.apply(&RenderState::new().with_blend(BlendMode::Additive)); }
+    // assert
+    let calls: Vec<&str> = gl_mock::calls().iter().map(|call| call.name).collect();
+    assert_eq!(vec!["glBlendFunc"], calls);
+  }
+
+  #[test]
+  fn invalidate_reapplies_everything() {
+    // arrange
+    gl_mock::install();
+    let mut cache = RenderStateCache::new();
+    let state = RenderState::new().with_scissor(0, 0, 64, 32).with_polygon_mode(PolygonMode::Line);
+    unsafe { cache.apply(&state); }
+    gl_mock::clear_calls();
+    // act
+    cache.invalidate();
+    unsafe { cache.apply(&state); }
+    // assert
+    assert_eq!(vec![0, 0, 64, 32], gl_mock::calls_to("glScissor")[0].args);
+    assert_eq!(vec![gl::FRONT_AND_BACK as i64, gl::LINE as i64], gl_mock::calls_to("glPolygonMode")[0].args);
+    assert_eq!(1, gl_mock::count("glDepthFunc"));
+  }
+
+  #[test]
+  fn transparent_blend_disables_depth_write() {
+    assert!(!RenderState::new().with_blend(BlendMode::Premultiplied).depth_write);
+    assert!(RenderState::new().with_blend(BlendMode::Opaque).depth_write);
+  }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::ptr;
+use gl::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFormat {
+  Rgba8,
+  Srgb8Alpha8,
+  Rgba16F,
+  Rgba32F,
+  R32F,
+  Depth24,
+  Depth32F,
+  Depth24Stencil8
+}
+
+impl TextureFormat {
+  pub fn internal_format(self) -> GLenum {
+    match self {
+      TextureFormat::Rgba8 => gl::RGBA8,
+      TextureFormat::Srgb8Alpha8 => gl::SRGB8_ALPHA8,
+      TextureFormat::Rgba16F => gl::RGBA16F,
+      TextureFormat::Rgba32F => gl::RGBA32F,
+      TextureFormat::R32F => gl::R32F,
+      TextureFormat::Depth24 => gl::DEPTH_COMPONENT24,
+      TextureFormat::Depth32F => gl::DEPTH_COMPONENT32F,
+      TextureFormat::Depth24Stencil8 => gl::DEPTH24_STENCIL8
+    }
+  }
+
+  // format and type of the (absent) pixel data passed to glTexImage2D
+  fn pixel_format(self) -> (GLenum, GLenum) {
+    match self {
+      TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8 => (gl::RGBA, gl::UNSIGNED_BYTE),
+      TextureFormat::Rgba16F | TextureFormat::Rgba32F => (gl::RGBA, gl::FLOAT),
+      TextureFormat::R32F => (gl::RED, gl::FLOAT),
+      TextureFormat::Depth24 | TextureFormat::Depth32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+      TextureFormat::Depth24Stencil8 => (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8)
+    }
+  }
+
+  pub fn is_depth(self) -> bool {
+    matches!(self, TextureFormat::Depth24 | TextureFormat::Depth32F | TextureFormat::Depth24Stencil8)
+  }
+
+  fn attachment_point(self) -> GLenum {
+    match self {
+      TextureFormat::Depth24Stencil8 => gl::DEPTH_STENCIL_ATTACHMENT,
+      _ => gl::DEPTH_ATTACHMENT
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramebufferError {
+  ZeroSize { width: GLsizei, height: GLsizei },
+  NotAColorFormat(TextureFormat),
+  NotADepthFormat(TextureFormat),
+  TooManyColorAttachments { requested: usize, max: usize },
+  Incomplete(GLenum)
+}
+
+impl fmt::Display for FramebufferError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      FramebufferError::ZeroSize { width, height } => write!(f, "framebuffer size must be positive but is {}x{}", width, height),
+      FramebufferError::NotAColorFormat(format) => write!(f, "{:?} cannot be used as a color attachment", format),
+      FramebufferError::NotADepthFormat(format) => write!(f, "{:?} cannot be used as a depth attachment", format),
+      FramebufferError::TooManyColorAttachments { requested, max } => write!(f, "{} color attachments requested but the driver supports {}", requested, max),
+      FramebufferError::Incomplete(status) => write!(f, "framebuffer is incomplete: {}", describe_status(status))
+    }
+  }
+}
+
+impl From<FramebufferError> for String {
+  fn from(error: FramebufferError) -> String {
+    error.to_string()
+  }
+}
+
+fn describe_status(status: GLenum) -> String {
+  let description = match status {
+    gl::FRAMEBUFFER_UNDEFINED => "GL_FRAMEBUFFER_UNDEFINED (the default framebuffer does not exist)",
+    gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT (an attachment is not renderable or has zero size)",
+    gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT (no images are attached)",
+    gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => "GL_FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER (a draw buffer has no attachment)",
+    gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => "GL_FRAMEBUFFER_INCOMPLETE_READ_BUFFER (the read buffer has no attachment)",
+    gl::FRAMEBUFFER_UNSUPPORTED => "GL_FRAMEBUFFER_UNSUPPORTED (this combination of formats is not supported by the driver)",
+    gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "GL_FRAMEBUFFER_INCOMPLETE_MULTISAMPLE (attachments have different sample counts)",
+    gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => "GL_FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS (attachments are not all layered)",
+    _ => return format!("unknown status 0x{:X}", status)
+  };
+  description.to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attachment {
+  pub texture: GLuint,
+  pub format: TextureFormat
+}
+
+// A framebuffer object with texture attachments, built by FramebufferBuilder. Deletes the framebuffer and the
+// attachment textures when dropped.
+pub struct RenderTarget {
+  pub fbo: GLuint,
+  pub width: GLsizei,
+  pub height: GLsizei,
+  pub color_attachments: Vec<Attachment>,
+  pub depth_attachment: Option<Attachment>
+}
+
+impl RenderTarget {
+  /// Binds the framebuffer and sets the viewport to its size
+  ///
+  /// # Safety
+  /// Needs the current GL context the target was created in.
+  pub unsafe fn bind(&self) {
+    gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+    gl::Viewport(0, 0, self.width, self.height);
+  }
+
+  pub fn color_texture(&self, index: usize) -> Option<GLuint> {
+    self.color_attachments.get(index).map(|a| a.texture)
+  }
+
+  pub fn depth_texture(&self) -> Option<GLuint> {
+    self.depth_attachment.map(|a| a.texture)
+  }
+
+  // Reallocates the storage of all attachments, their contents are lost
+  pub fn resize(&mut self, width: GLsizei, height: GLsizei) -> Result<(), FramebufferError> {
+    if width <= 0 || height <= 0 { return Err(FramebufferError::ZeroSize { width, height }); }
+    self.width = width;
+    self.height = height;
+    unsafe {
+      for attachment in self.color_attachments.iter().chain(self.depth_attachment.iter()) {
+        gl::BindTexture(gl::TEXTURE_2D, attachment.texture);
+        allocate_storage(attachment.format, width, height);
+      }
+      gl::BindTexture(gl::TEXTURE_2D, 0);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+      let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+      if status != gl::FRAMEBUFFER_COMPLETE { return Err(FramebufferError::Incomplete(status)); }
+    }
+    Ok(())
+  }
+}
+
+impl Drop for RenderTarget {
+  fn drop(&mut self) {
+    unsafe {
+      for attachment in self.color_attachments.iter().chain(self.depth_attachment.iter()).filter(|a| a.texture != 0) {
+        gl::DeleteTextures(1, &attachment.texture);
+      }
+      if self.fbo != 0 {
+        gl::DeleteFramebuffers(1, &self.fbo);
+      }
+    }
+  }
+}
+
+/// Binds the window's framebuffer and sets the viewport to the window size
+///
+/// # Safety
+/// Needs a current GL context.
+pub unsafe fn bind_default_framebuffer(width: GLsizei, height: GLsizei) {
+  gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+  gl::Viewport(0, 0, width, height);
+}
+
+unsafe fn allocate_storage(format: TextureFormat, width: GLsizei, height: GLsizei) {
+  let (pixel_format, data_type) = format.pixel_format();
+  gl::TexImage2D(gl::TEXTURE_2D, 0, format.internal_format() as GLint, width, height, 0, pixel_format, data_type, ptr::null());
+}
+
+// BUILDER
+
+pub struct FramebufferBuilder {
+  width: GLsizei,
+  height: GLsizei,
+  color_formats: Vec<TextureFormat>,
+  depth_format: Option<TextureFormat>,
+  depth_compare: bool
+}
+
+impl FramebufferBuilder {
+  pub fn new(width: GLsizei, height: GLsizei) -> Self {
+    FramebufferBuilder {
+      width,
+      height,
+      color_formats: Vec::new(),
+      depth_format: None,
+      depth_compare: false
+    }
+  }
+
+  // Color attachments are bound to GL_COLOR_ATTACHMENT0 + n in the order they are added
+  #[allow(dead_code)]
+  pub fn with_color_attachment(mut self, format: TextureFormat) -> Self {
+    self.color_formats.push(format);
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_depth_attachment(mut self, format: TextureFormat) -> Self {
+    self.depth_format = Some(format);
+    self
+  }
+
+  // The depth texture is sampled with a sampler2DShadow (hardware depth comparison), as for shadow maps
+  #[allow(dead_code)]
+  pub fn with_depth_compare(mut self) -> Self {
+    self.depth_compare = true;
+    self
+  }
+
+  pub fn build(self) -> Result<RenderTarget, FramebufferError> {
+    if self.width <= 0 || self.height <= 0 {
+      return Err(FramebufferError::ZeroSize { width: self.width, height: self.height });
+    }
+    if let Some(format) = self.color_formats.iter().find(|f| f.is_depth()) {
+      return Err(FramebufferError::NotAColorFormat(*format));
+    }
+    if let Some(format) = self.depth_format.filter(|f| !f.is_depth()) {
+      return Err(FramebufferError::NotADepthFormat(format));
+    }
+    let max_color_attachments = unsafe {
+      let mut max: GLint = 0;
+      gl::GetIntegerv(gl::MAX_COLOR_ATTACHMENTS, &mut max);
+      max as usize
+    };
+    if self.color_formats.len() > max_color_attachments {
+      return Err(FramebufferError::TooManyColorAttachments { requested: self.color_formats.len(), max: max_color_attachments });
+    }
+    let mut target = RenderTarget {
+      fbo: 0,
+      width: self.width,
+      height: self.height,
+      color_attachments: Vec::new(),
+      depth_attachment: None
+    };
+    unsafe {
+      gl::GenFramebuffers(1, &mut target.fbo);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+      let mut draw_buffers: Vec<GLenum> = Vec::new();
+      for (i, format) in self.color_formats.iter().enumerate() {
+        let texture = create_attachment_texture(*format, self.width, self.height, false);
+        let attachment_point = gl::COLOR_ATTACHMENT0 + i as GLenum;
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment_point, gl::TEXTURE_2D, texture, 0);
+        draw_buffers.push(attachment_point);
+        target.color_attachments.push(Attachment { texture, format: *format });
+      }
+      if let Some(format) = self.depth_format {
+        let texture = create_attachment_texture(format, self.width, self.height, self.depth_compare);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, format.attachment_point(), gl::TEXTURE_2D, texture, 0);
+        target.depth_attachment = Some(Attachment { texture, format });
+      }
+      if draw_buffers.is_empty() {
+        // depth only
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+      } else {
+        gl::DrawBuffers(draw_buffers.len() as GLsizei, draw_buffers.as_ptr());
+      }
+      let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+      if status != gl::FRAMEBUFFER_COMPLETE {
+        // dropping the target deletes the framebuffer and the textures attached so far
+        return Err(FramebufferError::Incomplete(status));
+      }
+    }
+    gl_check!("FramebufferBuilder::build");
+    Ok(target)
+  }
+}
+
+unsafe fn create_attachment_texture(format: TextureFormat, width: GLsizei, height: GLsizei, depth_compare: bool) -> GLuint {
+  let mut texture: GLuint = 0;
+  gl::GenTextures(1, &mut texture);
+  gl::BindTexture(gl::TEXTURE_2D, texture);
+  allocate_storage(format, width, height);
+  let filter = if format.is_depth() && !depth_compare { gl::NEAREST } else { gl::LINEAR };
+  gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+  gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+  if depth_compare {
+    // everything outside of the depth map passes the comparison
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+    let border: [GLfloat; 4] = [1.0, 1.0, 1.0, 1.0];
+    gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+  } else {
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+  }
+  gl::BindTexture(gl::TEXTURE_2D, 0);
+  texture
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn builds_multiple_color_attachments_and_depth() {
+    // arrange
+    gl_mock::install();
+    let builder = FramebufferBuilder::new(800, 600)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .with_color_attachment(TextureFormat::Rgba16F)
+      .with_depth_attachment(TextureFormat::Depth24);
+    // act
+    let target = builder.build().expect("framebuffer should be complete");
+    // assert
+    assert_eq!(2, target.color_attachments.len());
+    assert!(target.depth_texture().is_some());
+    assert_eq!(1, gl_mock::live_count("framebuffer"));
+    assert_eq!(3, gl_mock::live_count("texture"));
+    let draw_buffers = gl_mock::calls_to("glDrawBuffers");
+    assert_eq!(vec![gl::COLOR_ATTACHMENT0 as i64, gl::COLOR_ATTACHMENT1 as i64], draw_buffers[0].args);
+    let depth_attach = gl_mock::calls_to("glFramebufferTexture2D").into_iter()
+      .find(|call| call.args[1] == gl::DEPTH_ATTACHMENT as i64);
+    assert_eq!(Some(target.depth_texture().unwrap() as i64), depth_attach.map(|call| call.args[3]));
+  }
+
+  #[test]
+  fn depth_only_target_disables_draw_buffer() {
+    // arrange
+    gl_mock::install();
+    // act
+    let target = FramebufferBuilder::new(1024, 1024)
+      .with_depth_attachment(TextureFormat::Depth24)
+      .with_depth_compare()
+      .build()
+      .unwrap();
+    // assert
+    assert!(target.color_attachments.is_empty());
+    assert_eq!(vec![gl::NONE as i64], gl_mock::calls_to("glDrawBuffer")[0].args);
+    assert_eq!(0, gl_mock::count("glDrawBuffers"));
+    let compare_mode = gl_mock::calls_to("glTexParameteri").into_iter()
+      .find(|call| call.args[1] == gl::TEXTURE_COMPARE_MODE as i64);
+    assert!(compare_mode.is_some());
+  }
+
+  #[test]
+  fn incomplete_framebuffer_is_described() {
+    // arrange
+    gl_mock::install();
+    gl_mock::set_framebuffer_status(gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT);
+    // act
+    let result = FramebufferBuilder::new(16, 16).build();
+    // assert
+    let error = result.err().expect("build should fail");
+    assert_eq!(FramebufferError::Incomplete(gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT), error);
+    assert!(error.to_string().contains("GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT"));
+  }
+
+  #[test]
+  fn incomplete_framebuffer_deletes_what_it_created() {
+    // arrange
+    gl_mock::install();
+    gl_mock::set_framebuffer_status(gl::FRAMEBUFFER_UNSUPPORTED);
+    // act
+    let result = FramebufferBuilder::new(16, 16)
+      .with_color_attachment(TextureFormat::Rgba16F)
+      .with_depth_attachment(TextureFormat::Depth24Stencil8)
+      .build();
+    // assert
+    assert!(result.is_err());
+    assert_eq!(0, gl_mock::live_count("framebuffer"));
+    assert_eq!(0, gl_mock::live_count("texture"));
+  }
+
+  #[test]
+  fn dropping_deletes_the_framebuffer_and_attachments() {
+    // arrange
+    gl_mock::install();
+    let target = FramebufferBuilder::new(16, 16)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .with_depth_attachment(TextureFormat::Depth24)
+      .build()
+      .unwrap();
+    // act
+    drop(target);
+    // assert
+    assert_eq!(0, gl_mock::live_count("framebuffer"));
+    assert_eq!(0, gl_mock::live_count("texture"));
+  }
+
+  #[test]
+  fn rejects_invalid_configurations_before_touching_gl() {
+    gl_mock::install();
+    assert_eq!(Some(FramebufferError::ZeroSize { width: 0, height: 10 }), FramebufferBuilder::new(0, 10).build().err());
+    let depth_as_color = FramebufferBuilder::new(4, 4).with_color_attachment(TextureFormat::Depth24).build();
+    assert_eq!(Some(FramebufferError::NotAColorFormat(TextureFormat::Depth24)), depth_as_color.err());
+    let color_as_depth = FramebufferBuilder::new(4, 4).with_depth_attachment(TextureFormat::Rgba8).build();
+    assert_eq!(Some(FramebufferError::NotADepthFormat(TextureFormat::Rgba8)), color_as_depth.err());
+    assert_eq!(0, gl_mock::live_count("framebuffer"));
+  }
+
+  #[test]
+  fn too_many_color_attachments() {
+    // arrange
+    gl_mock::install();
+    gl_mock::with_state(|state| { state.integers.insert(gl::MAX_COLOR_ATTACHMENTS, 1); });
+    // act
+    let result = FramebufferBuilder::new(4, 4)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .build();
+    // assert
+    assert_eq!(Some(FramebufferError::TooManyColorAttachments { requested: 2, max: 1 }), result.err());
+  }
+
+  #[test]
+  fn resize_reallocates_every_attachment() {
+    // arrange
+    gl_mock::install();
+    let mut target = FramebufferBuilder::new(800, 600)
+      .with_color_attachment(TextureFormat::Rgba8)
+      .with_depth_attachment(TextureFormat::Depth24Stencil8)
+      .build()
+      .unwrap();
+    gl_mock::clear_calls();
+    // act
+    target.resize(1920, 1080).unwrap();
+    // assert
+    assert_eq!((1920, 1080), (target.width, target.height));
+    let allocations = gl_mock::calls_to("glTexImage2D");
+    assert_eq!(2, allocations.len());
+    assert!(allocations.iter().all(|call| call.args[3] == 1920 && call.args[4] == 1080));
+    assert_eq!(gl::DEPTH24_STENCIL8 as i64, allocations[1].args[2]);
+    assert_eq!(2, gl_mock::live_count("texture"));
+  }
+}
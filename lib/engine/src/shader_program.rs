@@ -1,14 +1,13 @@
-use std::cell::RefCell;
+use std::cell::{ Cell, RefCell };
 use std::ffi::CString;
 use std::collections::HashMap;
 use gl::types::*;
-use cgmath::{ Matrix, Matrix4 };
+use cgmath::{ Matrix, Matrix4, Vector2, Vector3, Vector4 };
 use std::ptr;
 use std::collections::HashSet;
 use std::any::TypeId;
-use std::marker::PhantomData;
-
-// todo: cleanup shaders
+use std::mem;
+use crate::debug_output::LOG_TARGET;
 
 // Helper structs & enums
 
@@ -38,16 +37,30 @@ impl Eq for ShaderHandle {}
 
 #[derive(Clone, Copy)]
 pub struct Uniform {
-  location: GLint,
-  gl_type: GLenum
+  location: GLint
+}
+
+impl Uniform {
+  pub fn location(&self) -> GLint { self.location }
 }
 
 // ShaderProgram
 
+// Owns the program object, which is deleted when the ShaderProgram is dropped
 #[derive(Default)]
 pub struct ShaderProgram {
-  pub handle: GLuint,
-  pub uniform_location_map: RefCell<HashMap<String, Uniform>>
+  handle: GLuint,
+  pub uniform_location_map: RefCell<HashMap<String, Uniform>>,
+  // how many of the block names of UniformBlockBindings the program is connected to
+  pub(crate) connected_blocks: Cell<usize>
+}
+
+impl Drop for ShaderProgram {
+  fn drop(&mut self) {
+    if self.handle != 0 {
+      unsafe { gl::DeleteProgram(self.handle); }
+    }
+  }
 }
 
 macro_rules! gl_stringify {
@@ -55,7 +68,37 @@ macro_rules! gl_stringify {
 }
 
 impl ShaderProgram {
+  /// Takes ownership of a linked program
+  ///
+  /// # Safety
+  /// handle has to be a linked program of the current GL context that nothing else deletes.
+  pub unsafe fn from_raw(handle: GLuint) -> Self {
+    ShaderProgram { handle, uniform_location_map: RefCell::new(HashMap::new()), connected_blocks: Cell::new(0) }
+  }
+
+  pub fn handle(&self) -> GLuint {
+    self.handle
+  }
+
+  // Gives up ownership, deleting the program is then up to the caller
+  pub fn into_raw(mut self) -> GLuint {
+    mem::replace(&mut self.handle, 0)
+  }
+
+  /// # Safety
+  /// Needs the current GL context the program was linked in. Panics when the uniform does not exist, see try_get_uniform.
   pub unsafe fn get_uniform(&self, name: &str) -> Uniform {
+    match self.try_get_uniform(name) {
+      Some(uniform) => uniform,
+      None => panic!("uniform {} does not exist", name)
+    }
+  }
+
+  /// Like get_uniform, but returns None for uniforms that do not exist or were optimized away
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn try_get_uniform(&self, name: &str) -> Option<Uniform> {
     let mut mut_map = self.uniform_location_map.borrow_mut();
     let uniform_option = mut_map.get(name);
     let uniform: Uniform;
@@ -63,98 +106,161 @@ impl ShaderProgram {
       uniform = *wrapped_uniform;
     } else {
       let uniform_location = gl::GetUniformLocation(self.handle, gl_stringify!(name));
-      if uniform_location < 0 { panic!("uniform {} does not exist", name); }
-      let mut length: GLsizei = 0;  // howmany characters are written by OpenGL
-      let mut size: GLint = 0;      // size of uniform variable
-      let mut gl_type: GLenum = 0;  // type of the uniform variable
-      let mut gl_char: GLchar = 0;  // buffer to write the name of the uniform variable to
-      gl::GetActiveUniform(self.handle, uniform_location as GLuint, 1, &mut length as _, &mut size as _, &mut gl_type as _, &mut gl_char as _);
-      uniform = Uniform { location: uniform_location, gl_type };
+      if uniform_location < 0 { return None; }
+      uniform = Uniform { location: uniform_location };
       mut_map.insert(String::from(name), uniform);
     }
-    uniform
+    Some(uniform)
+  }
+
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn has_uniform(&self, name: &str) -> bool {
+    self.try_get_uniform(name).is_some()
+  }
+
+  /// Whether the fragment shader declares an out variable with this name
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn has_output(&self, name: &str) -> bool {
+    let name = CString::new(name).unwrap();
+    gl::GetFragDataLocation(self.handle, name.as_ptr()) >= 0
   }
 
+  /// Whether the vertex shader has an active input with this name
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn has_attribute(&self, name: &str) -> bool {
+    self.attribute_location(name).is_some()
+  }
+
+  /// The location of the vertex shader input with this name, None when it is not active
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn attribute_location(&self, name: &str) -> Option<GLuint> {
+    let name = CString::new(name).unwrap();
+    let location = gl::GetAttribLocation(self.handle, name.as_ptr());
+    if location < 0 { None } else { Some(location as GLuint) }
+  }
+
+  /// Connects the uniform block with this name to a binding point, false when the program has no such block
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn bind_uniform_block(&self, name: &str, binding: GLuint) -> bool {
+    let name = CString::new(name).unwrap();
+    let index = gl::GetUniformBlockIndex(self.handle, name.as_ptr());
+    if index == gl::INVALID_INDEX { return false; }
+    gl::UniformBlockBinding(self.handle, index, binding);
+    true
+  }
+
+  /// # Safety
+  /// Needs the current GL context the program was linked in. Panics when the uniform does not exist.
   pub unsafe fn get_uniform_location(&self, name: &str) -> GLint {
     self.get_uniform(name).location
   }
 
+  /// # Safety
+  /// The program has to be in use in the current GL context. Panics when the uniform does not exist.
   pub unsafe fn set_uniform_matrix(&self, name: &str, matrix: Matrix4<GLfloat>) {
     let uniform_location: GLint = self.get_uniform_location(name);
     gl::UniformMatrix4fv(uniform_location, 1, gl::FALSE, matrix.as_ptr());
   }
 
+  /// # Safety
+  /// Needs the current GL context the program was linked in, the setter only works while the program is in use.
   pub unsafe fn create_uniform_setter(&self, name: &str) -> UniformSetter {
     let uniform = self.get_uniform(name);
     UniformSetter::new(uniform.location)
   }
 
-  #[allow(dead_code)]
-  pub unsafe fn get_active_attributes(&self) -> Vec<u8> {
+  /// Sets the uniform when the program has it, uniforms that are unused in the shader are optimized away
+  ///
+  /// # Safety
+  /// The program has to be in use in the current GL context.
+  pub unsafe fn set_uniform_if_present<T>(&self, name: &str, value: T) where UniformSetter: SetUniform<T> {
+    if let Some(uniform) = self.try_get_uniform(name) {
+      UniformSetter::new(uniform.location).set(value);
+    }
+  }
+
+  /// The inputs of the vertex shader in order of location, without built-ins like gl_VertexID
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn get_active_attributes(&self) -> Vec<ActiveAttribute> {
     let mut count: GLint = 0;
+    let mut max_length: GLint = 0;
     gl::GetProgramiv(self.handle, gl::ACTIVE_ATTRIBUTES, &mut count);
-    let mut bufSize = 1;
-    let mut length = 0;
-    let mut size = 0;
-    let mut gl_type: GLenum = 0;
-    let mut name: GLchar = 0;
-    let mut attrib_types = Vec::new();
+    gl::GetProgramiv(self.handle, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+    let mut attributes = Vec::new();
     for i in 0..count {
-      gl::GetActiveAttrib(self.handle, i as GLuint, bufSize, &mut length, &mut size, &mut gl_type, &mut name);
-      let float_count = gl_type_to_float_count(gl_type);
-      println!("Attribute of type {} and float count {}", gl_enum_to_string(gl_type), float_count);
-      attrib_types.push(float_count)
+      let mut name = vec![0u8; max_length.max(1) as usize];
+      let mut length: GLsizei = 0;
+      let mut size: GLint = 0;
+      let mut gl_type: GLenum = 0;
+      gl::GetActiveAttrib(self.handle, i as GLuint, name.len() as GLsizei, &mut length, &mut size, &mut gl_type, name.as_mut_ptr() as *mut GLchar);
+      name.truncate(length as usize);
+      let name = String::from_utf8_lossy(&name).into_owned();
+      let location = gl::GetAttribLocation(self.handle, gl_stringify!(name.as_str()));
+      if location >= 0 {
+        attributes.push(ActiveAttribute { name, location: location as GLuint, gl_type, size });
+      }
     }
-    attrib_types
+    attributes.sort_by_key(|attribute| attribute.location);
+    attributes
   }
 }
 
-fn gl_enum_to_string(gl_type: GLenum) -> String {
-  let thing: &str = match gl_type {
-    gl::FLOAT => "GL_FLOAT",
-    gl::FLOAT_MAT4 => "GL_FLOAT_MAT4",
-    gl::FLOAT_VEC2 => "GL_FLOAT_VEC2",
-    gl::FLOAT_VEC3 => "GL_FLOAT_VEC3",
-    gl::FLOAT_VEC4 => "GL_FLOAT_VEC4",
-    _ => "unknown"
-  };
-  String::from(thing)
-}
-
-fn gl_type_to_float_count(gl_type: GLenum) -> u8 {
-  let thing = match gl_type {
-    gl::FLOAT => 1,
-    gl::FLOAT_MAT4 => 16,
-    gl::FLOAT_VEC2 => 2,
-    gl::FLOAT_VEC3 => 3,
-    gl::FLOAT_VEC4 => 4,
-    _ => 0
-  };
-  thing
+// An input of the vertex shader, size is the array length (1 for no array)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActiveAttribute {
+  pub name: String,
+  pub location: GLuint,
+  pub gl_type: GLenum,
+  pub size: GLint
 }
 
 // checkout a complete list of gl types here: https://www.khronos.org/registry/OpenGL-Refpages/gl4/html/glGetActiveUniform.xhtml
+#[allow(dead_code)]
 fn get_required_type_id(gl_type: GLenum) -> TypeId {
   match gl_type {
-    GL_FLOAT => return TypeId::of::<GLfloat>(),
-    GL_FLOAT_MAT4 => return TypeId::of::<Matrix4<GLfloat>>(),
+    gl::FLOAT => TypeId::of::<GLfloat>(),
+    gl::FLOAT_MAT4 => TypeId::of::<Matrix4<GLfloat>>(),
     _ => panic!("gl type not recognized: {}", gl_type)
   }
 }
 
 // BUILDER
 
+// A builder that is dropped without building deletes the program and its shaders
 #[derive(Default)]
 pub struct ShaderProgramBuilder {
   handle: GLuint,
   shader_handles: HashSet<GLuint>,
 }
 
+impl Drop for ShaderProgramBuilder {
+  fn drop(&mut self) {
+    unsafe {
+      for shader_handle in self.shader_handles.drain() {
+        if self.handle != 0 { gl::DetachShader(self.handle, shader_handle); }
+        gl::DeleteShader(shader_handle);
+      }
+      if self.handle != 0 { gl::DeleteProgram(self.handle); }
+    }
+  }
+}
+
 impl ShaderProgramBuilder {
   pub fn new() -> Self {
     ShaderProgramBuilder {
       handle: unsafe { gl::CreateProgram() },
-      ..Default::default()
+      shader_handles: HashSet::new()
     }
   }
 
@@ -180,14 +286,18 @@ impl ShaderProgramBuilder {
     self
   }
 
-  pub fn build(self) -> ShaderProgram {
+  // The shaders are detached and deleted once the program is linked
+  pub fn build(mut self) -> ShaderProgram {
+    let handle = mem::replace(&mut self.handle, 0);
     unsafe {
-      link_program(self.handle);
-      gl::UseProgram(self.handle);
-    }
-    ShaderProgram {
-      handle: self.handle,
-      ..Default::default()
+      link_program(handle);
+      for shader_handle in self.shader_handles.drain() {
+        gl::DetachShader(handle, shader_handle);
+        gl::DeleteShader(shader_handle);
+      }
+      gl::UseProgram(handle);
+      gl_check!("ShaderProgramBuilder::build");
+      ShaderProgram::from_raw(handle)
     }
   }
 }
@@ -243,14 +353,16 @@ unsafe fn check_gl_status(handle: GLuint, status: GLenum){
       ptr::null_mut(),
       error.as_ptr() as *mut GLchar
     );
-    println!("Error: {}", error.to_string_lossy().into_owned())
+    error!(target: LOG_TARGET, "{}", error.to_string_lossy().into_owned())
   }
 }
 
 // UniformSetter
 
 pub trait SetUniform<T> {
-  unsafe fn set(&self, value: T) {
+  /// # Safety
+  /// The program the uniform location belongs to has to be in use in the current GL context.
+  unsafe fn set(&self, _value: T) {
     panic!("cannot handle type for OpenGL");
   }
 }
@@ -262,7 +374,7 @@ pub struct UniformSetter {
 
 impl UniformSetter {
   pub fn new(location: GLint) -> Self {
-    return UniformSetter { location };
+    UniformSetter { location }
   }
 }
 
@@ -276,4 +388,73 @@ impl SetUniform<GLfloat> for UniformSetter {
   unsafe fn set(&self, value: GLfloat) {
     gl::Uniform1f(self.location, value)
   }
-}
\ No newline at end of file
+}
+
+impl SetUniform<GLint> for UniformSetter {
+  unsafe fn set(&self, value: GLint) {
+    gl::Uniform1i(self.location, value)
+  }
+}
+
+impl SetUniform<Vector2<GLfloat>> for UniformSetter {
+  unsafe fn set(&self, value: Vector2<GLfloat>) {
+    gl::Uniform2f(self.location, value.x, value.y)
+  }
+}
+
+impl SetUniform<Vector3<GLfloat>> for UniformSetter {
+  unsafe fn set(&self, value: Vector3<GLfloat>) {
+    gl::Uniform3f(self.location, value.x, value.y, value.z)
+  }
+}
+
+impl SetUniform<Vector4<GLfloat>> for UniformSetter {
+  unsafe fn set(&self, value: Vector4<GLfloat>) {
+    gl::Uniform4f(self.location, value.x, value.y, value.z, value.w)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn shaders_are_deleted_after_linking() {
+    // arrange
+    gl_mock::install();
+    // act
+    let program = ShaderProgramBuilder::new()
+      .with_vertex_shader("void main() {}")
+      .with_fragment_shader("void main() {}")
+      .build();
+    // assert
+    assert_eq!(0, gl_mock::live_count("shader"));
+    assert_eq!(2, gl_mock::count("glDetachShader"));
+    assert_eq!(1, gl_mock::live_count("program"));
+    drop(program);
+    assert_eq!(0, gl_mock::live_count("program"));
+  }
+
+  #[test]
+  fn unbuilt_builder_deletes_everything() {
+    gl_mock::install();
+    drop(ShaderProgramBuilder::new().with_vertex_shader("void main() {}"));
+    assert_eq!(0, gl_mock::live_count("shader"));
+    assert_eq!(0, gl_mock::live_count("program"));
+  }
+
+  #[test]
+  fn into_raw_keeps_the_program_alive() {
+    // arrange
+    gl_mock::install();
+    let program = ShaderProgramBuilder::new().build();
+    // act
+    let handle = program.into_raw();
+    // assert
+    assert_ne!(0, handle);
+    assert_eq!(1, gl_mock::live_count("program"));
+    drop(unsafe { ShaderProgram::from_raw(handle) });
+    assert_eq!(0, gl_mock::live_count("program"));
+  }
+}
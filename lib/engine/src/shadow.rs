@@ -0,0 +1,107 @@
+use gl::types::*;
+use cgmath::{ ortho, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3 };
+use crate::camera::Camera;
+use crate::render_target::{ FramebufferBuilder, RenderTarget, TextureFormat };
+use crate::shader_program::ShaderProgram;
+use crate::texture::bind_texture_unit;
+
+// Shadow maps are bound to the texture units from SHADOW_TEXTURE_UNIT upwards,
+// lower units are left to material textures. Must match the lit shader.
+pub const MAX_SHADOW_MAPS: usize = 2;
+pub const SHADOW_TEXTURE_UNIT: GLuint = 8;
+
+// Depth texture attached to a framebuffer without color attachments
+pub struct ShadowMap {
+  pub target: RenderTarget
+}
+
+impl ShadowMap {
+  pub fn new(size: GLsizei) -> Result<ShadowMap, String> {
+    let target = FramebufferBuilder::new(size, size)
+      .with_depth_attachment(TextureFormat::Depth24)
+      .with_depth_compare()
+      .build()?;
+    Ok(ShadowMap { target })
+  }
+
+  pub fn depth_texture(&self) -> GLuint {
+    self.target.depth_texture().unwrap_or(0)
+  }
+
+  /// Binds the framebuffer and viewport and clears the depth
+  ///
+  /// # Safety
+  /// Needs the current GL context the shadow map was created in.
+  pub unsafe fn begin(&self) {
+    self.target.bind();
+    gl::Clear(gl::DEPTH_BUFFER_BIT);
+  }
+}
+
+/// Binds shadow map n to texture unit SHADOW_TEXTURE_UNIT + n and sets the ShadowMaps[n] sampler
+/// and ShadowMatrices[n] of the program, which is expected to be in use
+///
+/// # Safety
+/// The program has to be in use in the current GL context the maps were created in.
+pub unsafe fn upload_shadow_maps(program: &ShaderProgram, maps: &[ShadowMap], shadow_matrices: &[Matrix4<GLfloat>]) {
+  for (i, shadow_map) in maps.iter().enumerate() {
+    let sampler_name = format!("ShadowMaps[{}]", i);
+    if !program.has_uniform(&sampler_name) { continue; }
+    let unit = SHADOW_TEXTURE_UNIT + i as GLuint;
+    bind_texture_unit(unit, shadow_map.depth_texture());
+    program.set_uniform_if_present(&sampler_name, unit as GLint);
+    let matrix = shadow_matrices.get(i).cloned().unwrap_or_else(Matrix4::identity);
+    program.set_uniform_matrix(&format!("ShadowMatrices[{}]", i), matrix);
+  }
+}
+
+// Orthographic view-projection for a directional light that encloses the part of the camera
+// frustum up to max_distance from the eye. Casters up to the frustum's radius behind it are kept.
+pub fn directional_light_view_projection(direction: Vector3<GLfloat>, camera: &Camera, max_distance: GLfloat) -> Matrix4<GLfloat> {
+  let corners = camera.frustum_corners(camera.near, camera.far.min(max_distance));
+  let center = Point3::centroid(&corners);
+  let radius = corners.iter().map(|c| (*c - center).magnitude()).fold(0.0, GLfloat::max);
+  let direction = direction.normalize();
+  let up = if direction.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+  let light_view = Matrix4::look_at(center + -direction * radius, center, up);
+  let mut min = Vector3::new(GLfloat::MAX, GLfloat::MAX, GLfloat::MAX);
+  let mut max = Vector3::new(GLfloat::MIN, GLfloat::MIN, GLfloat::MIN);
+  for corner in corners.iter() {
+    let p = light_view.transform_point(*corner);
+    min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+    max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+  }
+  // the light looks down -z, so the nearest point has the largest z
+  let light_projection = ortho(min.x, max.x, min.y, max.y, -max.z - radius, -min.z);
+  light_projection * light_view
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::camera::CameraBuilder;
+
+  #[test]
+  fn light_frustum_contains_camera_frustum() {
+    // arrange
+    let camera = CameraBuilder::new().with_far(20.0).build();
+    let direction = Vector3::new(-0.5, -1.0, 0.8);
+    // act
+    let view_projection = directional_light_view_projection(direction, &camera, 10.0);
+    // assert
+    for corner in camera.frustum_corners(camera.near, 10.0).iter() {
+      let ndc = view_projection.transform_point(*corner);
+      for value in [ndc.x, ndc.y, ndc.z].iter() {
+        assert!(value.abs() <= 1.0 + 1e-4, "corner {:?} maps to {:?}", corner, ndc);
+      }
+    }
+  }
+
+  #[test]
+  fn straight_down_light_does_not_degenerate() {
+    let camera = CameraBuilder::new().build();
+    let view_projection = directional_light_view_projection(Vector3::new(0.0, -1.0, 0.0), &camera, 10.0);
+    let ndc = view_projection.transform_point(camera.target);
+    assert!(ndc.x.is_finite() && ndc.y.is_finite() && ndc.z.is_finite());
+  }
+}
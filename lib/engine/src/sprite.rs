@@ -0,0 +1,280 @@
+use std::mem::size_of;
+use std::rc::Rc;
+use std::slice;
+use gl::types::*;
+use cgmath::{ Matrix4, SquareMatrix, Transform, Point3 };
+use crate::dynamic_buffer::{ BufferUsage, DynamicBuffer };
+use crate::render_state::{ BlendMode, RenderState, RenderStateCache };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use crate::texture::{ bind_texture_unit, Texture };
+use crate::vao_builder::VaoBuilder;
+use crate::vao_builder::buffer_component::BufferComponent;
+
+// The texture of a batch is bound to this texture unit while drawing
+const SPRITE_TEXTURE_UNIT: GLuint = 0;
+
+// A rectangle of a texture in texels, from the top left corner of the image as it is in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteRect {
+  pub x: GLfloat,
+  pub y: GLfloat,
+  pub width: GLfloat,
+  pub height: GLfloat
+}
+
+impl SpriteRect {
+  pub fn new(x: GLfloat, y: GLfloat, width: GLfloat, height: GLfloat) -> SpriteRect {
+    SpriteRect { x, y, width, height }
+  }
+
+  // The cell at column and row of a sheet of equally sized cells
+  pub fn cell(column: u32, row: u32, width: GLfloat, height: GLfloat) -> SpriteRect {
+    SpriteRect::new(column as GLfloat * width, row as GLfloat * height, width, height)
+  }
+}
+
+// A textured quad on the xy plane of its transform, rect.width / pixels_per_unit world units wide.
+// Textures are expected to be flipped vertically when loaded, as TextureBuilder does by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sprite {
+  // sprites keep their texture alive, sprites with the same texture are drawn in one call
+  pub texture: Rc<Texture>,
+  // the part of the texture, all of it by default
+  pub rect: SpriteRect,
+  pub transform: Matrix4<GLfloat>,
+  // the point of the rect at the origin of the transform, from (0, 0) at the bottom left to (1, 1) at the top right
+  pub pivot: [GLfloat; 2],
+  // multiplies the texels
+  pub tint: [GLfloat; 4],
+  // lower layers are drawn first, sprites in the same layer are sorted by texture
+  pub layer: i32,
+  // texels per world unit, see Projection::PixelPerfect
+  pub pixels_per_unit: GLfloat
+}
+
+impl Sprite {
+  pub fn new(texture: &Rc<Texture>) -> Sprite {
+    Sprite {
+      texture: Rc::clone(texture),
+      rect: SpriteRect::new(0.0, 0.0, texture.width as GLfloat, texture.height as GLfloat),
+      transform: Matrix4::identity(),
+      pivot: [0.5, 0.5],
+      tint: [1.0, 1.0, 1.0, 1.0],
+      layer: 0,
+      pixels_per_unit: 1.0
+    }
+  }
+
+  pub fn with_rect(mut self, rect: SpriteRect) -> Self {
+    self.rect = rect;
+    self
+  }
+
+  pub fn with_transform(mut self, transform: Matrix4<GLfloat>) -> Self {
+    self.transform = transform;
+    self
+  }
+
+  pub fn with_pivot(mut self, x: GLfloat, y: GLfloat) -> Self {
+    self.pivot = [x, y];
+    self
+  }
+
+  pub fn with_tint(mut self, rgba: [GLfloat; 4]) -> Self {
+    self.tint = rgba;
+    self
+  }
+
+  pub fn with_layer(mut self, layer: i32) -> Self {
+    self.layer = layer;
+    self
+  }
+
+  pub fn with_pixels_per_unit(mut self, pixels_per_unit: GLfloat) -> Self {
+    self.pixels_per_unit = pixels_per_unit;
+    self
+  }
+
+  // Size of the quad in world units before the transform
+  pub fn size(&self) -> [GLfloat; 2] {
+    [self.rect.width / self.pixels_per_unit, self.rect.height / self.pixels_per_unit]
+  }
+
+  // Texture coordinates of the bottom left and the top right corner of the rect
+  pub fn uv_bounds(&self) -> ([GLfloat; 2], [GLfloat; 2]) {
+    let (width, height) = (self.texture.width.max(1) as GLfloat, self.texture.height.max(1) as GLfloat);
+    let rect = self.rect;
+    ([rect.x / width, 1.0 - (rect.y + rect.height) / height], [(rect.x + rect.width) / width, 1.0 - rect.y / height])
+  }
+}
+
+crate::vertex_format! {
+  pub struct SpriteVertex { pub position: [GLfloat; 3], pub uv: [GLfloat; 2], pub tint: [GLfloat; 4] }
+}
+
+// Appends two triangles for the sprite
+pub fn sprite_vertices(sprite: &Sprite, vertices: &mut Vec<SpriteVertex>) {
+  let [width, height] = sprite.size();
+  let (left, bottom) = (-sprite.pivot[0] * width, -sprite.pivot[1] * height);
+  let (uv_min, uv_max) = sprite.uv_bounds();
+  let corner = |x: usize, y: usize| SpriteVertex {
+    position: sprite.transform.transform_point(Point3::new(left + x as GLfloat * width, bottom + y as GLfloat * height, 0.0)).into(),
+    uv: [[uv_min[0], uv_max[0]][x], [uv_min[1], uv_max[1]][y]],
+    tint: sprite.tint
+  };
+  vertices.extend_from_slice(&[corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 0), corner(1, 1), corner(0, 1)]);
+}
+
+// Vertices with the same texture that are drawn with one call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteDraw {
+  pub texture: GLuint,
+  pub first: usize,
+  pub count: usize
+}
+
+// Sorts the sprites by layer and then by texture, sprites that are equal in both keep their order. Appends their
+// vertices in that order and a draw for every run of the same texture.
+pub fn batch_sprites(sprites: &[Sprite], vertices: &mut Vec<SpriteVertex>, draws: &mut Vec<SpriteDraw>) {
+  let mut order: Vec<&Sprite> = sprites.iter().collect();
+  order.sort_by_key(|sprite| (sprite.layer, sprite.texture.handle));
+  for sprite in order {
+    let first = vertices.len();
+    sprite_vertices(sprite, vertices);
+    match draws.last_mut() {
+      Some(draw) if draw.texture == sprite.texture.handle => draw.count += vertices.len() - first,
+      _ => draws.push(SpriteDraw { texture: sprite.texture.handle, first, count: vertices.len() - first })
+    }
+  }
+}
+
+// Draws sprites through a single vertex buffer with a draw call per texture batch, see batch_sprites
+pub struct SpriteBatch {
+  program: ShaderProgram,
+  buffers: BufferComponent,
+  vertex_buffer: DynamicBuffer,
+  vertices: Vec<SpriteVertex>,
+  draws: Vec<SpriteDraw>
+}
+
+impl SpriteBatch {
+  pub fn new() -> SpriteBatch {
+    let program = ShaderProgramBuilder::new()
+      .with_vertex_shader(include_str!("glsl/sprite/vertex.glsl"))
+      .with_fragment_shader(include_str!("glsl/sprite/fragment.glsl"))
+      .build();
+    let buffers = VaoBuilder::new().with_vertex::<SpriteVertex>().build();
+    let vertex_buffer = DynamicBuffer::new(0, size_of::<SpriteVertex>() / size_of::<GLfloat>(), BufferUsage::Stream);
+    SpriteBatch { program, buffers, vertex_buffer, vertices: Vec::new(), draws: Vec::new() }
+  }
+
+  /// Draws into the framebuffer that is bound and returns the draws it took. Sprites are depth tested against
+  /// the scene but don't write depth, so layers decide what is in front.
+  ///
+  /// # Safety
+  /// Needs the current GL context the batch and the sprites' textures were created in.
+  pub unsafe fn draw(&mut self, sprites: &[Sprite], view_projection: Matrix4<GLfloat>, state_cache: &mut RenderStateCache) -> &[SpriteDraw] {
+    self.vertices.clear();
+    self.draws.clear();
+    batch_sprites(sprites, &mut self.vertices, &mut self.draws);
+    if self.vertices.is_empty() { return &self.draws; }
+    // SpriteVertex is nothing but floats
+    let floats = slice::from_raw_parts(self.vertices.as_ptr() as *const GLfloat, self.vertices.len() * self.vertex_buffer.floats_per_vertex());
+    self.vertex_buffer.upload(&self.buffers, floats);
+    state_cache.apply(&RenderState::new().with_blend(BlendMode::Alpha));
+    gl::UseProgram(self.program.handle());
+    self.program.set_uniform_matrix("ViewProjection", view_projection);
+    self.program.set_uniform_if_present("Sprite", SPRITE_TEXTURE_UNIT as GLint);
+    gl::BindVertexArray(self.buffers.vao());
+    for draw in &self.draws {
+      bind_texture_unit(SPRITE_TEXTURE_UNIT, draw.texture);
+      gl::DrawArrays(gl::TRIANGLES, draw.first as GLint, draw.count as GLsizei);
+    }
+    gl::BindVertexArray(0);
+    gl_check!("SpriteBatch::draw");
+    &self.draws
+  }
+}
+
+impl Default for SpriteBatch {
+  fn default() -> Self {
+    SpriteBatch::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::Vector3;
+  use crate::gl_mock;
+
+  // the textures are deleted when the sprites are dropped, tests install the mock first
+  fn sprite(texture: GLuint, layer: i32) -> Sprite {
+    Sprite::new(&Rc::new(Texture { handle: texture, width: 64, height: 32 })).with_layer(layer)
+  }
+
+  #[test]
+  fn quads_are_sized_in_texels_around_the_pivot() {
+    // arrange
+    gl_mock::install();
+    let sprite = sprite(1, 0)
+      .with_rect(SpriteRect::cell(1, 0, 16.0, 16.0))
+      .with_pixels_per_unit(16.0)
+      .with_pivot(0.5, 0.0)
+      .with_transform(Matrix4::from_translation(Vector3::new(10.0, 5.0, 0.0)))
+      .with_tint([1.0, 0.0, 0.0, 1.0]);
+    let mut vertices = Vec::new();
+    // act
+    sprite_vertices(&sprite, &mut vertices);
+    // assert
+    assert_eq!(6, vertices.len());
+    assert_eq!([9.5, 5.0, 0.0], vertices[0].position);
+    assert_eq!([10.5, 6.0, 0.0], vertices[2].position);
+    assert_eq!([1.0, 0.0, 0.0, 1.0], vertices[5].tint);
+  }
+
+  #[test]
+  fn rects_are_measured_from_the_top_of_the_image() {
+    // arrange
+    gl_mock::install();
+    let sprite = sprite(1, 0).with_rect(SpriteRect::new(16.0, 8.0, 16.0, 8.0));
+    // act
+    let (uv_min, uv_max) = sprite.uv_bounds();
+    // assert: the texture is flipped, so the top of the image is at v = 1
+    assert_eq!([0.25, 0.5], uv_min);
+    assert_eq!([0.5, 0.75], uv_max);
+  }
+
+  #[test]
+  fn sprites_are_sorted_by_layer_then_texture() {
+    // arrange
+    gl_mock::install();
+    let sprites = [sprite(2, 1), sprite(1, 0), sprite(2, 0), sprite(1, 0), sprite(1, 1)];
+    let (mut vertices, mut draws) = (Vec::new(), Vec::new());
+    // act
+    batch_sprites(&sprites, &mut vertices, &mut draws);
+    // assert
+    assert_eq!(30, vertices.len());
+    assert_eq!(vec![
+      SpriteDraw { texture: 1, first: 0, count: 12 },
+      SpriteDraw { texture: 2, first: 12, count: 6 },
+      SpriteDraw { texture: 1, first: 18, count: 6 },
+      SpriteDraw { texture: 2, first: 24, count: 6 }
+    ], draws);
+  }
+
+  #[test]
+  fn equal_sprites_keep_their_order() {
+    // arrange
+    gl_mock::install();
+    let first = sprite(1, 0).with_tint([1.0, 0.0, 0.0, 1.0]);
+    let second = sprite(1, 0).with_tint([0.0, 1.0, 0.0, 1.0]);
+    let (mut vertices, mut draws) = (Vec::new(), Vec::new());
+    // act
+    batch_sprites(&[first.clone(), second.clone()], &mut vertices, &mut draws);
+    // assert
+    assert_eq!(1, draws.len());
+    assert_eq!(first.tint, vertices[0].tint);
+    assert_eq!(second.tint, vertices[6].tint);
+  }
+}
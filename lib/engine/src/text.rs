@@ -0,0 +1,358 @@
+use std::mem::{ self, size_of };
+use std::slice;
+use gl::types::*;
+use cgmath::{ Matrix4, Point3, Vector4 };
+use crate::dynamic_buffer::{ BufferUsage, DynamicBuffer };
+use crate::font::{ AtlasKind, FontAtlas };
+use crate::render_state::{ BlendMode, RenderState, RenderStateCache };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use crate::texture::Texture;
+use crate::vao_builder::VaoBuilder;
+use crate::vao_builder::buffer_component::BufferComponent;
+
+// The atlas is bound to this texture unit while drawing
+const ATLAS_TEXTURE_UNIT: GLuint = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+  Left,
+  Center,
+  Right
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+  // height of a line, in pixels for screen text and labels, in world units for world text
+  pub size: GLfloat,
+  pub color: [GLfloat; 4],
+  // of each line to the anchor
+  pub align: TextAlign,
+  // longer lines are wrapped at spaces, words that don't fit on a line of their own are broken
+  pub max_width: Option<GLfloat>,
+  // multiplies the line height
+  pub line_spacing: GLfloat
+}
+
+impl TextStyle {
+  pub fn new(size: GLfloat) -> TextStyle {
+    TextStyle { size, color: [1.0, 1.0, 1.0, 1.0], align: TextAlign::Left, max_width: None, line_spacing: 1.0 }
+  }
+
+  pub fn with_color(mut self, rgba: [GLfloat; 4]) -> Self {
+    self.color = rgba;
+    self
+  }
+
+  pub fn with_align(mut self, align: TextAlign) -> Self {
+    self.align = align;
+    self
+  }
+
+  pub fn with_max_width(mut self, max_width: GLfloat) -> Self {
+    self.max_width = Some(max_width);
+    self
+  }
+
+  pub fn with_line_spacing(mut self, line_spacing: GLfloat) -> Self {
+    self.line_spacing = line_spacing;
+    self
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphQuad {
+  pub min: [GLfloat; 2],
+  pub max: [GLfloat; 2],
+  pub uv_min: [GLfloat; 2],
+  pub uv_max: [GLfloat; 2]
+}
+
+// Quads relative to the anchor, x to the right and y down from the top of the first line, in the units of the style's size
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextLayout {
+  pub quads: Vec<GlyphQuad>,
+  pub width: GLfloat,
+  pub height: GLfloat,
+  pub lines: usize
+}
+
+pub fn layout_text(atlas: &FontAtlas, text: &str, style: &TextStyle) -> TextLayout {
+  let scale = style.size / atlas.line_height;
+  let lines = wrap_lines(atlas, text, style.max_width.map(|max_width| max_width / scale));
+  let line_height = atlas.line_height * style.line_spacing;
+  let mut quads = Vec::new();
+  let mut width: GLfloat = 0.0;
+  for (line_index, line) in lines.iter().enumerate() {
+    let line_width = text_width(atlas, line);
+    width = width.max(line_width);
+    let mut x = match style.align {
+      TextAlign::Left => 0.0,
+      TextAlign::Center => -line_width / 2.0,
+      TextAlign::Right => -line_width
+    };
+    let y = line_index as GLfloat * line_height;
+    for c in line.chars() {
+      let glyph = atlas.glyph(c);
+      if glyph.size[0] > 0.0 {
+        let (left, top) = (x + glyph.offset[0], y + glyph.offset[1]);
+        quads.push(GlyphQuad {
+          min: [left * scale, top * scale],
+          max: [(left + glyph.size[0]) * scale, (top + glyph.size[1]) * scale],
+          uv_min: glyph.uv_min,
+          uv_max: glyph.uv_max
+        });
+      }
+      x += glyph.advance;
+    }
+  }
+  let height = if lines.is_empty() { 0.0 } else { (lines.len() - 1) as GLfloat * line_height + atlas.line_height };
+  TextLayout { quads, width: width * scale, height: height * scale, lines: lines.len() }
+}
+
+fn text_width(atlas: &FontAtlas, text: &str) -> GLfloat {
+  text.chars().map(|c| atlas.glyph(c).advance).sum()
+}
+
+// Splits the text at line breaks and, with a max_width in font pixels, wherever the next word wouldn't fit
+fn wrap_lines(atlas: &FontAtlas, text: &str, max_width: Option<GLfloat>) -> Vec<String> {
+  let mut lines = Vec::new();
+  for paragraph in text.split('\n').map(|paragraph| paragraph.trim_end_matches('\r')) {
+    let max_width = match max_width {
+      Some(max_width) => max_width,
+      None => {
+        lines.push(paragraph.to_string());
+        continue;
+      }
+    };
+    let space = atlas.glyph(' ').advance;
+    let mut line = String::new();
+    let mut width = 0.0;
+    for (word_index, word) in paragraph.split(' ').enumerate() {
+      if word_index > 0 && !line.is_empty() {
+        if width + space + text_width(atlas, word) <= max_width {
+          line.push(' ');
+          width += space;
+        } else {
+          lines.push(mem::take(&mut line));
+          width = 0.0;
+        }
+      }
+      // only breaks within words that are longer than a line
+      for c in word.chars() {
+        let advance = atlas.glyph(c).advance;
+        if !line.is_empty() && width + advance > max_width {
+          lines.push(mem::take(&mut line));
+          width = 0.0;
+        }
+        line.push(c);
+        width += advance;
+      }
+    }
+    lines.push(line);
+  }
+  lines
+}
+
+// Where text is drawn, every placement is batched into the same draw call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextPlacement {
+  // pixels from the top left corner of the viewport
+  Screen { x: GLfloat, y: GLfloat },
+  // facing the screen at the projection of a world position and sized in pixels, hidden behind the camera
+  Label(Point3<GLfloat>),
+  // on the xy plane of the model matrix with y up, sized in world units
+  World(Matrix4<GLfloat>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextItem {
+  pub text: String,
+  pub placement: TextPlacement,
+  pub style: TextStyle
+}
+
+impl TextItem {
+  pub fn screen(text: &str, x: GLfloat, y: GLfloat, style: TextStyle) -> TextItem {
+    TextItem { text: text.to_string(), placement: TextPlacement::Screen { x, y }, style }
+  }
+
+  pub fn label(text: &str, position: Point3<GLfloat>, style: TextStyle) -> TextItem {
+    TextItem { text: text.to_string(), placement: TextPlacement::Label(position), style }
+  }
+
+  pub fn world(text: &str, model: Matrix4<GLfloat>, style: TextStyle) -> TextItem {
+    TextItem { text: text.to_string(), placement: TextPlacement::World(model), style }
+  }
+
+  // Labels and world text are placed through the view projection, screen text is not
+  pub fn needs_camera(&self) -> bool {
+    !matches!(self.placement, TextPlacement::Screen { .. })
+  }
+}
+
+crate::vertex_format! {
+  // the position is already in clip space, so that all placements share a program
+  pub struct TextVertex { pub position: [GLfloat; 4], pub uv: [GLfloat; 2], pub color: [GLfloat; 4] }
+}
+
+// Appends two triangles per glyph of the item
+pub fn text_vertices(atlas: &FontAtlas, item: &TextItem, viewport: (GLsizei, GLsizei), view_projection: Matrix4<GLfloat>, vertices: &mut Vec<TextVertex>) {
+  let pixel = (2.0 / viewport.0.max(1) as GLfloat, 2.0 / viewport.1.max(1) as GLfloat);
+  let to_clip: Box<dyn Fn(GLfloat, GLfloat) -> [GLfloat; 4]> = match item.placement {
+    TextPlacement::Screen { x, y } => Box::new(move |lx, ly| [(x + lx) * pixel.0 - 1.0, 1.0 - (y + ly) * pixel.1, -1.0, 1.0]),
+    TextPlacement::Label(position) => {
+      let anchor = view_projection * position.to_homogeneous();
+      if anchor.w <= 0.0 { return; }
+      let ndc = anchor.truncate() / anchor.w;
+      Box::new(move |lx, ly| [ndc.x + lx * pixel.0, ndc.y - ly * pixel.1, ndc.z, 1.0])
+    },
+    TextPlacement::World(model) => {
+      let model_view_projection = view_projection * model;
+      Box::new(move |lx, ly| (model_view_projection * Vector4::new(lx, -ly, 0.0, 1.0)).into())
+    }
+  };
+  let color = item.style.color;
+  for quad in layout_text(atlas, &item.text, &item.style).quads {
+    let corner = |x: usize, y: usize| TextVertex {
+      position: to_clip([quad.min[0], quad.max[0]][x], [quad.min[1], quad.max[1]][y]),
+      uv: [[quad.uv_min[0], quad.uv_max[0]][x], [quad.uv_min[1], quad.uv_max[1]][y]],
+      color
+    };
+    vertices.extend_from_slice(&[corner(0, 0), corner(0, 1), corner(1, 1), corner(0, 0), corner(1, 1), corner(1, 0)]);
+  }
+}
+
+// Draws text over the scene with a font atlas, see TextPlacement
+pub struct TextRenderer {
+  atlas: FontAtlas,
+  texture: Texture,
+  program: ShaderProgram,
+  buffers: BufferComponent,
+  vertex_buffer: DynamicBuffer,
+  vertices: Vec<TextVertex>
+}
+
+impl TextRenderer {
+  pub fn new(atlas: FontAtlas) -> Result<TextRenderer, String> {
+    let texture = atlas.create_texture()?;
+    let program = ShaderProgramBuilder::new()
+      .with_vertex_shader(include_str!("glsl/text/vertex.glsl"))
+      .with_fragment_shader(include_str!("glsl/text/fragment.glsl"))
+      .build();
+    let buffers = VaoBuilder::new().with_vertex::<TextVertex>().build();
+    let vertex_buffer = DynamicBuffer::new(0, size_of::<TextVertex>() / size_of::<GLfloat>(), BufferUsage::Stream);
+    Ok(TextRenderer { atlas, texture, program, buffers, vertex_buffer, vertices: Vec::new() })
+  }
+
+  pub fn atlas(&self) -> &FontAtlas {
+    &self.atlas
+  }
+
+  /// Draws the items with a single call into the framebuffer that is bound and returns how many vertices it took.
+  /// Text is drawn over everything without a depth test, labels and world text included.
+  ///
+  /// # Safety
+  /// Needs the current GL context the renderer was created in.
+  pub unsafe fn draw(&mut self, items: &[TextItem], viewport: (GLsizei, GLsizei), view_projection: Matrix4<GLfloat>, state_cache: &mut RenderStateCache) -> usize {
+    self.vertices.clear();
+    for item in items {
+      text_vertices(&self.atlas, item, viewport, view_projection, &mut self.vertices);
+    }
+    if self.vertices.is_empty() { return 0; }
+    // TextVertex is nothing but floats
+    let floats = slice::from_raw_parts(self.vertices.as_ptr() as *const GLfloat, self.vertices.len() * self.vertex_buffer.floats_per_vertex());
+    self.vertex_buffer.upload(&self.buffers, floats);
+    state_cache.apply(&RenderState::new().with_depth_test(false).with_depth_write(false).with_blend(BlendMode::Alpha));
+    gl::UseProgram(self.program.handle());
+    self.program.set_uniform_if_present("Atlas", (&self.texture, ATLAS_TEXTURE_UNIT));
+    self.program.set_uniform_if_present("DistanceField", matches!(self.atlas.kind, AtlasKind::Sdf { .. }) as GLint);
+    gl::BindVertexArray(self.buffers.vao());
+    gl::DrawArrays(gl::TRIANGLES, 0, self.vertices.len() as GLsizei);
+    gl::BindVertexArray(0);
+    gl_check!("TextRenderer::draw");
+    self.vertices.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cgmath::SquareMatrix;
+
+  fn assert_near(expected: [GLfloat; 4], actual: [GLfloat; 4]) {
+    assert!(expected.iter().zip(actual.iter()).all(|(e, a)| (e - a).abs() < 1e-5), "expected {:?} but got {:?}", expected, actual);
+  }
+
+  #[test]
+  fn lines_are_aligned_to_the_anchor() {
+    // arrange
+    let atlas = FontAtlas::bitmap();
+    let style = TextStyle::new(20.0).with_align(TextAlign::Center);
+    // act
+    let layout = layout_text(&atlas, "ab\nc", &style);
+    // assert
+    assert_eq!(2, layout.lines);
+    assert_eq!(3, layout.quads.len());
+    // glyphs advance 6 font pixels, twice the size for a 20 pixel line
+    assert_eq!(24.0, layout.width);
+    assert_eq!(40.0, layout.height);
+    assert_eq!([-12.0, 2.0], layout.quads[0].min);
+    assert_eq!([-6.0, 22.0], layout.quads[2].min);
+  }
+
+  #[test]
+  fn wraps_at_spaces_and_breaks_long_words() {
+    // arrange
+    let atlas = FontAtlas::bitmap();
+    // act
+    let words = wrap_lines(&atlas, "one two three", Some(48.0));
+    let long_word = wrap_lines(&atlas, "abcdefghij", Some(24.0));
+    let line_breaks = wrap_lines(&atlas, "a\r\n\nb", None);
+    // assert
+    assert_eq!(vec!["one two", "three"], words);
+    assert_eq!(vec!["abcd", "efgh", "ij"], long_word);
+    assert_eq!(vec!["a", "", "b"], line_breaks);
+  }
+
+  #[test]
+  fn max_width_is_in_the_units_of_the_size() {
+    let atlas = FontAtlas::bitmap();
+    let layout = layout_text(&atlas, "one two three", &TextStyle::new(20.0).with_max_width(96.0));
+    assert_eq!(2, layout.lines);
+    assert_eq!(84.0, layout.width);
+  }
+
+  #[test]
+  fn screen_text_is_placed_in_pixels() {
+    // arrange
+    let atlas = FontAtlas::bitmap();
+    let item = TextItem::screen("I", 100.0, 50.0, TextStyle::new(10.0).with_color([1.0, 0.0, 0.0, 1.0]));
+    let mut vertices = Vec::new();
+    // act
+    text_vertices(&atlas, &item, (200, 100), Matrix4::identity(), &mut vertices);
+    // assert
+    assert_eq!(6, vertices.len());
+    // the top left corner of the glyph is a row below the top of the line
+    assert_near([0.0, -0.02, -1.0, 1.0], vertices[0].position);
+    assert_near([0.05, -0.18, -1.0, 1.0], vertices[2].position);
+    assert_eq!([1.0, 0.0, 0.0, 1.0], vertices[5].color);
+  }
+
+  #[test]
+  fn labels_behind_the_camera_are_skipped() {
+    let atlas = FontAtlas::bitmap();
+    let mut flip = Matrix4::identity();
+    flip.w.w = -1.0;
+    let mut vertices = Vec::new();
+    text_vertices(&atlas, &TextItem::label("hidden", Point3::new(0.0, 0.0, 0.0), TextStyle::new(10.0)), (200, 100), flip, &mut vertices);
+    assert!(vertices.is_empty());
+  }
+
+  #[test]
+  fn only_screen_text_does_without_a_camera() {
+    let style = TextStyle::new(10.0);
+    assert!(!TextItem::screen("fps", 0.0, 0.0, style).needs_camera());
+    assert!(TextItem::label("name", Point3::new(0.0, 0.0, 0.0), style).needs_camera());
+    assert!(TextItem::world("sign", Matrix4::identity(), style).needs_camera());
+  }
+}
@@ -0,0 +1,284 @@
+use std::mem;
+use std::path::Path;
+use std::os::raw::c_void;
+use gl::types::*;
+use image::DynamicImage;
+use crate::shader_program::{ SetUniform, UniformSetter };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+  Repeat,
+  MirroredRepeat,
+  ClampToEdge
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+  Nearest,
+  Linear
+}
+
+// Color textures authored in sRGB should be uploaded as Srgb so that sampling returns linear values.
+// Data textures (normal maps, masks, lookup tables) should be Linear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+  Linear,
+  Srgb
+}
+
+// Owns the texture object and deletes it when dropped
+#[derive(Debug, PartialEq)]
+pub struct Texture {
+  pub handle: GLuint,
+  pub width: u32,
+  pub height: u32
+}
+
+impl Texture {
+  // Gives up ownership and returns the handle, deleting the texture is then up to the caller
+  pub fn into_raw(mut self) -> GLuint {
+    mem::replace(&mut self.handle, 0)
+  }
+
+  /// # Safety
+  /// Needs the current GL context the texture was created in.
+  pub unsafe fn bind(&self, unit: GLuint) {
+    bind_texture_unit(unit, self.handle);
+  }
+}
+
+impl Drop for Texture {
+  fn drop(&mut self) {
+    if self.handle != 0 {
+      unsafe { gl::DeleteTextures(1, &self.handle); }
+    }
+  }
+}
+
+/// # Safety
+/// Needs a current GL context, handle has to be 0 or a live texture of it.
+pub unsafe fn bind_texture_unit(unit: GLuint, handle: GLuint) {
+  gl::ActiveTexture(gl::TEXTURE0 + unit);
+  gl::BindTexture(gl::TEXTURE_2D, handle);
+}
+
+// Binds the texture to the texture unit and points the sampler uniform at that unit
+impl<'a> SetUniform<(&'a Texture, GLuint)> for UniformSetter {
+  unsafe fn set(&self, value: (&'a Texture, GLuint)) {
+    let (texture, unit) = value;
+    texture.bind(unit);
+    self.set(unit as GLint);
+  }
+}
+
+// BUILDER
+
+enum TextureSource {
+  Image(DynamicImage),
+  Rgba { width: u32, height: u32, pixels: Vec<u8> }
+}
+
+pub struct TextureBuilder {
+  source: TextureSource,
+  wrap_s: Wrap,
+  wrap_t: Wrap,
+  min_filter: Filter,
+  mag_filter: Filter,
+  mipmaps: bool,
+  color_space: ColorSpace,
+  flip_vertical: bool
+}
+
+impl TextureBuilder {
+  // Loads a PNG or JPEG (or any other format supported by the image crate)
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TextureBuilder, String> {
+    let path = path.as_ref();
+    let image = image::open(path).map_err(|e| format!("could not load texture {}: {}", path.display(), e))?;
+    Ok(TextureBuilder::from_image(image))
+  }
+
+  pub fn from_memory(bytes: &[u8]) -> Result<TextureBuilder, String> {
+    let image = image::load_from_memory(bytes).map_err(|e| format!("could not decode texture: {}", e))?;
+    Ok(TextureBuilder::from_image(image))
+  }
+
+  pub fn from_image(image: DynamicImage) -> TextureBuilder {
+    TextureBuilder::with_source(TextureSource::Image(image))
+  }
+
+  // Raw RGBA8 pixels, rows are expected bottom to top as OpenGL does
+  pub fn from_rgba(width: u32, height: u32, pixels: Vec<u8>) -> TextureBuilder {
+    TextureBuilder::with_source(TextureSource::Rgba { width, height, pixels })
+      .with_flip_vertical(false)
+  }
+
+  fn with_source(source: TextureSource) -> TextureBuilder {
+    TextureBuilder {
+      source,
+      wrap_s: Wrap::Repeat,
+      wrap_t: Wrap::Repeat,
+      min_filter: Filter::Linear,
+      mag_filter: Filter::Linear,
+      mipmaps: true,
+      color_space: ColorSpace::Linear,
+      flip_vertical: true
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+    self.wrap_s = wrap;
+    self.wrap_t = wrap;
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_wrap_st(mut self, wrap_s: Wrap, wrap_t: Wrap) -> Self {
+    self.wrap_s = wrap_s;
+    self.wrap_t = wrap_t;
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_filter(mut self, min_filter: Filter, mag_filter: Filter) -> Self {
+    self.min_filter = min_filter;
+    self.mag_filter = mag_filter;
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_mipmaps(mut self, mipmaps: bool) -> Self {
+    self.mipmaps = mipmaps;
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+    self.color_space = color_space;
+    self
+  }
+
+  // Images are stored top to bottom, OpenGL expects the first row at the bottom
+  #[allow(dead_code)]
+  pub fn with_flip_vertical(mut self, flip_vertical: bool) -> Self {
+    self.flip_vertical = flip_vertical;
+    self
+  }
+
+  pub fn build(self) -> Result<Texture, String> {
+    let (width, height, pixels) = match self.source {
+      TextureSource::Image(image) => {
+        let image = if self.flip_vertical { image.flipv() } else { image };
+        let rgba = image.to_rgba();
+        let (width, height) = rgba.dimensions();
+        (width, height, rgba.into_raw())
+      },
+      TextureSource::Rgba { width, height, pixels } => (width, height, pixels)
+    };
+    if pixels.len() != (width * height * 4) as usize {
+      return Err(format!("expected {} bytes of RGBA data for a {}x{} texture but got {}", width * height * 4, width, height, pixels.len()));
+    }
+    let mut handle: GLuint = 0;
+    unsafe {
+      gl::GenTextures(1, &mut handle);
+      gl::BindTexture(gl::TEXTURE_2D, handle);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl_wrap(self.wrap_s) as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl_wrap(self.wrap_t) as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl_min_filter(self.min_filter, self.mipmaps) as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl_mag_filter(self.mag_filter) as GLint);
+      gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+      gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,                                          // mipmap level
+        gl_internal_format(self.color_space) as GLint,
+        width as GLsizei,
+        height as GLsizei,
+        0,                                          // border, must be 0
+        gl::RGBA,                                   // format of the pixel data
+        gl::UNSIGNED_BYTE,
+        pixels.as_ptr() as *const c_void
+      );
+      if self.mipmaps {
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+      }
+      gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+    gl_check!("TextureBuilder::build");
+    Ok(Texture { handle, width, height })
+  }
+}
+
+fn gl_wrap(wrap: Wrap) -> GLenum {
+  match wrap {
+    Wrap::Repeat => gl::REPEAT,
+    Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+    Wrap::ClampToEdge => gl::CLAMP_TO_EDGE
+  }
+}
+
+fn gl_min_filter(filter: Filter, mipmaps: bool) -> GLenum {
+  match (filter, mipmaps) {
+    (Filter::Nearest, false) => gl::NEAREST,
+    (Filter::Linear, false) => gl::LINEAR,
+    (Filter::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+    (Filter::Linear, true) => gl::LINEAR_MIPMAP_LINEAR
+  }
+}
+
+fn gl_mag_filter(filter: Filter) -> GLenum {
+  match filter {
+    Filter::Nearest => gl::NEAREST,
+    Filter::Linear => gl::LINEAR
+  }
+}
+
+fn gl_internal_format(color_space: ColorSpace) -> GLenum {
+  match color_space {
+    ColorSpace::Linear => gl::RGBA8,
+    ColorSpace::Srgb => gl::SRGB8_ALPHA8
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  #[test]
+  fn min_filter_uses_mipmap_variant_when_mipmapped() {
+    assert_eq!(gl::LINEAR, gl_min_filter(Filter::Linear, false));
+    assert_eq!(gl::LINEAR_MIPMAP_LINEAR, gl_min_filter(Filter::Linear, true));
+    assert_eq!(gl::NEAREST_MIPMAP_NEAREST, gl_min_filter(Filter::Nearest, true));
+  }
+
+  #[test]
+  fn srgb_uses_srgb_internal_format() {
+    assert_eq!(gl::SRGB8_ALPHA8, gl_internal_format(ColorSpace::Srgb));
+    assert_eq!(gl::RGBA8, gl_internal_format(ColorSpace::Linear));
+  }
+
+  #[test]
+  fn dropping_deletes_the_texture_unless_released() {
+    // arrange
+    gl_mock::install();
+    let texture = TextureBuilder::from_rgba(1, 1, vec![255; 4]).with_mipmaps(false).build().unwrap();
+    let released = TextureBuilder::from_rgba(1, 1, vec![255; 4]).with_mipmaps(false).build().unwrap();
+    // act
+    let handle = released.into_raw();
+    drop(texture);
+    // assert
+    assert_ne!(0, handle);
+    assert_eq!(1, gl_mock::count("glDeleteTextures"));
+    assert_eq!(1, gl_mock::live_count("texture"));
+  }
+
+  #[test]
+  fn build_rejects_wrong_pixel_count() {
+    // arrange
+    let builder = TextureBuilder::from_rgba(2, 2, vec![255; 12]);
+    // act
+    let result = builder.build();
+    // assert
+    assert!(result.is_err());
+  }
+}
@@ -0,0 +1,325 @@
+use std::ptr;
+use gl::types::*;
+use cgmath::{ Matrix4, Vector2, Vector3, Vector4 };
+use crate::shader_program::ShaderProgram;
+
+// A type that can be a member of a std140 uniform block
+pub trait Std140Member {
+  // base alignment in bytes
+  const ALIGN: usize;
+  fn write(&self, bytes: &mut Vec<u8>);
+}
+
+fn write_floats(bytes: &mut Vec<u8>, floats: &[GLfloat]) {
+  for float in floats {
+    bytes.extend_from_slice(&float.to_ne_bytes());
+  }
+}
+
+impl Std140Member for GLfloat {
+  const ALIGN: usize = 4;
+  fn write(&self, bytes: &mut Vec<u8>) { write_floats(bytes, &[*self]) }
+}
+
+impl Std140Member for GLint {
+  const ALIGN: usize = 4;
+  fn write(&self, bytes: &mut Vec<u8>) { bytes.extend_from_slice(&self.to_ne_bytes()) }
+}
+
+impl Std140Member for GLuint {
+  const ALIGN: usize = 4;
+  fn write(&self, bytes: &mut Vec<u8>) { bytes.extend_from_slice(&self.to_ne_bytes()) }
+}
+
+// a GLSL bool takes four bytes
+impl Std140Member for bool {
+  const ALIGN: usize = 4;
+  fn write(&self, bytes: &mut Vec<u8>) { (*self as GLuint).write(bytes) }
+}
+
+impl Std140Member for Vector2<GLfloat> {
+  const ALIGN: usize = 8;
+  fn write(&self, bytes: &mut Vec<u8>) { write_floats(bytes, &[self.x, self.y]) }
+}
+
+// aligned like a vec4, but a scalar may follow in the last four bytes
+impl Std140Member for Vector3<GLfloat> {
+  const ALIGN: usize = 16;
+  fn write(&self, bytes: &mut Vec<u8>) { write_floats(bytes, &[self.x, self.y, self.z]) }
+}
+
+impl Std140Member for Vector4<GLfloat> {
+  const ALIGN: usize = 16;
+  fn write(&self, bytes: &mut Vec<u8>) { write_floats(bytes, &[self.x, self.y, self.z, self.w]) }
+}
+
+// four vec4 columns
+impl Std140Member for Matrix4<GLfloat> {
+  const ALIGN: usize = 16;
+  fn write(&self, bytes: &mut Vec<u8>) {
+    let floats: &[GLfloat; 16] = self.as_ref();
+    write_floats(bytes, floats)
+  }
+}
+
+fn round_up(offset: usize, alignment: usize) -> usize {
+  offset.div_ceil(alignment) * alignment
+}
+
+// Lays out the members of a uniform block in the order they are written, with std140 padding
+#[derive(Default)]
+pub struct Std140Writer {
+  bytes: Vec<u8>
+}
+
+impl Std140Writer {
+  pub fn new() -> Self {
+    Std140Writer { bytes: Vec::new() }
+  }
+
+  // where the next member would start without its alignment
+  pub fn offset(&self) -> usize {
+    self.bytes.len()
+  }
+
+  pub fn member<T: Std140Member>(&mut self, value: &T) -> &mut Self {
+    self.align(T::ALIGN);
+    value.write(&mut self.bytes);
+    self
+  }
+
+  // Array elements start at multiples of 16 bytes, so an array of floats takes 16 bytes per float
+  pub fn array<T: Std140Member>(&mut self, values: &[T]) -> &mut Self {
+    for value in values {
+      self.align(16);
+      value.write(&mut self.bytes);
+    }
+    self.align(16);
+    self
+  }
+
+  // The block padded to a multiple of 16 bytes
+  pub fn finish(mut self) -> Vec<u8> {
+    self.align(16);
+    self.bytes
+  }
+
+  fn align(&mut self, alignment: usize) {
+    let offset = round_up(self.bytes.len(), alignment);
+    self.bytes.resize(offset, 0);
+  }
+}
+
+// A Rust struct that mirrors a uniform block, write_std140 writes the members in the order the block declares them
+pub trait Std140 {
+  fn write_std140(&self, writer: &mut Std140Writer);
+
+  fn std140_bytes(&self) -> Vec<u8> {
+    let mut writer = Std140Writer::new();
+    self.write_std140(&mut writer);
+    writer.finish()
+  }
+}
+
+// Owns a uniform buffer that stays bound to its binding point, every program connected
+// to that binding point (see UniformBlockBindings) reads the same data
+pub struct UniformBuffer {
+  handle: GLuint,
+  binding: GLuint,
+  size: usize
+}
+
+impl UniformBuffer {
+  pub fn new(binding: GLuint, size: usize) -> UniformBuffer {
+    let mut handle: GLuint = 0;
+    unsafe {
+      gl::GenBuffers(1, &mut handle);
+      gl::BindBuffer(gl::UNIFORM_BUFFER, handle);
+      gl::BufferData(gl::UNIFORM_BUFFER, size as GLsizeiptr, ptr::null(), gl::DYNAMIC_DRAW);
+      gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+      gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, handle);
+    }
+    UniformBuffer { handle, binding, size }
+  }
+
+  // A buffer with the size of the block
+  pub fn for_block<T: Std140>(binding: GLuint, block: &T) -> UniformBuffer {
+    UniformBuffer::new(binding, block.std140_bytes().len())
+  }
+
+  pub fn handle(&self) -> GLuint { self.handle }
+
+  pub fn binding(&self) -> GLuint { self.binding }
+
+  pub fn size(&self) -> usize { self.size }
+
+  /// # Safety
+  /// Needs the current GL context the buffer was created in.
+  pub unsafe fn update(&self, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > self.size {
+      return Err(format!("{} bytes do not fit into a uniform buffer of {} bytes", bytes.len(), self.size));
+    }
+    gl::BindBuffer(gl::UNIFORM_BUFFER, self.handle);
+    gl::BufferSubData(gl::UNIFORM_BUFFER, 0, bytes.len() as GLsizeiptr, bytes.as_ptr() as *const GLvoid);
+    gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    gl_check!("UniformBuffer::update");
+    Ok(())
+  }
+
+  /// # Safety
+  /// Needs the current GL context the buffer was created in.
+  pub unsafe fn update_block<T: Std140>(&self, block: &T) -> Result<(), String> {
+    self.update(&block.std140_bytes())
+  }
+}
+
+impl Drop for UniformBuffer {
+  fn drop(&mut self) {
+    unsafe { gl::DeleteBuffers(1, &self.handle); }
+  }
+}
+
+// Hands out a binding point per uniform block name and connects programs to the blocks they declare
+#[derive(Default)]
+pub struct UniformBlockBindings {
+  names: Vec<String>
+}
+
+impl UniformBlockBindings {
+  pub fn new() -> Self {
+    UniformBlockBindings { names: Vec::new() }
+  }
+
+  // The binding point of the block, the same name always gets the same binding point
+  pub fn binding(&mut self, name: &str) -> GLuint {
+    match self.names.iter().position(|known| known == name) {
+      Some(index) => index as GLuint,
+      None => {
+        self.names.push(name.to_string());
+        (self.names.len() - 1) as GLuint
+      }
+    }
+  }
+
+  /// Blocks the program does not declare are skipped. The program remembers how far it is connected,
+  /// so calling this again only connects the blocks that were added since.
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn connect(&self, program: &ShaderProgram) {
+    for (binding, name) in self.names.iter().enumerate().skip(program.connected_blocks.get()) {
+      program.bind_uniform_block(name, binding as GLuint);
+    }
+    program.connected_blocks.set(self.names.len());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gl_mock;
+
+  struct Mixed {
+    scale: GLfloat,
+    direction: Vector3<GLfloat>,
+    intensity: GLfloat,
+    offset: Vector2<GLfloat>,
+    weights: [GLfloat; 2]
+  }
+
+  impl Std140 for Mixed {
+    fn write_std140(&self, writer: &mut Std140Writer) {
+      writer.member(&self.scale).member(&self.direction).member(&self.intensity).member(&self.offset).array(&self.weights);
+    }
+  }
+
+  fn float_at(bytes: &[u8], offset: usize) -> GLfloat {
+    GLfloat::from_ne_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+  }
+
+  #[test]
+  fn members_follow_std140_alignment() {
+    // arrange
+    let block = Mixed { scale: 1.0, direction: Vector3::new(2.0, 3.0, 4.0), intensity: 5.0, offset: Vector2::new(6.0, 7.0), weights: [8.0, 9.0] };
+    // act
+    let bytes = block.std140_bytes();
+    // assert: the vec3 starts at 16, the float fills its last four bytes, the vec2 is 8 aligned
+    // and the array elements are 16 bytes apart
+    assert_eq!(1.0, float_at(&bytes, 0));
+    assert_eq!(2.0, float_at(&bytes, 16));
+    assert_eq!(5.0, float_at(&bytes, 28));
+    assert_eq!(6.0, float_at(&bytes, 32));
+    assert_eq!(8.0, float_at(&bytes, 48));
+    assert_eq!(9.0, float_at(&bytes, 64));
+    assert_eq!(80, bytes.len());
+  }
+
+  #[test]
+  fn matrices_are_written_by_column() {
+    let mut writer = Std140Writer::new();
+    writer.member(&1.0f32).member(&Matrix4::from_translation(Vector3::new(2.0, 3.0, 4.0)));
+    let bytes = writer.finish();
+    assert_eq!(80, bytes.len());
+    assert_eq!(2.0, float_at(&bytes, 16 + 48));
+  }
+
+  #[test]
+  fn binding_points_are_stable_per_name() {
+    let mut bindings = UniformBlockBindings::new();
+    assert_eq!(0, bindings.binding("Camera"));
+    assert_eq!(1, bindings.binding("Lights"));
+    assert_eq!(0, bindings.binding("Camera"));
+  }
+
+  #[test]
+  fn connect_skips_blocks_the_program_does_not_declare() {
+    // arrange
+    gl_mock::install();
+    gl_mock::set_uniform_blocks(&["Lights"]);
+    let mut bindings = UniformBlockBindings::new();
+    bindings.binding("Camera");
+    let lights = bindings.binding("Lights");
+    let program = unsafe { ShaderProgram::from_raw(7) };
+    // act
+    unsafe { bindings.connect(&program); }
+    // assert
+    assert_eq!(vec![vec![7, 0, lights as i64]], gl_mock::calls_to("glUniformBlockBinding").into_iter().map(|call| call.args).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn programs_are_only_connected_to_new_blocks() {
+    // arrange
+    gl_mock::install();
+    gl_mock::set_uniform_blocks(&["Camera", "Lights"]);
+    let mut bindings = UniformBlockBindings::new();
+    bindings.binding("Camera");
+    let program = unsafe { ShaderProgram::from_raw(7) };
+    unsafe { bindings.connect(&program); }
+    // act
+    unsafe { bindings.connect(&program); }
+    let lights = bindings.binding("Lights");
+    unsafe { bindings.connect(&program); }
+    // assert: a new program with a reused name starts over
+    let other = unsafe { ShaderProgram::from_raw(program.into_raw()) };
+    unsafe { bindings.connect(&other); }
+    let bound: Vec<i64> = gl_mock::calls_to("glUniformBlockBinding").into_iter().map(|call| call.args[2]).collect();
+    assert_eq!(vec![0, lights as i64, 0, lights as i64], bound);
+  }
+
+  #[test]
+  fn buffer_stays_bound_and_rejects_oversized_updates() {
+    // arrange
+    gl_mock::install();
+    let buffer = UniformBuffer::new(3, 16);
+    // act
+    let fits = unsafe { buffer.update(&[0; 16]) };
+    let too_big = unsafe { buffer.update(&[0; 32]) };
+    // assert
+    assert_eq!(vec![gl::UNIFORM_BUFFER as i64, 3, buffer.handle() as i64], gl_mock::calls_to("glBindBufferBase")[0].args);
+    assert!(fits.is_ok());
+    assert!(too_big.is_err());
+    assert_eq!(1, gl_mock::count("glBufferSubData"));
+    drop(buffer);
+    assert_eq!(0, gl_mock::live_count("buffer"));
+  }
+}
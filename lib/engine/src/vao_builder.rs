@@ -1,82 +1,426 @@
-use std::mem::size_of;
+use std::mem::{ size_of, size_of_val };
 use gl::types::*;
 pub mod attrib_data;
 use self::attrib_data::AttribData;
 pub mod attrib_parameters;
-use self::attrib_parameters::AttribParameters;
+use self::attrib_parameters::{ input_format, AttribParameters, ComponentType, Conversion };
 pub mod buffer_component;
 use self::buffer_component::BufferComponent;
+use crate::vertex::Vertex;
+use crate::shader_program::ShaderProgram;
+use crate::instancing::INSTANCE_MODEL_ATTRIBUTE;
 
+// Attributes come from the vertex buffer that was started last (see with_buffer),
+// except for instance attributes, which have a buffer of their own.
 pub struct VaoBuilder {
   use_indices: bool,
-  attribs: Vec<AttribData>,
+  // the attributes of each vertex buffer, there is always at least one buffer
+  buffers: Vec<Vec<AttribData>>,
+  instance_attribs: Vec<AttribData>,
   next_attrib_location: GLuint
 }
 
+impl Default for VaoBuilder {
+  fn default() -> Self {
+    VaoBuilder::new()
+  }
+}
+
 impl VaoBuilder {
   pub fn new() -> VaoBuilder {
     VaoBuilder {
       use_indices: false,
-      attribs: Vec::new(),
+      buffers: vec![Vec::new()],
+      instance_attribs: Vec::new(),
       next_attrib_location: 0
     }
   }
 
+  /// The layout the vertex shader of the program expects: its inputs interleaved in one vertex buffer
+  /// in order of location, floats for float inputs and 32 bit integers for int and uint inputs.
+  /// An InstanceModel input comes from the instance buffer. Fails for inputs that no buffer can feed.
+  /// Inputs the shader compiler optimized out are not active and take no room in the layout, so callers that
+  /// know how their vertices are laid out should compare with check_vertex_stride.
+  ///
+  /// # Safety
+  /// Needs the current GL context the program was linked in.
+  pub unsafe fn from_program(program: &ShaderProgram) -> Result<VaoBuilder, String> {
+    let mut inputs = Vec::new();
+    let mut instance_location = None;
+    for attribute in program.get_active_attributes() {
+      if attribute.name == INSTANCE_MODEL_ATTRIBUTE {
+        if attribute.gl_type != gl::FLOAT_MAT4 {
+          return Err(format!("{} has to be a mat4", INSTANCE_MODEL_ATTRIBUTE));
+        }
+        instance_location = Some(attribute.location);
+        continue;
+      }
+      let (components, columns, component_type, conversion) = input_format(attribute.gl_type)
+        .ok_or_else(|| format!("Vertex input {} has type {:#x}, which cannot be read from a vertex buffer", attribute.name, attribute.gl_type))?;
+      for i in 0..(columns * attribute.size) as GLuint {
+        inputs.push((attribute.location + i, components, component_type, conversion));
+      }
+    }
+    // the driver reports the inputs in any order, the offsets follow the locations
+    inputs.sort_by_key(|(location, _, _, _)| *location);
+    let stride = inputs.iter().map(|(_, components, component_type, _)| component_type.attribute_size(*components)).sum();
+    let mut builder = VaoBuilder::new();
+    let mut offset = 0;
+    for (location, components, component_type, conversion) in inputs {
+      builder.next_attrib_location = location;
+      builder = builder.try_with_attribute(AttribParameters { components, component_type, conversion, stride, offset, divisor: 0 })?;
+      offset += component_type.attribute_size(components);
+    }
+    if let Some(location) = instance_location {
+      builder.next_attrib_location = location;
+      builder = builder.with_instance_matrix();
+    }
+    Ok(builder)
+  }
+
+  // Bytes per vertex in the first vertex buffer
+  pub fn vertex_stride(&self) -> usize {
+    self.buffers[0].first().map_or(0, |attrib| attrib.params.stride)
+  }
+
+  // An error when the first vertex buffer does not have the stride in bytes the vertex data was written with,
+  // as when the compiler optimized out an input the shader does not use
+  pub fn check_vertex_stride(&self, stride: usize) -> Result<(), String> {
+    if self.vertex_stride() != stride {
+      return Err(format!("The vertex inputs take {} bytes but the vertices have {}, an input may have been optimized out", self.vertex_stride(), stride));
+    }
+    Ok(())
+  }
+
+  // The number of vertices in the floats for the first vertex buffer, an error when its layout
+  // has non-float attributes or the floats do not make up whole vertices
+  pub fn float_vertex_count(&self, floats: &[GLfloat]) -> Result<usize, String> {
+    if let Some(attrib) = self.buffers[0].iter().find(|attrib| attrib.params.component_type != ComponentType::Float) {
+      return Err(format!("The attribute at location {} is not made of floats", attrib.location));
+    }
+    let stride = self.vertex_stride();
+    if stride == 0 {
+      return Err("The layout has no vertex attributes".to_string());
+    }
+    let bytes = size_of_val(floats);
+    if !bytes.is_multiple_of(stride) {
+      return Err(format!("{} floats are no whole number of vertices with {} floats each", floats.len(), stride / size_of::<GLfloat>()));
+    }
+    Ok(bytes / stride)
+  }
+
   #[allow(dead_code)]
   pub fn with_ibo(mut self) -> VaoBuilder {
     self.use_indices = true;
     self
   }
 
+  // Panics on a combination of components, type and conversion that GL does not accept, see try_with_attribute
   #[allow(dead_code)]
-  pub fn with_attribute(mut self, params: AttribParameters) -> VaoBuilder {
-    self.attribs.push(AttribData {
-      location: self.next_attrib_location,
-      floats_per_attribute: params.floats_per_attribute,
-      floats_per_vertex: params.floats_per_vertex,
-      offset: params.offset
-    });
-    self.next_attrib_location = self.next_attrib_location + 1;
+  pub fn with_attribute(self, params: AttribParameters) -> VaoBuilder {
+    self.try_with_attribute(params).unwrap_or_else(|message| panic!("{}", message))
+  }
+
+  // An error instead of a panic for attributes that come from user input
+  pub fn try_with_attribute(mut self, params: AttribParameters) -> Result<VaoBuilder, String> {
+    let attrib = attrib_data(self.next_attrib_location, params)?;
+    self.buffers.last_mut().expect("there is always a vertex buffer").push(attrib);
+    self.next_attrib_location += 1;
+    Ok(self)
+  }
+
+  // Starts another vertex buffer, the following attributes come from it.
+  // Attributes in separate buffers can be updated without touching the others.
+  #[allow(dead_code)]
+  pub fn with_buffer(mut self) -> VaoBuilder {
+    self.buffers.push(Vec::new());
+    self
+  }
+
+  // One attribute per field of the vertex struct, at the next locations
+  #[allow(dead_code)]
+  pub fn with_vertex<V: Vertex>(mut self) -> VaoBuilder {
+    for attribute in V::ATTRIBUTES {
+      self = self.with_attribute(attribute.params(size_of::<V>()));
+    }
+    self
+  }
+
+  // An attribute from the instance buffer that advances once per instance,
+  // stride and offset refer to the data of one instance
+  #[allow(dead_code)]
+  pub fn with_instance_attribute(mut self, params: AttribParameters) -> VaoBuilder {
+    let divisor = params.divisor.max(1);
+    let attrib = attrib_data(self.next_attrib_location, params.per_instance(divisor)).unwrap_or_else(|message| panic!("{}", message));
+    self.instance_attribs.push(attrib);
+    self.next_attrib_location += 1;
+    self
+  }
+
+  // A mat4 per instance, which takes four consecutive locations, one per column
+  #[allow(dead_code)]
+  pub fn with_instance_matrix(mut self) -> VaoBuilder {
+    for column in 0..4 {
+      self = self.with_instance_attribute(AttribParameters::floats(4, 16, column * 4));
+    }
     self
   }
 
   pub fn build(self) -> BufferComponent {
     let mut vao: GLuint = 0; // vertex array object
-    let mut vbo: GLuint = 0; // vertex buffer object
+    let mut vbos: Vec<GLuint> = Vec::new(); // vertex buffer objects
     let mut ibo: GLuint = 0; // index buffer object
+    let mut instance_vbo: GLuint = 0;
     unsafe {
       gl::GenVertexArrays(1, &mut vao);
       gl::BindVertexArray(vao);
-      gl::GenBuffers(1, &mut vbo);
-      gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
       if self.use_indices {
         gl::GenBuffers(1, &mut ibo);
         gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
       }
-      for attrib_data in self.attribs {
-        setup_attribute(attrib_data);
+      for attribs in self.buffers {
+        let mut vbo: GLuint = 0;
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        for attrib_data in attribs {
+          setup_attribute(attrib_data);
+        }
+        vbos.push(vbo);
+      }
+      if !self.instance_attribs.is_empty() {
+        gl::GenBuffers(1, &mut instance_vbo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, instance_vbo);
+        for attrib_data in self.instance_attribs {
+          setup_attribute(attrib_data);
+        }
       }
 
       gl::BindBuffer(gl::ARRAY_BUFFER, 0);
       gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
       gl::BindVertexArray(0);
     }
-    BufferComponent {
-      vao: vao,
-      vbo: vbo,
-      ibo: ibo
-    }
+    gl_check!("VaoBuilder::build");
+    unsafe { BufferComponent::from_raw(vao, vbos, ibo, instance_vbo) }
   }
 }
 
-unsafe fn setup_attribute(attrib: AttribData){
+fn attrib_data(location: GLuint, params: AttribParameters) -> Result<AttribData, String> {
+  params.validate().map_err(|message| format!("invalid attribute at location {}: {}", location, message))?;
+  Ok(AttribData { location, params })
+}
+
+pub(crate) unsafe fn setup_attribute(attrib: AttribData){
+  let params = attrib.params;
   gl::EnableVertexAttribArray(attrib.location); // this is "layout (location = 0)" in vertex shader
-  gl::VertexAttribPointer(
-    attrib.location,   // location
-    attrib.floats_per_attribute,          // number per attribute
-    gl::FLOAT,  // data type
-    gl::FALSE,  // normalized
-    (attrib.floats_per_vertex * size_of::<GLfloat>()) as GLint,  // stride
-    (attrib.offset * size_of::<GLfloat>()) as *const gl::types::GLvoid  // offset
-  );
+  let offset = params.offset as *const gl::types::GLvoid;
+  if params.conversion == Conversion::Integer {
+    gl::VertexAttribIPointer(attrib.location, params.components, params.component_type.gl_enum(), params.stride as GLsizei, offset);
+  } else {
+    gl::VertexAttribPointer(
+      attrib.location,                      // location
+      params.components,                    // number per attribute
+      params.component_type.gl_enum(),      // data type
+      (params.conversion == Conversion::Normalized) as GLboolean,  // normalized
+      params.stride as GLsizei,             // stride in bytes
+      offset                                // offset in bytes
+    );
+  }
+  if params.divisor != 0 {
+    gl::VertexAttribDivisor(attrib.location, params.divisor);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::attrib_parameters::ComponentType;
+  use crate::gl_mock;
+
+  #[test]
+  fn instance_matrix_follows_the_vertex_attributes() {
+    // arrange
+    gl_mock::install();
+    // act
+    let buffers = VaoBuilder::new()
+      .with_attribute(AttribParameters::floats(3, 7, 0))
+      .with_attribute(AttribParameters::floats(4, 7, 3))
+      .with_instance_matrix()
+      .build();
+    // assert
+    assert_ne!(0, buffers.instance_vbo());
+    assert_ne!(buffers.vbo(), buffers.instance_vbo());
+    let divisors: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribDivisor").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![vec![2, 1], vec![3, 1], vec![4, 1], vec![5, 1]], divisors);
+    // location, size, type, normalized, stride and offset in bytes
+    let float = gl::FLOAT as i64;
+    let pointers: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribPointer").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![0, 3, float, 0, 28, 0], pointers[0]);
+    assert_eq!(vec![3, 4, float, 0, 64, 16], pointers[3]);
+  }
+
+  #[test]
+  fn vertex_struct_fields_become_attributes() {
+    // arrange
+    gl_mock::install();
+    // act
+    VaoBuilder::new().with_vertex::<crate::vertex::PosColor>().build();
+    // assert
+    let pointers: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribPointer").into_iter().map(|call| call.args).collect();
+    let float = gl::FLOAT as i64;
+    assert_eq!(vec![vec![0, 3, float, 0, 28, 0], vec![1, 4, float, 0, 28, 12]], pointers);
+  }
+
+  #[test]
+  fn compact_attributes_keep_their_type() {
+    // arrange: u16 position, u8 color, packed normal and an integer id in 20 bytes
+    gl_mock::install();
+    // act
+    VaoBuilder::new()
+      .with_attribute(AttribParameters::new(3, ComponentType::UnsignedShort, 20, 0))
+      .with_attribute(AttribParameters::new(4, ComponentType::UnsignedByte, 20, 6).normalized())
+      .with_attribute(AttribParameters::new(4, ComponentType::Int2101010Rev, 20, 10).normalized())
+      .with_attribute(AttribParameters::new(1, ComponentType::UnsignedShort, 20, 14).integer())
+      .build();
+    // assert
+    let pointers: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribPointer").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![
+      vec![0, 3, gl::UNSIGNED_SHORT as i64, 0, 20, 0],
+      vec![1, 4, gl::UNSIGNED_BYTE as i64, 1, 20, 6],
+      vec![2, 4, gl::INT_2_10_10_10_REV as i64, 1, 20, 10]
+    ], pointers);
+    assert_eq!(vec![3, 1, gl::UNSIGNED_SHORT as i64, 20, 14], gl_mock::calls_to("glVertexAttribIPointer")[0].args);
+  }
+
+  #[test]
+  #[should_panic(expected = "cannot be read as integers")]
+  fn float_components_cannot_be_integer_attributes() {
+    VaoBuilder::new().with_attribute(AttribParameters::floats(2, 2, 0).integer());
+  }
+
+  #[test]
+  fn invalid_attributes_can_be_rejected_without_a_panic() {
+    // act
+    let result = VaoBuilder::new()
+      .with_attribute(AttribParameters::floats(3, 5, 0))
+      .try_with_attribute(AttribParameters::new(3, ComponentType::Int2101010Rev, 5, 3));
+    // assert
+    let message = result.err().expect("packed formats need four components");
+    assert!(message.starts_with("invalid attribute at location 1:"), "{}", message);
+  }
+
+  #[test]
+  fn layout_follows_the_program_inputs() {
+    // arrange: the inputs are reported out of order and with a gap at location 2
+    gl_mock::install();
+    gl_mock::set_attributes(&[
+      ("Id", 3, gl::UNSIGNED_INT, 1),
+      ("Position", 0, gl::FLOAT_VEC3, 1),
+      (INSTANCE_MODEL_ATTRIBUTE, 4, gl::FLOAT_MAT4, 1),
+      ("Color", 1, gl::FLOAT_VEC4, 1)
+    ]);
+    let program = unsafe { ShaderProgram::from_raw(1) };
+    // act
+    let builder = unsafe { VaoBuilder::from_program(&program) }.expect("inputs should have a layout");
+    let stride = builder.vertex_stride();
+    builder.build();
+    // assert
+    assert_eq!(32, stride);
+    let pointers: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribPointer").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![0, 3, gl::FLOAT as i64, 0, 32, 0], pointers[0]);
+    assert_eq!(vec![1, 4, gl::FLOAT as i64, 0, 32, 12], pointers[1]);
+    assert_eq!(vec![3, 1, gl::UNSIGNED_INT as i64, 32, 28], gl_mock::calls_to("glVertexAttribIPointer")[0].args);
+    let divisors: Vec<Vec<i64>> = gl_mock::calls_to("glVertexAttribDivisor").into_iter().map(|call| call.args).collect();
+    assert_eq!(vec![vec![4, 1], vec![5, 1], vec![6, 1], vec![7, 1]], divisors);
+  }
+
+  #[test]
+  fn vertex_data_has_to_match_the_program_inputs() {
+    // arrange
+    gl_mock::install();
+    gl_mock::set_attributes(&[("Position", 0, gl::FLOAT_VEC3, 1), ("Color", 1, gl::FLOAT_VEC4, 1)]);
+    let program = unsafe { ShaderProgram::from_raw(1) };
+    // act
+    let builder = unsafe { VaoBuilder::from_program(&program) }.expect("inputs should have a layout");
+    // assert
+    assert_eq!(Ok(2), builder.float_vertex_count(&[0.0; 14]));
+    assert!(builder.float_vertex_count(&[0.0; 10]).is_err());
+    assert_eq!(Ok(()), builder.check_vertex_stride(28));
+    assert!(builder.check_vertex_stride(40).is_err());
+    gl_mock::set_attributes(&[("Position", 0, gl::DOUBLE_VEC3, 1)]);
+    assert!(unsafe { VaoBuilder::from_program(&program) }.is_err());
+    gl_mock::set_attributes(&[("Id", 0, gl::INT, 1)]);
+    assert!(unsafe { VaoBuilder::from_program(&program) }.expect("ints have a layout").float_vertex_count(&[0.0; 2]).is_err());
+  }
+
+  #[test]
+  fn no_instance_buffer_without_instance_attributes() {
+    gl_mock::install();
+    let buffers = VaoBuilder::new()
+      .with_attribute(AttribParameters::floats(3, 3, 0))
+      .build();
+    assert_eq!(0, buffers.instance_vbo());
+    assert_eq!(0, gl_mock::count("glVertexAttribDivisor"));
+    assert_eq!(1, gl_mock::live_count("buffer"));
+  }
+
+  #[test]
+  fn attributes_come_from_the_buffer_started_last() {
+    // arrange
+    gl_mock::install();
+    // act: positions, colors and a per-instance offset in three buffers
+    let buffers = VaoBuilder::new()
+      .with_attribute(AttribParameters::floats(3, 3, 0))
+      .with_buffer()
+      .with_attribute(AttribParameters::new(4, ComponentType::UnsignedByte, 4, 0).normalized())
+      .with_buffer()
+      .with_attribute(AttribParameters::floats(2, 2, 0).per_instance(1))
+      .build();
+    // assert: each attribute pointer is set while its buffer is bound
+    assert_eq!(3, buffers.vbos().len());
+    let array_buffer_at_pointer: Vec<i64> = gl_mock::calls().iter()
+      .filter(|call| call.name == "glBindBuffer" || call.name == "glVertexAttribPointer")
+      .scan(0, |bound, call| {
+        if call.name == "glBindBuffer" { *bound = call.args[1]; Some(None) } else { Some(Some(*bound)) }
+      })
+      .flatten()
+      .collect();
+    assert_eq!(buffers.vbos().iter().map(|vbo| *vbo as i64).collect::<Vec<_>>(), array_buffer_at_pointer);
+    assert_eq!(vec![vec![2, 1]], gl_mock::calls_to("glVertexAttribDivisor").into_iter().map(|call| call.args).collect::<Vec<_>>());
+    assert_eq!(0, buffers.vbo_at(3));
+    drop(buffers);
+    assert_eq!(0, gl_mock::live_count("buffer"));
+  }
+
+  #[test]
+  fn dropping_the_component_deletes_every_object() {
+    // arrange
+    gl_mock::install();
+    let buffers = VaoBuilder::new()
+      .with_ibo()
+      .with_attribute(AttribParameters::floats(3, 3, 0))
+      .with_instance_matrix()
+      .build();
+    assert_eq!(3, gl_mock::live_count("buffer"));
+    // act
+    drop(buffers);
+    // assert
+    assert_eq!(0, gl_mock::live_count("buffer"));
+    assert_eq!(0, gl_mock::live_count("vertex array"));
+  }
+
+  #[test]
+  fn into_raw_releases_ownership() {
+    // arrange
+    gl_mock::install();
+    let buffers = VaoBuilder::new().build();
+    // act
+    let (vao, vbos, ibo, instance_vbo) = buffers.into_raw();
+    // assert
+    assert_ne!(0, vao);
+    assert_eq!(1, vbos.len());
+    assert_ne!(0, vbos[0]);
+    assert_eq!((0, 0), (ibo, instance_vbo));
+    assert_eq!(0, gl_mock::count("glDeleteBuffers"));
+    assert_eq!(1, gl_mock::live_count("vertex array"));
+  }
 }
\ No newline at end of file
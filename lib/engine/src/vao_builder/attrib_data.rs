@@ -1,9 +1,7 @@
-use gl::types::GLint;
 use gl::types::GLuint;
+use super::attrib_parameters::AttribParameters;
 
 pub struct AttribData {
   pub location: GLuint,
-  pub floats_per_attribute: GLint,
-  pub floats_per_vertex: usize,
-  pub offset: usize
-}
\ No newline at end of file
+  pub params: AttribParameters
+}
@@ -1,7 +1,145 @@
-use gl::types::GLint;
+use std::mem::size_of;
+use gl::types::*;
 
+// The type of each component of an attribute in the vertex buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+  Float,
+  HalfFloat,
+  Byte,
+  UnsignedByte,
+  Short,
+  UnsignedShort,
+  Int,
+  UnsignedInt,
+  // x, y, z with 10 bits and w with 2 bits packed into one u32, only for attributes with 4 components
+  Int2101010Rev
+}
+
+impl ComponentType {
+  pub fn gl_enum(self) -> GLenum {
+    match self {
+      ComponentType::Float => gl::FLOAT,
+      ComponentType::HalfFloat => gl::HALF_FLOAT,
+      ComponentType::Byte => gl::BYTE,
+      ComponentType::UnsignedByte => gl::UNSIGNED_BYTE,
+      ComponentType::Short => gl::SHORT,
+      ComponentType::UnsignedShort => gl::UNSIGNED_SHORT,
+      ComponentType::Int => gl::INT,
+      ComponentType::UnsignedInt => gl::UNSIGNED_INT,
+      ComponentType::Int2101010Rev => gl::INT_2_10_10_10_REV
+    }
+  }
+
+  // The bytes of an attribute with this many components
+  pub fn attribute_size(self, components: GLint) -> usize {
+    let component_size = match self {
+      ComponentType::Byte | ComponentType::UnsignedByte => 1,
+      ComponentType::HalfFloat | ComponentType::Short | ComponentType::UnsignedShort => 2,
+      ComponentType::Float | ComponentType::Int | ComponentType::UnsignedInt => 4,
+      ComponentType::Int2101010Rev => return 4
+    };
+    component_size * components as usize
+  }
+
+  fn is_integer(self) -> bool {
+    !matches!(self, ComponentType::Float | ComponentType::HalfFloat | ComponentType::Int2101010Rev)
+  }
+}
+
+// How the shader sees the components
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conversion {
+  // converted to float as they are, e.g. 255u8 becomes 255.0
+  Float,
+  // mapped to 0..1 for unsigned and -1..1 for signed types, e.g. 255u8 becomes 1.0
+  Normalized,
+  // kept as integers, the shader input has to be int, uint or an ivec/uvec (glVertexAttribIPointer)
+  Integer
+}
+
+// Stride and offset are in bytes, within the vertex buffer the attribute comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct AttribParameters {
-  pub floats_per_attribute: GLint,
-  pub floats_per_vertex: usize,
-  pub offset: usize
+  pub components: GLint,
+  pub component_type: ComponentType,
+  pub conversion: Conversion,
+  pub stride: usize,
+  pub offset: usize,
+  // 0 advances per vertex, n advances once every n instances
+  pub divisor: GLuint
+}
+
+impl AttribParameters {
+  pub fn new(components: GLint, component_type: ComponentType, stride: usize, offset: usize) -> Self {
+    AttribParameters { components, component_type, conversion: Conversion::Float, stride, offset, divisor: 0 }
+  }
+
+  // A float attribute in a buffer of floats, with the vertex size and offset counted in floats
+  pub fn floats(floats_per_attribute: GLint, floats_per_vertex: usize, offset: usize) -> Self {
+    let float_size = size_of::<GLfloat>();
+    AttribParameters::new(floats_per_attribute, ComponentType::Float, floats_per_vertex * float_size, offset * float_size)
+  }
+
+  pub fn normalized(mut self) -> Self {
+    self.conversion = Conversion::Normalized;
+    self
+  }
+
+  pub fn integer(mut self) -> Self {
+    self.conversion = Conversion::Integer;
+    self
+  }
+
+  // Advances once every divisor instances instead of once per vertex
+  pub fn per_instance(mut self, divisor: GLuint) -> Self {
+    self.divisor = divisor;
+    self
+  }
+
+  // The combinations that glVertexAttribPointer and glVertexAttribIPointer accept
+  pub fn validate(&self) -> Result<(), String> {
+    if self.components < 1 || self.components > 4 {
+      return Err(format!("An attribute has 1 to 4 components, not {}", self.components));
+    }
+    if self.component_type == ComponentType::Int2101010Rev && self.components != 4 {
+      return Err("A packed 2_10_10_10 attribute has 4 components".to_string());
+    }
+    if self.conversion == Conversion::Integer && !self.component_type.is_integer() {
+      return Err(format!("{:?} components cannot be read as integers", self.component_type));
+    }
+    Ok(())
+  }
+}
+
+// How a vertex shader input of this type is fed from a vertex buffer: components per location, the number
+// of locations (one per matrix column), the component type and the conversion. None for double inputs.
+pub fn input_format(gl_type: GLenum) -> Option<(GLint, GLint, ComponentType, Conversion)> {
+  let float = |components, columns| Some((components, columns, ComponentType::Float, Conversion::Float));
+  let integer = |components, component_type| Some((components, 1, component_type, Conversion::Integer));
+  match gl_type {
+    gl::FLOAT => float(1, 1),
+    gl::FLOAT_VEC2 => float(2, 1),
+    gl::FLOAT_VEC3 => float(3, 1),
+    gl::FLOAT_VEC4 => float(4, 1),
+    // a matCxR has C columns of R components
+    gl::FLOAT_MAT2 => float(2, 2),
+    gl::FLOAT_MAT3 => float(3, 3),
+    gl::FLOAT_MAT4 => float(4, 4),
+    gl::FLOAT_MAT2x3 => float(3, 2),
+    gl::FLOAT_MAT2x4 => float(4, 2),
+    gl::FLOAT_MAT3x2 => float(2, 3),
+    gl::FLOAT_MAT3x4 => float(4, 3),
+    gl::FLOAT_MAT4x2 => float(2, 4),
+    gl::FLOAT_MAT4x3 => float(3, 4),
+    gl::INT => integer(1, ComponentType::Int),
+    gl::INT_VEC2 => integer(2, ComponentType::Int),
+    gl::INT_VEC3 => integer(3, ComponentType::Int),
+    gl::INT_VEC4 => integer(4, ComponentType::Int),
+    gl::UNSIGNED_INT => integer(1, ComponentType::UnsignedInt),
+    gl::UNSIGNED_INT_VEC2 => integer(2, ComponentType::UnsignedInt),
+    gl::UNSIGNED_INT_VEC3 => integer(3, ComponentType::UnsignedInt),
+    gl::UNSIGNED_INT_VEC4 => integer(4, ComponentType::UnsignedInt),
+    _ => None
+  }
 }
@@ -1,7 +1,55 @@
+use std::mem;
 use gl::types::GLuint;
 
+// Owns a vertex array and its buffers, they are deleted when the component is dropped.
+// vbos holds the vertex buffers in the order VaoBuilder::with_buffer added them, the first one always exists.
+// The ibo and instance_vbo are 0 when the vertex array has none.
 pub struct BufferComponent {
-  pub vao: GLuint,
-  pub vbo: GLuint,
-  pub ibo: GLuint
-}
\ No newline at end of file
+  vao: GLuint,
+  vbos: Vec<GLuint>,
+  ibo: GLuint,
+  instance_vbo: GLuint
+}
+
+impl BufferComponent {
+  /// Takes ownership of the objects
+  ///
+  /// # Safety
+  /// The objects have to be live in the current GL context and nothing else may delete them.
+  pub unsafe fn from_raw(vao: GLuint, vbos: Vec<GLuint>, ibo: GLuint, instance_vbo: GLuint) -> Self {
+    BufferComponent { vao, vbos, ibo, instance_vbo }
+  }
+
+  pub fn vao(&self) -> GLuint { self.vao }
+
+  // The first vertex buffer
+  pub fn vbo(&self) -> GLuint { self.vbo_at(0) }
+
+  // 0 when there is no vertex buffer with this index
+  pub fn vbo_at(&self, index: usize) -> GLuint { self.vbos.get(index).cloned().unwrap_or(0) }
+
+  pub fn vbos(&self) -> &[GLuint] { &self.vbos }
+
+  pub fn ibo(&self) -> GLuint { self.ibo }
+
+  pub fn instance_vbo(&self) -> GLuint { self.instance_vbo }
+
+  // Gives up ownership and returns (vao, vbos, ibo, instance_vbo), deleting them is then up to the caller
+  pub fn into_raw(mut self) -> (GLuint, Vec<GLuint>, GLuint, GLuint) {
+    let take = |name: &mut GLuint| mem::replace(name, 0);
+    (take(&mut self.vao), mem::take(&mut self.vbos), take(&mut self.ibo), take(&mut self.instance_vbo))
+  }
+}
+
+impl Drop for BufferComponent {
+  fn drop(&mut self) {
+    unsafe {
+      for buffer in self.vbos.iter().chain([self.ibo, self.instance_vbo].iter()).filter(|buffer| **buffer != 0) {
+        gl::DeleteBuffers(1, buffer);
+      }
+      if self.vao != 0 {
+        gl::DeleteVertexArrays(1, &self.vao);
+      }
+    }
+  }
+}
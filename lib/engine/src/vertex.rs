@@ -0,0 +1,291 @@
+use std::mem::{ size_of, size_of_val };
+use std::slice;
+use gl::types::*;
+use cgmath::{ Point3, Vector2, Vector3, Vector4 };
+use crate::vao_builder::attrib_parameters::{ input_format, AttribParameters, ComponentType, Conversion };
+use crate::shader_program::{ ActiveAttribute, ShaderProgram };
+use crate::instancing::INSTANCE_MODEL_ATTRIBUTE;
+
+// A field type of a vertex struct, each field becomes one vertex attribute.
+// Integer components are converted to float as they are, wrap them in Normalized or Integer to change that.
+pub trait AttributeType {
+  const COMPONENTS: GLint;
+  const COMPONENT_TYPE: ComponentType;
+  const CONVERSION: Conversion = Conversion::Float;
+}
+
+// Component types that may be normalized or read as integers
+pub trait IntegerComponents: AttributeType {}
+
+macro_rules! attribute_types {
+  ($($component:ty => $component_type:expr),*) => {
+    $(
+      impl AttributeType for $component { const COMPONENTS: GLint = 1; const COMPONENT_TYPE: ComponentType = $component_type; }
+      impl AttributeType for [$component; 1] { const COMPONENTS: GLint = 1; const COMPONENT_TYPE: ComponentType = $component_type; }
+      impl AttributeType for [$component; 2] { const COMPONENTS: GLint = 2; const COMPONENT_TYPE: ComponentType = $component_type; }
+      impl AttributeType for [$component; 3] { const COMPONENTS: GLint = 3; const COMPONENT_TYPE: ComponentType = $component_type; }
+      impl AttributeType for [$component; 4] { const COMPONENTS: GLint = 4; const COMPONENT_TYPE: ComponentType = $component_type; }
+    )*
+  }
+}
+
+macro_rules! integer_components {
+  ($($component:ty),*) => {
+    $(
+      impl IntegerComponents for $component {}
+      impl IntegerComponents for [$component; 1] {}
+      impl IntegerComponents for [$component; 2] {}
+      impl IntegerComponents for [$component; 3] {}
+      impl IntegerComponents for [$component; 4] {}
+    )*
+  }
+}
+
+attribute_types! {
+  GLfloat => ComponentType::Float,
+  GLbyte => ComponentType::Byte,
+  GLubyte => ComponentType::UnsignedByte,
+  GLshort => ComponentType::Short,
+  GLushort => ComponentType::UnsignedShort,
+  GLint => ComponentType::Int,
+  GLuint => ComponentType::UnsignedInt
+}
+
+integer_components!(GLbyte, GLubyte, GLshort, GLushort, GLint, GLuint);
+
+impl AttributeType for Vector2<GLfloat> { const COMPONENTS: GLint = 2; const COMPONENT_TYPE: ComponentType = ComponentType::Float; }
+impl AttributeType for Vector3<GLfloat> { const COMPONENTS: GLint = 3; const COMPONENT_TYPE: ComponentType = ComponentType::Float; }
+impl AttributeType for Vector4<GLfloat> { const COMPONENTS: GLint = 4; const COMPONENT_TYPE: ComponentType = ComponentType::Float; }
+impl AttributeType for Point3<GLfloat> { const COMPONENTS: GLint = 3; const COMPONENT_TYPE: ComponentType = ComponentType::Float; }
+
+// Integer components mapped to 0..1 (unsigned) or -1..1 (signed), e.g. Normalized([255u8, 0, 0, 255]) for red
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Normalized<T>(pub T);
+
+impl<T: IntegerComponents> AttributeType for Normalized<T> {
+  const COMPONENTS: GLint = T::COMPONENTS;
+  const COMPONENT_TYPE: ComponentType = T::COMPONENT_TYPE;
+  const CONVERSION: Conversion = Conversion::Normalized;
+}
+
+// Integer components that reach the shader as int, uint or an ivec/uvec
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Integer<T>(pub T);
+
+impl<T: IntegerComponents> AttributeType for Integer<T> {
+  const COMPONENTS: GLint = T::COMPONENTS;
+  const COMPONENT_TYPE: ComponentType = T::COMPONENT_TYPE;
+  const CONVERSION: Conversion = Conversion::Integer;
+}
+
+// A unit vector in four bytes: x, y and z as signed normalized 10 bit integers, the shader reads a vec4 with w = 0
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedNormal(pub GLuint);
+
+impl PackedNormal {
+  pub fn new(normal: Vector3<GLfloat>) -> Self {
+    let pack = |value: GLfloat| ((value.clamp(-1.0, 1.0) * 511.0).round() as i32 as GLuint) & 0x3ff;
+    PackedNormal(pack(normal.x) | pack(normal.y) << 10 | pack(normal.z) << 20)
+  }
+}
+
+impl AttributeType for PackedNormal {
+  const COMPONENTS: GLint = 4;
+  const COMPONENT_TYPE: ComponentType = ComponentType::Int2101010Rev;
+  const CONVERSION: Conversion = Conversion::Normalized;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+  pub name: &'static str,
+  pub components: GLint,
+  pub component_type: ComponentType,
+  pub conversion: Conversion,
+  // in bytes from the start of the vertex
+  pub offset: usize
+}
+
+impl VertexAttribute {
+  pub fn params(&self, stride: usize) -> AttribParameters {
+    AttribParameters { components: self.components, component_type: self.component_type, conversion: self.conversion, stride, offset: self.offset, divisor: 0 }
+  }
+}
+
+/// A #[repr(C)] struct without padding with one attribute per field, in order of attribute location.
+/// Declare it with vertex_format!, which computes the offsets and rejects fields that are no attribute type.
+///
+/// # Safety
+/// ATTRIBUTES has to describe every byte of the struct, vertex buffers are filled straight from its memory (see as_bytes).
+pub unsafe trait Vertex: Copy {
+  const ATTRIBUTES: &'static [VertexAttribute];
+}
+
+// The vertices as the bytes that go into the vertex buffer
+pub fn as_bytes<V: Vertex>(vertices: &[V]) -> &[u8] {
+  unsafe { slice::from_raw_parts(vertices.as_ptr() as *const u8, size_of_val(vertices)) }
+}
+
+// The positions when the first attribute has at least three float components, for bounds
+pub fn positions<V: Vertex>(vertices: &[V]) -> Vec<Point3<GLfloat>> {
+  match V::ATTRIBUTES.first() {
+    Some(first) if first.component_type == ComponentType::Float && first.conversion == Conversion::Float && first.components >= 3 => {
+      as_bytes(vertices).chunks_exact(size_of::<V>()).map(|vertex| {
+        let float = |i: usize| {
+          let start = first.offset + i * size_of::<GLfloat>();
+          GLfloat::from_ne_bytes([vertex[start], vertex[start + 1], vertex[start + 2], vertex[start + 3]])
+        };
+        Point3::new(float(0), float(1), float(2))
+      }).collect()
+    },
+    _ => Vec::new()
+  }
+}
+
+/// Checks that the vertex struct feeds the inputs of the program's vertex shader, see check_inputs
+///
+/// # Safety
+/// Needs the current GL context the program was linked in.
+pub unsafe fn check_program<V: Vertex>(program: &ShaderProgram) -> Result<(), String> {
+  check_inputs::<V>(&program.get_active_attributes())
+}
+
+// Every input needs a field at its location (VaoBuilder::with_vertex puts the fields at locations 0, 1, ...),
+// and int inputs need Integer fields. The component counts may differ, GL drops the extra components
+// and fills missing ones from (0, 0, 0, 1).
+pub fn check_inputs<V: Vertex>(inputs: &[ActiveAttribute]) -> Result<(), String> {
+  for input in inputs.iter().filter(|input| input.name != INSTANCE_MODEL_ATTRIBUTE) {
+    let (_, columns, _, conversion) = input_format(input.gl_type)
+      .ok_or_else(|| format!("Vertex input {} has type {:#x}, which cannot be read from a vertex buffer", input.name, input.gl_type))?;
+    if columns * input.size != 1 {
+      return Err(format!("Vertex input {} takes several locations, a vertex field fills only one", input.name));
+    }
+    let field = V::ATTRIBUTES.get(input.location as usize)
+      .ok_or_else(|| format!("No vertex field for input {} at location {}", input.name, input.location))?;
+    if (field.conversion == Conversion::Integer) != (conversion == Conversion::Integer) {
+      return Err(format!("Vertex field {} and input {} have to be both integers or both not", field.name, input.name));
+    }
+  }
+  Ok(())
+}
+
+// Declares a vertex struct and implements Vertex for it:
+//   vertex_format! {
+//     pub struct PosColor { pub position: [f32; 3], pub color: [f32; 4] }
+//   }
+#[macro_export]
+macro_rules! vertex_format {
+  ($(#[$meta:meta])* $vis:vis struct $name:ident { $($field_vis:vis $field:ident : $ty:ty),* $(,)? }) => {
+    $(#[$meta])*
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    $vis struct $name {
+      $($field_vis $field: $ty),*
+    }
+
+    unsafe impl $crate::vertex::Vertex for $name {
+      const ATTRIBUTES: &'static [$crate::vertex::VertexAttribute] = &[
+        $($crate::vertex::VertexAttribute {
+          name: stringify!($field),
+          components: <$ty as $crate::vertex::AttributeType>::COMPONENTS,
+          component_type: <$ty as $crate::vertex::AttributeType>::COMPONENT_TYPE,
+          conversion: <$ty as $crate::vertex::AttributeType>::CONVERSION,
+          offset: ::std::mem::offset_of!($name, $field)
+        }),*
+      ];
+    }
+
+    // the fields have to cover the whole struct, padding bytes would be uploaded uninitialized
+    const _: () = assert!(
+      ::std::mem::size_of::<$name>() == 0 $(+ ::std::mem::size_of::<$ty>())*,
+      "vertex fields must not need padding, order them by alignment"
+    );
+  };
+}
+
+// The layouts of the example models, the position comes first so that bounds can be taken from it
+vertex_format! {
+  pub struct PosColor { pub position: [GLfloat; 3], pub color: [GLfloat; 4] }
+}
+
+vertex_format! {
+  pub struct PosUv { pub position: [GLfloat; 3], pub uv: [GLfloat; 2] }
+}
+
+vertex_format! {
+  pub struct PosNormal { pub position: [GLfloat; 3], pub normal: [GLfloat; 3] }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  vertex_format! {
+    struct Mixed { position: Point3<GLfloat>, weight: GLfloat, uv: Vector2<GLfloat> }
+  }
+
+  // 16 bytes instead of 40 for a float position, color and normal
+  vertex_format! {
+    struct Compact { position: [GLushort; 3], id: Integer<GLushort>, color: Normalized<[GLubyte; 4]>, normal: PackedNormal }
+  }
+
+  #[test]
+  fn offsets_follow_the_fields() {
+    // act
+    let attributes = Mixed::ATTRIBUTES;
+    // assert
+    assert_eq!(24, size_of::<Mixed>());
+    assert_eq!(vec![("position", 3, 0), ("weight", 1, 12), ("uv", 2, 16)],
+      attributes.iter().map(|a| (a.name, a.components, a.offset)).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn compact_fields_keep_their_type_and_conversion() {
+    // act
+    let attributes = Compact::ATTRIBUTES;
+    // assert
+    assert_eq!(16, size_of::<Compact>());
+    assert_eq!(vec![
+      (ComponentType::UnsignedShort, Conversion::Float, 0),
+      (ComponentType::UnsignedShort, Conversion::Integer, 6),
+      (ComponentType::UnsignedByte, Conversion::Normalized, 8),
+      (ComponentType::Int2101010Rev, Conversion::Normalized, 12)
+    ], attributes.iter().map(|a| (a.component_type, a.conversion, a.offset)).collect::<Vec<_>>());
+    assert!(positions(&[Compact { position: [1, 2, 3], id: Integer(0), color: Normalized([0; 4]), normal: PackedNormal(0) }]).is_empty());
+  }
+
+  #[test]
+  fn packed_normals_use_signed_ten_bit_components() {
+    assert_eq!(511, PackedNormal::new(Vector3::new(1.0, 0.0, 0.0)).0);
+    assert_eq!(0x201 << 10, PackedNormal::new(Vector3::new(0.0, -1.0, 0.0)).0);
+    assert_eq!(511 << 20, PackedNormal::new(Vector3::new(0.0, 0.0, 2.0)).0);
+  }
+
+  fn input(name: &str, location: GLuint, gl_type: GLenum) -> ActiveAttribute {
+    ActiveAttribute { name: name.to_string(), location, gl_type, size: 1 }
+  }
+
+  #[test]
+  fn inputs_need_a_field_of_the_same_kind() {
+    // arrange
+    let position = input("Position", 0, gl::FLOAT_VEC4);
+    let instance = input(INSTANCE_MODEL_ATTRIBUTE, 2, gl::FLOAT_MAT4);
+    // act & assert: a vec3 field may feed a vec4 input and instance inputs come from elsewhere
+    assert_eq!(Ok(()), check_inputs::<PosColor>(&[position.clone(), input("Color", 1, gl::FLOAT_VEC4), instance]));
+    assert!(check_inputs::<PosColor>(&[position.clone(), input("Id", 1, gl::UNSIGNED_INT)]).is_err());
+    assert!(check_inputs::<PosColor>(&[position.clone(), input("Normal", 2, gl::FLOAT_VEC3)]).is_err());
+    assert!(check_inputs::<Compact>(&[input("Id", 1, gl::UNSIGNED_INT), input("Color", 2, gl::FLOAT_VEC4)]).is_ok());
+  }
+
+  #[test]
+  fn positions_are_read_from_the_first_attribute() {
+    let vertices = [
+      PosColor { position: [1.0, 2.0, 3.0], color: [0.1, 0.2, 0.3, 1.0] },
+      PosColor { position: [4.0, 5.0, 6.0], color: [0.4, 0.5, 0.6, 1.0] }
+    ];
+    assert_eq!(56, as_bytes(&vertices).len());
+    assert_eq!(vec![Point3::new(1.0, 2.0, 3.0), Point3::new(4.0, 5.0, 6.0)], positions(&vertices));
+  }
+}
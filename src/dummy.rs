@@ -1,10 +1,13 @@
 // external crates
 #[macro_use]
 extern crate if_chain;
+#[macro_use]
+extern crate log;
 // use gl::types::*;
 use gl::types::GLfloat;
 use engine::camera;
 use engine::shader_program;
+use engine::material::{ BlendMode, Material, ProgramId };
 // modules
 mod context;
 mod model_creator;
@@ -13,6 +16,8 @@ mod game_state;
 mod game_builder;
 use game_builder::*;
 mod game_state_renderer;
+mod frame_stats;
+mod post_effects;
 
 fn main() -> Result<(), String> {
   start_game()
@@ -23,7 +28,9 @@ fn start_game() -> Result<(), String> {
   let fragment_glsl: &str = include_str!("../src/glsl/fragment.glsl");
   let game_builder = GameBuilder::new()
     .with_shaders(vertex_glsl, fragment_glsl)
-    .with_name("Hello Dummy");
+    .with_name("Hello Dummy")
+    .with_post_effect(post_effects::fxaa())
+    .with_post_effect(post_effects::vignette(0.6));
   let mut game = game_builder.build();
   let vertices: Vec<GLfloat> = vec![
     // X    Y   Z       R     G     B   A
@@ -35,5 +42,13 @@ fn start_game() -> Result<(), String> {
      0.1, -0.1, 0.1,    0.0, 0.0, 1.0, 1.0
   ];
   game.add_model(vertices);
+  let glass: Vec<GLfloat> = vec![
+    // a translucent triangle between the camera and the others
+     0.0,  0.3, -0.2,    1.0, 1.0, 1.0, 0.4,
+    -0.3, -0.3, -0.2,    1.0, 1.0, 1.0, 0.4,
+     0.3, -0.3, -0.2,    1.0, 1.0, 1.0, 0.4
+  ];
+  let glass_material = Material::new(ProgramId(0)).with_blend_mode(BlendMode::Alpha);
+  game.add_model_with_material(glass, glass_material, gl::TRIANGLES);
   game.run()
 }
@@ -3,9 +3,8 @@ use glutin::{EventsLoop, Event, WindowEvent, VirtualKeyCode, ElementState};
 
 pub fn handle_events_loop(mut events_loop: EventsLoop, game: &mut GameState) -> EventsLoop {
   events_loop.poll_events(|event| {
-    match event {
-      Event::WindowEvent{ event, .. } => { handle_window_event(event, game); },
-      _ => ()
+    if let Event::WindowEvent{ event, .. } = event {
+      handle_window_event(event, game);
     }
   });
   events_loop
@@ -24,12 +23,11 @@ fn handle_window_event(event: WindowEvent, game: &mut GameState) {
 fn handle_key_input(input: glutin::KeyboardInput, game: &mut GameState) {
   match input.state {
     ElementState::Pressed => {
-      if let Some(keycode) = input.virtual_keycode
-      {
-        match keycode{
-          VirtualKeyCode::Escape => { game.running = false },
-          _ => ()
-        }
+      match input.virtual_keycode {
+        Some(VirtualKeyCode::Escape) => game.running = false,
+        Some(VirtualKeyCode::F2) => game.frame_stats.export_requested = true,
+        Some(VirtualKeyCode::F3) => game.show_entity_gizmos = !game.show_entity_gizmos,
+        _ => {}
       }
     },
     ElementState::Released => {
@@ -0,0 +1,76 @@
+use std::fs::File;
+use gl::types::*;
+use fbx3d::decode_fbx;
+use fbx3d::types::{ Node, Property };
+
+// Positions and triangle indices of the first geometry in a binary FBX file
+pub struct FbxMesh {
+  pub positions: Vec<[GLfloat; 3]>,
+  pub indices: Vec<u32>
+}
+
+pub fn load_fbx_mesh(path: &str) -> Result<FbxMesh, String> {
+  let mut file = File::open(path).map_err(|e| format!("could not open {}: {}", path, e))?;
+  let nodes = decode_fbx(&mut file).map_err(|e| format!("could not decode {}: {}", path, e))?;
+  let vertices_node = find_node(&nodes, "Vertices").ok_or("FBX file has no Vertices")?;
+  let index_node = find_node(&nodes, "PolygonVertexIndex").ok_or("FBX file has no PolygonVertexIndex")?;
+  let positions = match vertices_node.properties.first() {
+    Some(Property::F64Array(values)) => values.chunks(3).map(|c| [c[0] as GLfloat, c[1] as GLfloat, c[2] as GLfloat]).collect(),
+    _ => return Err("FBX Vertices is not an array of doubles".to_string())
+  };
+  let indices = match index_node.properties.first() {
+    Some(Property::I32Array(values)) => triangulate(values),
+    _ => return Err("FBX PolygonVertexIndex is not an array of ints".to_string())
+  };
+  Ok(FbxMesh { positions, indices })
+}
+
+// fbx3d nests nodes that follow an array property inside it, so search the whole tree
+fn find_node<'a>(nodes: &'a [Node], name: &str) -> Option<&'a Node> {
+  for node in nodes {
+    if node.name == name { return Some(node); }
+    if let Some(found) = find_node(&node.subnodes, name) { return Some(found); }
+  }
+  None
+}
+
+// FBX marks the last index of each polygon by storing it as -(index + 1); polygons are fanned into triangles
+fn triangulate(polygon_indices: &[i32]) -> Vec<u32> {
+  let mut triangles = Vec::new();
+  let mut polygon: Vec<u32> = Vec::new();
+  for raw_index in polygon_indices {
+    let is_last = *raw_index < 0;
+    let index = if is_last { !*raw_index } else { *raw_index };
+    polygon.push(index as u32);
+    if is_last {
+      for i in 1..polygon.len().saturating_sub(1) {
+        triangles.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+      }
+      polygon.clear();
+    }
+  }
+  triangles
+}
+
+#[cfg(test)]
+mod fbx_loader_tests {
+  use super::*;
+
+  #[test]
+  fn triangulate_fans_quads() {
+    // arrange: a triangle followed by a quad
+    let polygon_indices = [0, 1, -3, 3, 4, 5, -7];
+    // act
+    let triangles = triangulate(&polygon_indices);
+    // assert
+    assert_eq!(vec![0, 1, 2, 3, 4, 5, 3, 5, 6], triangles);
+  }
+
+  #[test]
+  fn loads_teapot_as_triangles() {
+    let mesh = load_fbx_mesh("teapot.fbx").expect("teapot.fbx should load");
+    assert_eq!(13974 / 3, mesh.positions.len());
+    assert_eq!(27648, mesh.indices.len());
+    assert!(mesh.indices.iter().all(|i| (*i as usize) < mesh.positions.len()));
+  }
+}
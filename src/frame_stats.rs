@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::io::{ self, Write };
+use std::time::Duration;
+use crate::game_state_renderer::RenderStats;
+
+// ten seconds at 60 Hz
+const DEFAULT_WINDOW_FRAMES: usize = 600;
+// weight of the newest frame in the smoothed frame time
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameSample {
+  pub frame: u64,
+  pub frame_time: Duration,
+  pub render: RenderStats
+}
+
+// Frame times and render counts over a sliding window of the last frames. The game loop records every frame,
+// systems read it from GameState.
+pub struct FrameStats {
+  samples: VecDeque<FrameSample>,
+  window_frames: usize,
+  frame_count: u64,
+  // exponential moving average in seconds
+  smoothed_frame_time: f64,
+  // set by the F2 key, the game loop writes the window to CSV and clears it
+  pub export_requested: bool
+}
+
+impl Default for FrameStats {
+  fn default() -> Self {
+    FrameStats::new(DEFAULT_WINDOW_FRAMES)
+  }
+}
+
+impl FrameStats {
+  pub fn new(window_frames: usize) -> FrameStats {
+    FrameStats {
+      samples: VecDeque::with_capacity(window_frames),
+      window_frames,
+      frame_count: 0,
+      smoothed_frame_time: 0.0,
+      export_requested: false
+    }
+  }
+
+  pub fn record(&mut self, frame_time: Duration, render: RenderStats) {
+    let seconds = frame_time.as_secs_f64();
+    self.smoothed_frame_time = if self.frame_count == 0 { seconds } else { self.smoothed_frame_time + SMOOTHING * (seconds - self.smoothed_frame_time) };
+    self.samples.push_back(FrameSample { frame: self.frame_count, frame_time, render });
+    while self.samples.len() > self.window_frames {
+      self.samples.pop_front();
+    }
+    self.frame_count += 1;
+  }
+
+  // Frames recorded since the start, not only those in the window
+  pub fn frame_count(&self) -> u64 {
+    self.frame_count
+  }
+
+  pub fn last(&self) -> Option<FrameSample> {
+    self.samples.back().cloned()
+  }
+
+  pub fn frame_time(&self) -> Duration {
+    self.last().map_or(Duration::default(), |sample| sample.frame_time)
+  }
+
+  // From the smoothed frame time, so that it doesn't jump with every frame
+  pub fn fps(&self) -> f64 {
+    if self.smoothed_frame_time > 0.0 { 1.0 / self.smoothed_frame_time } else { 0.0 }
+  }
+
+  pub fn min_frame_time(&self) -> Duration {
+    self.samples.iter().map(|sample| sample.frame_time).min().unwrap_or_default()
+  }
+
+  pub fn max_frame_time(&self) -> Duration {
+    self.samples.iter().map(|sample| sample.frame_time).max().unwrap_or_default()
+  }
+
+  // The frame time that percent of the frames in the window don't exceed (nearest rank)
+  pub fn percentile(&self, percent: f64) -> Duration {
+    if self.samples.is_empty() { return Duration::default(); }
+    let mut frame_times: Vec<Duration> = self.samples.iter().map(|sample| sample.frame_time).collect();
+    frame_times.sort();
+    let rank = (percent / 100.0 * frame_times.len() as f64).ceil() as usize;
+    frame_times[rank.clamp(1, frame_times.len()) - 1]
+  }
+
+  pub fn p99(&self) -> Duration {
+    self.percentile(99.0)
+  }
+
+  // One line for the window title
+  pub fn summary(&self) -> String {
+    let render = self.last().map_or(RenderStats::default(), |sample| sample.render);
+    format!("{:.1} fps | {:.2} ms (min {:.2}, max {:.2}, p99 {:.2}) | {} draw calls, {} vertices",
+      self.fps(), milliseconds(self.frame_time()), milliseconds(self.min_frame_time()), milliseconds(self.max_frame_time()),
+      milliseconds(self.p99()), render.draw_calls, render.vertices)
+  }
+
+  // One row per frame in the window, oldest first
+  pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "frame,frame_time_ms,draw_calls,vertices,visible_entities,culled_entities")?;
+    for sample in &self.samples {
+      writeln!(writer, "{},{:.3},{},{},{},{}", sample.frame, milliseconds(sample.frame_time),
+        sample.render.draw_calls, sample.render.vertices, sample.render.visible_entities, sample.render.culled_entities)?;
+    }
+    Ok(())
+  }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod frame_stats_tests {
+  use super::*;
+
+  fn record_millis(stats: &mut FrameStats, frame_times: &[u64]) {
+    for frame_time in frame_times {
+      stats.record(Duration::from_millis(*frame_time), RenderStats::default());
+    }
+  }
+
+  #[test]
+  fn min_max_and_percentile_cover_the_window() {
+    // arrange
+    let mut stats = FrameStats::new(4);
+    // act
+    record_millis(&mut stats, &[50, 10, 20, 16, 17]);
+    // assert
+    assert_eq!(5, stats.frame_count());
+    assert_eq!(Duration::from_millis(17), stats.frame_time());
+    assert_eq!(Duration::from_millis(10), stats.min_frame_time());
+    assert_eq!(Duration::from_millis(20), stats.max_frame_time());
+    assert_eq!(Duration::from_millis(20), stats.p99());
+    assert_eq!(Duration::from_millis(16), stats.percentile(50.0));
+  }
+
+  #[test]
+  fn fps_is_smoothed() {
+    // arrange
+    let mut stats = FrameStats::new(4);
+    record_millis(&mut stats, &[10]);
+    let first = stats.fps();
+    // act
+    record_millis(&mut stats, &[20]);
+    // assert
+    assert!((first - 100.0).abs() < 1e-6);
+    // 10 ms moved a tenth of the way to 20 ms
+    assert!((stats.fps() - 1000.0 / 11.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn csv_has_a_row_per_frame_in_the_window() {
+    // arrange
+    let mut stats = FrameStats::new(2);
+    let render = RenderStats { visible_entities: 3, culled_entities: 1, draw_calls: 4, vertices: 36 };
+    stats.record(Duration::from_millis(5), render);
+    stats.record(Duration::from_micros(16_500), render);
+    stats.record(Duration::from_millis(15), render);
+    let mut csv = Vec::new();
+    // act
+    stats.write_csv(&mut csv).unwrap();
+    // assert
+    assert_eq!("frame,frame_time_ms,draw_calls,vertices,visible_entities,culled_entities\n\
+      1,16.500,4,36,3,1\n\
+      2,15.000,4,36,3,1\n", String::from_utf8(csv).unwrap());
+  }
+}
@@ -1,16 +1,40 @@
 // use gl::types::*;
-use gl::types::{GLfloat, GLenum};
+use gl::types::{GLfloat, GLenum, GLint, GLsizei, GLuint};
 use glutin::{GlContext, GlWindow, EventsLoop};
 use cgmath::{ Rad, Deg, Matrix4, Point3, Vector3 };
 use engine::camera;
 use engine::shader_program;
 use shader_program::{ ShaderProgram, ShaderProgramBuilder };
-use camera::{CameraBuilder, Camera};
+use camera::{CameraBuilder, Camera, pixel_perfect_zoom};
 use crate::context::setup_context;
-use crate::model_creator::add_model;
+use crate::model_creator::{ add_model, add_vertex_model, add_model_with_material, add_model_with_layout, add_model_for_program, add_instanced_model, add_indexed_model, add_instance, add_dynamic_model, add_animated_model };
+use engine::dynamic_buffer::BufferUsage;
+use engine::material::{ Material, ProgramId };
 use crate::game_state::{ GameStateBuilder, GameState };
 use crate::event_handler;
 use crate::game_state_renderer::{ GameStateRenderer };
+use engine::ecs::generational_index::GenerationalIndex;
+use engine::post_process::{ PostProcessEffect, PostProcessStack };
+use engine::hdr::HdrSettings;
+use engine::render_state::RenderState;
+use engine::vertex::Vertex;
+use engine::logging::{ self, LevelFilter };
+use engine::profiler::Profiler;
+use std::fs::File;
+use std::time::Instant;
+use crate::frame_stats::FrameStats;
+use engine::font::FontAtlas;
+use engine::text::{ TextItem, TextStyle };
+use engine::sprite::Sprite;
+use engine::texture::Texture;
+use engine::debug_output::{ enable_debug_output, set_error_checks };
+
+// frames the profiler keeps when only a trace is asked for, a few seconds at 60 Hz
+const DEFAULT_PROFILER_FRAMES: usize = 300;
+// about twice a second with vsync
+const TITLE_UPDATE_FRAMES: u64 = 30;
+// twice the bitmap font's line height keeps its pixels square
+const FPS_OVERLAY_SIZE: GLfloat = 20.0;
 
 pub struct GameBuilder {
   name: String,
@@ -19,7 +43,24 @@ pub struct GameBuilder {
   vertex_glsl: Option<String>,
   fragment_glsl: Option<String>,
   geometry_glsl: Option<String>,
-  mode: GLenum
+  mode: GLenum,
+  shadow_map_size: Option<GLsizei>,
+  post_effects: Vec<PostProcessEffect>,
+  hdr: Option<HdrSettings>,
+  deferred_shading: bool,
+  clear_color: [GLfloat; 4],
+  render_state: RenderState,
+  debug_output: bool,
+  profiler_frames: Option<usize>,
+  chrome_trace: Option<String>,
+  frame_stats_in_title: bool,
+  frame_stats_csv: String,
+  font: Option<FontAtlas>,
+  fps_overlay: bool,
+  debug_lines: Option<bool>,
+  sprites: bool,
+  // pixels per unit and the reference resolution in pixels
+  pixel_perfect_camera: Option<(GLfloat, (u32, u32))>
 }
 
 impl GameBuilder {
@@ -31,7 +72,23 @@ impl GameBuilder {
       vertex_glsl: None,
       fragment_glsl: None,
       geometry_glsl: None,
-      mode: gl::TRIANGLES
+      mode: gl::TRIANGLES,
+      shadow_map_size: None,
+      post_effects: Vec::new(),
+      hdr: None,
+      deferred_shading: false,
+      clear_color: [0.0, 154.0/255.0, 206.0/255.0, 235.0/255.0],
+      render_state: RenderState::default(),
+      debug_output: cfg!(debug_assertions),
+      profiler_frames: None,
+      chrome_trace: None,
+      frame_stats_in_title: false,
+      frame_stats_csv: "frame_stats.csv".to_string(),
+      font: None,
+      fps_overlay: false,
+      debug_lines: None,
+      sprites: false,
+      pixel_perfect_camera: None
     }
   }
 
@@ -67,30 +124,202 @@ impl GameBuilder {
     self
   }
 
-  // todo: pub with_clear_color() and other gl settings
+  // Shadow maps for shadow casting directional lights, size is the width and height in texels
+  #[allow(dead_code)]
+  pub fn with_shadows(mut self, size: GLsizei) -> Self {
+    self.shadow_map_size = Some(size);
+    self
+  }
+
+  // Adds a full-screen pass after the scene is drawn, passes run in the order they are added
+  #[allow(dead_code)]
+  pub fn with_post_effect(mut self, effect: PostProcessEffect) -> Self {
+    self.post_effects.push(effect);
+    self
+  }
+
+  // Entities whose program writes the G-buffer (see engine::deferred) are lit by light volumes,
+  // entities with other programs are still drawn forward
+  #[allow(dead_code)]
+  pub fn with_deferred_shading(mut self) -> Self {
+    self.deferred_shading = true;
+    self
+  }
+
+  // Draws the scene into a floating point target that is tonemapped (and bloomed) before the post effects
+  #[allow(dead_code)]
+  pub fn with_hdr(mut self, settings: HdrSettings) -> Self {
+    self.hdr = Some(settings);
+    self
+  }
+
+  #[allow(dead_code)]
+  pub fn with_clear_color(mut self, rgba: [GLfloat; 4]) -> Self {
+    self.clear_color = rgba;
+    self
+  }
+
+  // Render state for entities without a material, materials carry their own
+  #[allow(dead_code)]
+  pub fn with_render_state(mut self, render_state: RenderState) -> Self {
+    self.render_state = render_state;
+    self
+  }
+
+  // Logs GL debug messages and the errors after engine calls, on by default in debug builds
+  #[allow(dead_code)]
+  pub fn with_debug_output(mut self, enabled: bool) -> Self {
+    self.debug_output = enabled;
+    self
+  }
+
+  // Times the systems, the draw and each render pass on the CPU and the GPU, keeps the last history_frames frames.
+  // Their statistics are printed when the game loop ends.
+  #[allow(dead_code)]
+  pub fn with_profiler(mut self, history_frames: usize) -> Self {
+    self.profiler_frames = Some(history_frames);
+    self
+  }
+
+  // Writes the frames the profiler kept to a trace_event JSON file when the game loop ends, for chrome://tracing or Perfetto
+  #[allow(dead_code)]
+  pub fn with_chrome_trace(mut self, path: &str) -> Self {
+    self.profiler_frames = self.profiler_frames.or(Some(DEFAULT_PROFILER_FRAMES));
+    self.chrome_trace = Some(path.to_string());
+    self
+  }
+
+  // Appends the FPS, frame times and draw counts to the window title a few times per second
+  #[allow(dead_code)]
+  pub fn with_frame_stats_in_title(mut self) -> Self {
+    self.frame_stats_in_title = true;
+    self
+  }
+
+  // Where F2 writes the frame stats of the last frames
+  #[allow(dead_code)]
+  pub fn with_frame_stats_csv(mut self, path: &str) -> Self {
+    self.frame_stats_csv = path.to_string();
+    self
+  }
+
+  // Draws the texts that systems queue in GameState::texts with the font
+  #[allow(dead_code)]
+  pub fn with_font(mut self, atlas: FontAtlas) -> Self {
+    self.font = Some(atlas);
+    self
+  }
+
+  // Draws the frame stats in the top left corner, with the bundled bitmap font unless there is another one
+  #[allow(dead_code)]
+  pub fn with_fps_overlay(mut self) -> Self {
+    self.fps_overlay = true;
+    self
+  }
+
+  // Draws the lines that systems add to GameState::debug after the scene, with depth_test they are hidden behind it.
+  // F3 adds the bounds and axes of every entity.
+  #[allow(dead_code)]
+  pub fn with_debug_lines(mut self, depth_test: bool) -> Self {
+    self.debug_lines = Some(depth_test);
+    self
+  }
+
+  // Draws the sprites in GameState::sprites after the forward pass, see Game::add_sprite
+  #[allow(dead_code)]
+  pub fn with_sprites(mut self) -> Self {
+    self.sprites = true;
+    self
+  }
+
+  // A 2D camera looking down -z at the origin, where a world unit covers pixels_per_unit texels. They are scaled up
+  // by the largest whole number at which the reference resolution fits into the window, see Projection::PixelPerfect.
+  #[allow(dead_code)]
+  pub fn with_pixel_perfect_camera(mut self, pixels_per_unit: GLfloat, reference_width: u32, reference_height: u32) -> Self {
+    self.pixel_perfect_camera = Some((pixels_per_unit, (reference_width, reference_height)));
+    self
+  }
 
-  pub fn build(self) -> Game {
-    let (window, events_loop) = setup_context(&self.name, 1600, 900);
-    unsafe {
-      gl::ClearColor(0.0, 154.0/255.0, 206.0/255.0, 235.0/255.0);
-      gl::Enable(gl::DEPTH_TEST);
+  pub fn build(mut self) -> Game {
+    logging::init(LevelFilter::Info);
+    let (window, events_loop) = setup_context(&self.name, self.width, self.height, self.debug_output);
+    if self.debug_output {
+      if let Err(message) = unsafe { enable_debug_output() } {
+        warn!("Debug output disabled: {}", message);
+      }
+      set_error_checks(true);
+    }
+    let mut renderer = GameStateRenderer::new(self.mode);
+    renderer.set_viewport_size(self.width as GLsizei, self.height as GLsizei);
+    renderer.set_clear_color(self.clear_color);
+    renderer.set_default_render_state(self.render_state);
+    if self.fps_overlay && self.font.is_none() {
+      self.font = Some(FontAtlas::bitmap());
+    }
+    if let Some(atlas) = self.font.take() {
+      if let Err(message) = renderer.enable_text(atlas) {
+        warn!("Text disabled: {}", message);
+      }
+    }
+    if let Some(depth_test) = self.debug_lines {
+      renderer.enable_debug_lines(depth_test);
+    }
+    if self.sprites {
+      renderer.enable_sprites();
+    }
+    if let Some(history_frames) = self.profiler_frames {
+      renderer.set_profiler(Profiler::new(history_frames, true));
+    }
+    if let Some(size) = self.shadow_map_size {
+      if let Err(message) = renderer.enable_shadows(size, 10.0) {
+        warn!("Shadows disabled: {}", message);
+      }
+    }
+    if self.deferred_shading {
+      if let Err(message) = renderer.enable_deferred_shading() {
+        warn!("Deferred shading disabled: {}", message);
+      }
     }
-    let renderer = GameStateRenderer::new(self.mode);
+    let post_process = if self.post_effects.is_empty() && self.hdr.is_none() { None } else {
+      match PostProcessStack::new(self.width as GLsizei, self.height as GLsizei, self.hdr, &self.post_effects) {
+        Ok(stack) => Some(stack),
+        Err(message) => {
+          warn!("Post-processing disabled: {}", message);
+          None
+        }
+      }
+    };
+    let chrome_trace = self.chrome_trace.clone();
+    let title = if self.frame_stats_in_title { Some(self.name.clone()) } else { None };
+    let frame_stats_csv = self.frame_stats_csv.clone();
+    let fps_overlay = self.fps_overlay;
     let game_state = build_game_state(self);
     Game {
       window,
       events_loop,
       game_state,
-      renderer
+      renderer,
+      post_process,
+      chrome_trace,
+      title,
+      frame_stats_csv,
+      fps_overlay
     }
   }
 }
 
+// Fields are dropped in order, the GL objects have to go before the window takes the context with it
 pub struct Game {
-  pub window: GlWindow, 
-  pub events_loop: EventsLoop, 
   pub game_state: GameState,
-  pub renderer: GameStateRenderer
+  pub renderer: GameStateRenderer,
+  pub post_process: Option<PostProcessStack>,
+  pub chrome_trace: Option<String>,
+  // the window title the frame stats are appended to, None leaves the title alone
+  pub title: Option<String>,
+  pub frame_stats_csv: String,
+  pub fps_overlay: bool,
+  pub window: GlWindow,
+  pub events_loop: EventsLoop
 }
 
 impl Game {
@@ -99,6 +328,66 @@ impl Game {
     add_model(&mut self.game_state, vertices);
   }
 
+  #[allow(dead_code)]
+  pub fn add_vertex_model<V: Vertex>(&mut self, vertices: &[V]) -> GenerationalIndex {
+    add_vertex_model(&mut self.game_state, vertices)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_shader_program(&mut self, program: ShaderProgram) -> ProgramId {
+    self.game_state.add_shader_program(program)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_model_with_material(&mut self, vertices: Vec<GLfloat>, material: Material, mode: GLenum) -> GenerationalIndex {
+    add_model_with_material(&mut self.game_state, vertices, material, mode)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_model_with_layout(&mut self, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum) -> GenerationalIndex {
+    add_model_with_layout(&mut self.game_state, vertices, layout, material, mode)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_model_for_program(&mut self, vertices: Vec<GLfloat>, floats_per_vertex: usize, material: Material, mode: GLenum) -> Result<GenerationalIndex, String> {
+    add_model_for_program(&mut self.game_state, vertices, floats_per_vertex, material, mode)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_instanced_model(&mut self, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum) -> GenerationalIndex {
+    add_instanced_model(&mut self.game_state, vertices, layout, material, mode)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_indexed_model(&mut self, vertices: Vec<GLfloat>, indices: &[GLuint], layout: &[GLint], material: Material, mode: GLenum) -> Result<GenerationalIndex, String> {
+    add_indexed_model(&mut self.game_state, vertices, indices, layout, material, mode)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_instance(&mut self, mesh_entity: GenerationalIndex, model_matrix: Matrix4<GLfloat>) -> Option<GenerationalIndex> {
+    add_instance(&mut self.game_state, mesh_entity, model_matrix)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_dynamic_model(&mut self, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum, usage: BufferUsage) -> GenerationalIndex {
+    add_dynamic_model(&mut self.game_state, vertices, layout, material, mode, usage)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_texture(&mut self, texture: Texture) -> &Texture {
+    self.game_state.add_texture(texture)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_sprite(&mut self, sprite: Sprite) -> GenerationalIndex {
+    self.game_state.add_sprite(sprite)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_animated_model(&mut self, positions: Vec<GLfloat>, attributes: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum, usage: BufferUsage) -> Result<GenerationalIndex, String> {
+    add_animated_model(&mut self.game_state, positions, attributes, layout, material, mode, usage)
+  }
+
   pub fn run(self) -> Result<(), String> {
     run_game(self)
   }
@@ -121,8 +410,11 @@ fn build_game_state(game_builder: GameBuilder) -> GameState {
       None
     }
   };
-  if let Some(program) = &some_program { unsafe{ program.get_active_attributes(); } }
-  let some_cam = Some(build_camera());
+  let camera = match game_builder.pixel_perfect_camera {
+    Some((pixels_per_unit, reference)) => build_pixel_perfect_camera((game_builder.width, game_builder.height), pixels_per_unit, reference),
+    None => build_camera()
+  };
+  let some_cam = Some(camera);
   GameStateBuilder::new()
     .with_shader_program(some_program)
     .with_camera(some_cam)
@@ -130,7 +422,7 @@ fn build_game_state(game_builder: GameBuilder) -> GameState {
 }
 
 fn build_camera() -> Camera {
-  return CameraBuilder::new()
+  CameraBuilder::new()
     .with_eye(Point3::new(0.0, 0.0, -2.0))
     .with_target(Point3::new(0.0, 0.0, 0.0))
     .with_up(Vector3::new(0.0, 1.0, 0.0))
@@ -138,35 +430,147 @@ fn build_camera() -> Camera {
     .with_aspect(16.0/9.0)
     .with_near(0.1)
     .with_far(100.0)
-    .build();
+    .build()
+}
+
+fn build_pixel_perfect_camera(viewport: (u32, u32), pixels_per_unit: GLfloat, reference: (u32, u32)) -> Camera {
+  CameraBuilder::new()
+    .with_eye(Point3::new(0.0, 0.0, 10.0))
+    .with_target(Point3::new(0.0, 0.0, 0.0))
+    .with_up(Vector3::new(0.0, 1.0, 0.0))
+    .with_pixel_perfect(viewport.0, viewport.1, pixels_per_unit, pixel_perfect_zoom(viewport, reference))
+    .with_near(0.1)
+    .with_far(100.0)
+    .build()
 }
 
 fn run_game(game: Game) -> Result<(), String> {
   let mut next_loop = game.events_loop;
+  // locals are dropped in reverse, so the window outlives the GL objects
   let window = game.window;
   let mut game_state = game.game_state;
   let renderer = game.renderer;
+  let post_process = game.post_process;
+  let profiler = renderer.profiler();
+  let mut frame_start = Instant::now();
+  // sorts the frame times for the percentiles, so it is only refreshed every TITLE_UPDATE_FRAMES frames
+  let mut stats_summary = String::new();
   // ggez might have a useful timer, as well as other functionalities like sound
   // https://docs.rs/ggez/0.4.0/ggez/index.html
   loop {
-    next_loop = event_handler::handle_events_loop(next_loop, &mut game_state);
-    update(&mut game_state);
-    renderer.draw(&game_state)?;
-    window.swap_buffers().unwrap();
+    profiler.begin_frame();
+    {
+      let _events = profiler.scope("Events");
+      next_loop = event_handler::handle_events_loop(next_loop, &mut game_state);
+    }
+    {
+      let _update = profiler.scope("Update");
+      update(&mut game_state);
+      // picks up moves of the camera, a pixel perfect view stays on whole pixels
+      if let Some(camera) = &mut game_state.camera {
+        camera.update_view();
+      }
+    }
+    if game_state.show_entity_gizmos {
+      game_state.add_entity_gizmos();
+    }
+    if game.fps_overlay {
+      game_state.texts.push(TextItem::screen(&stats_summary, 8.0, 8.0, TextStyle::new(FPS_OVERLAY_SIZE)));
+    }
+    match &post_process {
+      Some(stack) => {
+        renderer.draw_into(&game_state, Some(stack.scene_target()))?;
+        let _post_process = profiler.gpu_scope("Post-process");
+        let draws = unsafe { stack.apply(&mut renderer.state_cache()) };
+        renderer.count_draws(draws);
+      }
+      None => renderer.draw(&game_state)?
+    }
+    renderer.draw_text(&game_state)?;
+    game_state.texts.clear();
+    game_state.debug.clear();
+    {
+      let _swap = profiler.scope("Swap buffers");
+      window.swap_buffers().unwrap();
+    }
+    profiler.end_frame();
+    let now = Instant::now();
+    game_state.frame_stats.record(now - frame_start, renderer.stats());
+    frame_start = now;
+    let refresh_summary = stats_summary.is_empty() || game_state.frame_stats.frame_count().is_multiple_of(TITLE_UPDATE_FRAMES);
+    if refresh_summary && (game.title.is_some() || game.fps_overlay) {
+      stats_summary = game_state.frame_stats.summary();
+      if let Some(title) = &game.title {
+        window.set_title(&format!("{} | {}", title, stats_summary));
+      }
+    }
+    if game_state.frame_stats.export_requested {
+      game_state.frame_stats.export_requested = false;
+      export_frame_stats(&game_state.frame_stats, &game.frame_stats_csv);
+    }
     if !game_state.running {
       break;
     }
   }
-  println!("game loop done");
+  info!("game loop done");
+  report_profile(profiler, game.chrome_trace.as_deref());
   Ok(())
 }
 
-fn update(game: &mut GameState) -> Option<()> {
+fn export_frame_stats(frame_stats: &FrameStats, path: &str) {
+  match File::create(path).and_then(|mut file| frame_stats.write_csv(&mut file)) {
+    Ok(()) => info!("Frame stats written to {}", path),
+    Err(error) => warn!("Could not write the frame stats to {}: {}", path, error)
+  }
+}
+
+fn report_profile(profiler: &Profiler, chrome_trace: Option<&str>) {
+  for stats in profiler.summary() {
+    let gpu = stats.gpu_average.map_or(String::new(), |gpu_average| format!(", gpu {:.3} ms", gpu_average.as_secs_f64() * 1000.0));
+    info!("{}: cpu {:.3} ms (max {:.3} ms){} over {} frames", stats.name,
+      stats.cpu_average.as_secs_f64() * 1000.0, stats.cpu_max.as_secs_f64() * 1000.0, gpu, stats.frames);
+  }
+  if let Some(path) = chrome_trace {
+    let written = File::create(path).and_then(|mut file| profiler.write_chrome_trace(profiler.frame_range(), &mut file));
+    match written {
+      Ok(()) => info!("Chrome trace written to {}", path),
+      Err(error) => warn!("Could not write the Chrome trace to {}: {}", path, error)
+    }
+  }
+}
+
+// Spins every entity with a model matrix, lights and sprites have none and stay as they are
+fn update(game: &mut GameState) {
   for entity_index in &game.entities {
-    let model_matrix = game.model_matrices.get(*entity_index)?;
+    let model_matrix = match game.model_matrices.get(*entity_index) {
+      Some(model_matrix) => model_matrix,
+      None => continue
+    };
     let rot = Matrix4::from_angle_y(Rad(0.1));
     game.model_matrices.set(*entity_index, rot*model_matrix);
   }
-  Some(())
+}
+
+#[cfg(test)]
+mod game_builder_tests {
+  use super::*;
+  use engine::light::DirectionalLight;
+  use std::rc::Rc;
+
+  #[test]
+  fn entities_after_lights_and_sprites_still_rotate() {
+    // arrange
+    let mut game = GameStateBuilder::new().build();
+    game.add_directional_light(DirectionalLight::new(Vector3::new(0.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 1.0));
+    // no GL in these tests, a texture without a handle is never deleted
+    game.add_sprite(Sprite::new(&Rc::new(Texture { handle: 0, width: 16, height: 16 })));
+    let mesh = game.entity_allocator.allocate();
+    game.model_matrices.set(mesh, Matrix4::from_scale(1.0));
+    game.entities.push(mesh);
+    // act
+    update(&mut game);
+    // assert
+    assert_eq!(Some(&Matrix4::from_angle_y(Rad(0.1))), game.model_matrices.get(mesh));
+  }
 }
 
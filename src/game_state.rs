@@ -1,39 +1,141 @@
-use crate::shader_program::{ ShaderProgram, SetUniform };
+use crate::shader_program::ShaderProgram;
+use std::rc::Rc;
 use gl::types::*;
 use cgmath::{ Matrix4 };
 use crate::camera::Camera;
 use engine::ecs::generational_index::*;
 use engine::ecs::generational_entries::*;
+use engine::material::{ Material, ProgramId };
+use engine::light::{ DirectionalLight, PointLight };
+use engine::culling::BoundingVolume;
+use engine::dynamic_buffer::DynamicBuffer;
+use engine::vao_builder::buffer_component::BufferComponent;
+use crate::frame_stats::FrameStats;
+use engine::text::TextItem;
+use engine::debug_draw::{ DebugLines, YELLOW };
+use engine::sprite::Sprite;
+use engine::texture::Texture;
 
 // GameState
 
 #[derive(Default)]
 pub struct GameState {
   pub running: bool,
-  pub shader_program: Option<ShaderProgram>,
+  pub shader_programs: Vec<ShaderProgram>,
   pub camera: Option<Camera>,
   // ECS
   pub entity_allocator: GenerationalIndexAllocator,
-  pub vaos: GenerationalEntries<GLuint>,
+  // the vertex array and buffers of the entity's mesh, copies of a mesh share it, see vao and instance_buffer
+  pub meshes: GenerationalEntries<Rc<BufferComponent>>,
   pub model_matrices: GenerationalEntries<Matrix4<GLfloat>>,
   pub vertex_counts: GenerationalEntries<GLsizei>,
-  pub entities: Vec<GenerationalIndex>
+  pub materials: GenerationalEntries<Material>,
+  pub draw_modes: GenerationalEntries<GLenum>,
+  // model space bounds, entities without bounds are never culled
+  pub bounds: GenerationalEntries<BoundingVolume>,
+  // vertex buffers of meshes that can be updated, see model_creator::update_vertices
+  pub dynamic_buffers: GenerationalEntries<DynamicBuffer>,
+  // vertices in the static attribute buffer of an animated model, updates may not go past them
+  pub vertex_limits: GenerationalEntries<usize>,
+  pub directional_lights: GenerationalEntries<DirectionalLight>,
+  pub point_lights: GenerationalEntries<PointLight>,
+  // drawn after the forward pass, see engine::sprite::SpriteBatch
+  pub sprites: GenerationalEntries<Sprite>,
+  // textures that materials refer to by handle, kept alive as long as the game state
+  pub textures: Vec<Texture>,
+  pub entities: Vec<GenerationalIndex>,
+  // recorded by the game loop after every frame
+  pub frame_stats: FrameStats,
+  // queued by systems every frame, drawn over the scene and cleared by the game loop
+  pub texts: Vec<TextItem>,
+  // lines added by systems every frame, drawn after the scene and cleared by the game loop
+  pub debug: DebugLines,
+  // F3 adds the bounds and axes of every entity to the debug lines
+  pub show_entity_gizmos: bool
 }
 
 impl GameState {
-  pub fn new(shader_program: Option<ShaderProgram>) -> GameState {
+  pub fn new(shader_programs: Vec<ShaderProgram>) -> GameState {
     GameState {
       running: true,
-      shader_program,
+      shader_programs,
       ..Default::default()
     }
   }
+
+  // The vertex array of the entity's mesh
+  #[allow(dead_code)]
+  pub fn vao(&self, entity_index: GenerationalIndex) -> Option<GLuint> {
+    self.meshes.get(entity_index).map(|mesh| mesh.vao())
+  }
+
+  // The buffer for per instance model matrices of the entity's mesh, see model_creator::add_instanced_model
+  #[allow(dead_code)]
+  pub fn instance_buffer(&self, entity_index: GenerationalIndex) -> Option<GLuint> {
+    self.meshes.get(entity_index).map(|mesh| mesh.instance_vbo()).filter(|vbo| *vbo != 0)
+  }
+
+  // Takes ownership of the texture, materials can refer to the returned one for as long as the game state lives
+  #[allow(dead_code)]
+  pub fn add_texture(&mut self, texture: Texture) -> &Texture {
+    self.textures.push(texture);
+    &self.textures[self.textures.len() - 1]
+  }
+
+  // Takes ownership of the program, materials refer to it by the returned id
+  #[allow(dead_code)]
+  pub fn add_shader_program(&mut self, program: ShaderProgram) -> ProgramId {
+    self.shader_programs.push(program);
+    ProgramId(self.shader_programs.len() - 1)
+  }
+
+  #[allow(dead_code)]
+  pub fn add_directional_light(&mut self, light: DirectionalLight) -> GenerationalIndex {
+    let entity_index = self.entity_allocator.allocate();
+    self.directional_lights.set(entity_index, light);
+    self.entities.push(entity_index);
+    entity_index
+  }
+
+  #[allow(dead_code)]
+  pub fn add_point_light(&mut self, light: PointLight) -> GenerationalIndex {
+    let entity_index = self.entity_allocator.allocate();
+    self.point_lights.set(entity_index, light);
+    self.entities.push(entity_index);
+    entity_index
+  }
+
+  #[allow(dead_code)]
+  pub fn add_sprite(&mut self, sprite: Sprite) -> GenerationalIndex {
+    let entity_index = self.entity_allocator.allocate();
+    self.sprites.set(entity_index, sprite);
+    self.entities.push(entity_index);
+    entity_index
+  }
+
+  // The bounds and the axes of every entity with a model matrix, axes reach as far as the largest half extent of a box
+  pub fn add_entity_gizmos(&mut self) {
+    for entity_index in &self.entities {
+      let model = match self.model_matrices.get(*entity_index) {
+        Some(model) => *model,
+        None => continue
+      };
+      let mut length = 1.0;
+      if let Some(bounds) = self.bounds.get(*entity_index) {
+        self.debug.bounds(bounds, model, YELLOW);
+        if let BoundingVolume::Box(aabb) = bounds {
+          length = aabb.extents().x.max(aabb.extents().y).max(aabb.extents().z).max(0.01);
+        }
+      }
+      self.debug.axes(model, length);
+    }
+  }
 }
 
 // builder
 #[derive(Default)]
 pub struct GameStateBuilder {
-  pub shader_program: Option<ShaderProgram>,
+  pub shader_programs: Vec<ShaderProgram>,
   pub camera: Option<Camera>,
 }
 
@@ -45,7 +147,9 @@ impl GameStateBuilder {
 
   #[allow(dead_code)]
   pub fn with_shader_program(mut self, shader_program: Option<ShaderProgram>) -> Self {
-    self.shader_program = shader_program;
+    if let Some(program) = shader_program {
+      self.shader_programs.push(program);
+    }
     self
   }
 
@@ -57,7 +161,7 @@ impl GameStateBuilder {
 
   #[allow(dead_code)]
   pub fn build(self) -> GameState {
-    let mut state = GameState::new(self.shader_program);
+    let mut state = GameState::new(self.shader_programs);
     state.camera = self.camera;
     state
   }
@@ -66,6 +170,25 @@ impl GameStateBuilder {
 #[cfg(test)]
 mod game_state_tests {
   use super::*;
+  use cgmath::Point3;
+  use engine::culling::Aabb;
+
+  #[test]
+  fn entity_gizmos_draw_bounds_and_axes() {
+    // arrange
+    let mut game = GameStateBuilder::new().build();
+    let with_bounds = game.entity_allocator.allocate();
+    game.model_matrices.set(with_bounds, Matrix4::from_scale(1.0));
+    game.bounds.set(with_bounds, BoundingVolume::Box(Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0))));
+    let without_bounds = game.entity_allocator.allocate();
+    game.model_matrices.set(without_bounds, Matrix4::from_scale(1.0));
+    let light = game.entity_allocator.allocate();
+    game.entities.extend_from_slice(&[with_bounds, without_bounds, light]);
+    // act
+    game.add_entity_gizmos();
+    // assert
+    assert_eq!(12 + 3 + 3, game.debug.line_count());
+  }
 
   #[test]
   fn can_build_empty_game_state() {
@@ -75,6 +198,6 @@ mod game_state_tests {
     let game = builder.build();
     // assert
     assert!(game.camera.is_none());
-    assert!(game.shader_program.is_none());
+    assert!(game.shader_programs.is_empty());
   }
 }
\ No newline at end of file
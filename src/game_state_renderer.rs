@@ -1,46 +1,709 @@
-use crate::shader_program::{ ShaderProgram, SetUniform };
+use crate::shader_program::{ ShaderProgram, ShaderProgramBuilder };
+use std::cell::{ Cell, RefCell, RefMut };
+use std::time::Instant;
 use gl::types::*;
-// use cgmath::{ Matrix4 };
-// use crate::camera::Camera;
 use engine::ecs::generational_index::*;
-// use engine::ecs::generational_entries::*;
+use std::cmp::Ordering;
+use std::ptr;
+use std::rc::Rc;
+use engine::material::{ BlendMode, Material, ProgramId };
+use engine::light::{ self, DirectionalLight, PointLight };
+use engine::render_state::{ RenderState, RenderStateCache };
+use engine::render_target::{ bind_default_framebuffer, RenderTarget };
+use engine::deferred::{ self, DeferredShading };
+use engine::post_process::DrawCount;
+use engine::culling::Frustum;
+use engine::instancing::{ self, InstanceBuffer };
+use engine::vao_builder::buffer_component::BufferComponent;
+use engine::debug_output::DebugGroup;
+use engine::gl_check;
+use engine::profiler::{ Profiler, ProfileScope };
+use engine::font::FontAtlas;
+use engine::text::{ TextItem, TextRenderer };
+use engine::debug_draw::DebugLineRenderer;
+use engine::sprite::{ Sprite, SpriteBatch };
+use engine::camera::{ CameraBlock, CAMERA_BLOCK };
+use engine::uniform_buffer::{ UniformBlockBindings, UniformBuffer };
+use engine::shadow::{ self, ShadowMap, MAX_SHADOW_MAPS, directional_light_view_projection };
+use cgmath::{ Matrix4, SquareMatrix, Vector4 };
 use crate::game_state::GameState;
 
+// Entities to draw with the program of their material
+type DrawOrder = Vec<(ProgramId, GenerationalIndex)>;
+
+// Depth only pass from each shadow casting directional light
+struct ShadowPass {
+  program: ShaderProgram,
+  maps: Vec<ShadowMap>,
+  // how far from the eye the shadow maps reach
+  distance: GLfloat
+}
+
+// Counts of the last drawn frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+  pub visible_entities: usize,
+  // outside of the camera's view frustum
+  pub culled_entities: usize,
+  // of entities, including their shadow maps, and of the full-screen passes; an instanced batch is one call
+  pub draw_calls: usize,
+  // vertices submitted by those calls, counted per instance
+  pub vertices: usize
+}
+
 pub struct GameStateRenderer {
-  mode: GLenum
+  mode: GLenum,
+  viewport_size: (GLsizei, GLsizei),
+  shadow_pass: Option<ShadowPass>,
+  // programs that write the G-buffer are lit in a deferred pass, the others are drawn forward on top
+  deferred: Option<DeferredShading>,
+  // used for entities without a material
+  default_render_state: RenderState,
+  state_cache: RefCell<RenderStateCache>,
+  stats: Cell<RenderStats>,
+  // the Camera block, written once per frame and read by every program that declares it
+  camera_buffer: UniformBuffer,
+  block_bindings: UniformBlockBindings,
+  // the model matrices of batches whose mesh has no instance buffer of its own
+  instances: InstanceBuffer,
+  start_time: Instant,
+  // times the draw and each render pass, the game loop adds its systems
+  profiler: Profiler,
+  text: Option<RefCell<TextRenderer>>,
+  sprites: Option<RefCell<SpriteBatch>>,
+  // and whether the lines are hidden behind the scene
+  debug_lines: Option<(RefCell<DebugLineRenderer>, bool)>
 }
 
 impl GameStateRenderer {
 
   pub fn new(mode: GLenum) -> Self { // gl::TRIANGLES
+    let mut block_bindings = UniformBlockBindings::new();
+    let camera_binding = block_bindings.binding(CAMERA_BLOCK);
     GameStateRenderer {
-      mode
+      mode,
+      viewport_size: (1600, 900),
+      shadow_pass: None,
+      deferred: None,
+      default_render_state: RenderState::default(),
+      state_cache: RefCell::new(RenderStateCache::new()),
+      stats: Cell::new(RenderStats::default()),
+      camera_buffer: UniformBuffer::for_block(camera_binding, &CameraBlock::default()),
+      block_bindings,
+      instances: InstanceBuffer::new(),
+      start_time: Instant::now(),
+      profiler: Profiler::disabled(),
+      text: None,
+      sprites: None,
+      debug_lines: None
+    }
+  }
+
+  pub fn set_viewport_size(&mut self, width: GLsizei, height: GLsizei) {
+    self.viewport_size = (width, height);
+  }
+
+  pub fn set_clear_color(&mut self, rgba: [GLfloat; 4]) {
+    unsafe { gl::ClearColor(rgba[0], rgba[1], rgba[2], rgba[3]); }
+  }
+
+  pub fn set_default_render_state(&mut self, render_state: RenderState) {
+    self.default_render_state = render_state;
+  }
+
+  // Passes outside of the renderer, like post-processing, share the cache so that it stays in sync with GL
+  pub fn state_cache(&self) -> RefMut<'_, RenderStateCache> {
+    self.state_cache.borrow_mut()
+  }
+
+  #[allow(dead_code)]
+  pub fn stats(&self) -> RenderStats {
+    self.stats.get()
+  }
+
+  pub fn set_profiler(&mut self, profiler: Profiler) {
+    self.profiler = profiler;
+  }
+
+  pub fn profiler(&self) -> &Profiler {
+    &self.profiler
+  }
+
+  // A render pass is a debug group and is timed on the CPU and the GPU
+  fn begin_pass(&self, name: &'static str) -> (DebugGroup, ProfileScope<'_>) {
+    (DebugGroup::new(name), self.profiler.gpu_scope(name))
+  }
+
+  // Creates MAX_SHADOW_MAPS square depth maps of the given size
+  pub fn enable_shadows(&mut self, size: GLsizei, distance: GLfloat) -> Result<(), String> {
+    let program = ShaderProgramBuilder::new()
+      .with_vertex_shader(include_str!("glsl/shadow/vertex.glsl"))
+      .with_fragment_shader(include_str!("glsl/shadow/fragment.glsl"))
+      .build();
+    let mut maps = Vec::new();
+    for _ in 0..MAX_SHADOW_MAPS {
+      maps.push(ShadowMap::new(size)?);
+    }
+    self.shadow_pass = Some(ShadowPass { program, maps, distance });
+    Ok(())
+  }
+
+  // Creates the G-buffer with the size of the viewport
+  pub fn enable_deferred_shading(&mut self) -> Result<(), String> {
+    self.deferred = Some(DeferredShading::new(self.viewport_size.0, self.viewport_size.1)?);
+    Ok(())
+  }
+
+  pub fn enable_text(&mut self, atlas: FontAtlas) -> Result<(), String> {
+    self.text = Some(RefCell::new(TextRenderer::new(atlas)?));
+    Ok(())
+  }
+
+  // Draws the sprites of the game state after the forward pass
+  pub fn enable_sprites(&mut self) {
+    self.sprites = Some(RefCell::new(SpriteBatch::new()));
+  }
+
+  // Draws the debug lines of the game state after the scene, depth_test hides them behind it
+  pub fn enable_debug_lines(&mut self, depth_test: bool) {
+    self.debug_lines = Some((RefCell::new(DebugLineRenderer::new()), depth_test));
+  }
+
+  // Draws the queued texts of the game state into the window, after post-processing so that they stay legible
+  pub fn draw_text(&self, game_state: &GameState) -> Result<(), &str> {
+    let text = match (&self.text, game_state.texts.is_empty()) {
+      (Some(text), false) => text,
+      _ => return Ok(())
+    };
+    // screen text is placed in pixels, only labels and world text need a camera
+    let view_projection = match &game_state.camera {
+      Some(cam) => cam.projection_matrix * cam.view_matrix,
+      None if game_state.texts.iter().any(TextItem::needs_camera) => return Err("Trying to draw labels or world text but no camera in GameState"),
+      None => Matrix4::identity()
+    };
+    let _pass = self.begin_pass("Text");
+    let vertices = unsafe {
+      bind_default_framebuffer(self.viewport_size.0, self.viewport_size.1);
+      text.borrow_mut().draw(&game_state.texts, self.viewport_size, view_projection, &mut self.state_cache.borrow_mut())
+    };
+    if vertices > 0 {
+      self.count_draw(vertices);
     }
+    Ok(())
   }
 
   pub fn draw(&self, game_state: &GameState) -> Result<(),&str> {
-    unsafe { gl::Clear(gl::DEPTH_BUFFER_BIT | gl::COLOR_BUFFER_BIT); }
-    let program = game_state.shader_program.as_ref().map_or(Err("Trying to draw but no shader program in GameState"), |p| Ok(p))?;
-    let cam = game_state.camera.as_ref().map_or(Err("Trying to draw but no camera in GameState"), |c| Ok(c))?;
+    self.draw_into(game_state, None)
+  }
+
+  // Draws the scene into the target, or into the window's framebuffer when there is none
+  pub fn draw_into(&self, game_state: &GameState, target: Option<&RenderTarget>) -> Result<(),&str> {
+    let _draw = self.profiler.scope("Draw");
+    // sprites bring their own program
+    if game_state.shader_programs.is_empty() && self.sprites.is_none() { return Err("Trying to draw but no shader program in GameState"); }
+    let cam = game_state.camera.as_ref().ok_or("Trying to draw but no camera in GameState")?;
+    let (directional_lights, point_lights) = collect_lights(game_state);
+    let camera_block = CameraBlock::new(cam, self.start_time.elapsed().as_secs_f32());
+    unsafe { self.camera_buffer.update_block(&camera_block).map_err(|_| "Camera block does not fit its uniform buffer")?; }
+    let mut state_cache = self.state_cache.borrow_mut();
+    self.stats.set(RenderStats::default());
+    let shadow_matrices = {
+      let _pass = self.begin_pass("Shadows");
+      self.draw_shadow_maps(game_state, &directional_lights, &mut state_cache)
+    };
+    // shadow casters are not culled, they may throw shadows into the view from outside of it
+    let (visible_order, culled_entities) = frustum_cull(game_state, draw_order(game_state), &Frustum::from_matrix(cam.projection_matrix * cam.view_matrix), self.mode);
+    self.stats.set(RenderStats { visible_entities: visible_order.len(), culled_entities, ..self.stats.get() });
+    let instance_locations: Vec<Option<GLuint>> = game_state.shader_programs.iter().map(|program| unsafe { instancing::instance_model_location(program) }).collect();
+    let (opaque_order, transparent_order) = render_queue(game_state, cam.view_matrix, visible_order, &instance_locations);
+    // transparent entities are always drawn forward
+    let (geometry_order, forward_order) = match &self.deferred {
+      Some(_) => {
+        let gbuffer_programs: Vec<bool> = game_state.shader_programs.iter().map(|program| unsafe { deferred::writes_gbuffer(program) }).collect();
+        split_deferred(opaque_order, &gbuffer_programs)
+      },
+      None => (Vec::new(), opaque_order)
+    };
+    if let (Some(deferred), false) = (&self.deferred, geometry_order.is_empty()) {
+      let _pass = self.begin_pass("Geometry");
+      unsafe { deferred.begin_geometry(&mut state_cache); }
+      // the camera comes from the Camera block, the lights are applied in the lighting pass
+      self.draw_entities(game_state, &geometry_order, &instance_locations, &mut state_cache, |_| {})?;
+    }
     unsafe {
-      program.set_uniform_matrix("View", cam.view_matrix);
-      program.set_uniform_matrix("Projection", cam.projection_matrix);
+      // depth writes, the color mask and the scissor box also apply to glClear
+      state_cache.apply(&RenderState::default());
+      match target {
+        Some(target) => target.bind(),
+        None => bind_default_framebuffer(self.viewport_size.0, self.viewport_size.1)
+      }
+      gl::Clear(gl::DEPTH_BUFFER_BIT | gl::COLOR_BUFFER_BIT);
+    }
+    gl_check!("GameStateRenderer::clear");
+    if let (Some(deferred), false) = (&self.deferred, geometry_order.is_empty()) {
+      let _pass = self.begin_pass("Lighting");
+      let shadow_maps = self.shadow_pass.as_ref().map_or(&[][..], |shadow_pass| &shadow_pass.maps[..]);
+      unsafe {
+        self.count_draws(deferred.draw_lighting(cam, &directional_lights, &point_lights, shadow_maps, &shadow_matrices, &mut state_cache));
+        self.count_draws(deferred.copy_depth_to(target.map_or(0, |target| target.fbo), &mut state_cache));
+      }
+    }
+    let bind_forward_program = |program: &ShaderProgram| unsafe {
+      light::upload_lights(program, &directional_lights, &point_lights);
+      self.upload_shadow_maps(program, &shadow_matrices);
+    };
+    {
+      let _pass = self.begin_pass("Forward");
+      self.draw_entities(game_state, &forward_order, &instance_locations, &mut state_cache, bind_forward_program)?;
+      self.draw_entities(game_state, &transparent_order, &instance_locations, &mut state_cache, bind_forward_program)?;
+    }
+    if let Some(sprite_batch) = &self.sprites {
+      let sprites = collect_sprites(game_state);
+      if !sprites.is_empty() {
+        let _pass = self.begin_pass("Sprites");
+        let mut sprite_batch = sprite_batch.borrow_mut();
+        for draw in unsafe { sprite_batch.draw(&sprites, cam.projection_matrix * cam.view_matrix, &mut state_cache) } {
+          self.count_draw(draw.count);
+        }
+      }
     }
-    for entity_index in &game_state.entities {
-      self.draw_entity(game_state, program, *entity_index);
+    if let (Some((debug_lines, depth_test)), false) = (&self.debug_lines, game_state.debug.is_empty()) {
+      let _pass = self.begin_pass("Debug lines");
+      let vertices = unsafe { debug_lines.borrow_mut().draw(&game_state.debug, cam.projection_matrix * cam.view_matrix, *depth_test, &mut state_cache) };
+      self.count_draw(vertices);
     }
     Ok(())
   }
 
-  fn draw_entity(&self, game_state: &GameState, program: &ShaderProgram, entity_index: GenerationalIndex) -> Option<()> {
-    let vao = *game_state.vaos.get(entity_index)?;
+  // Draws the entities in order, bind_program sets the per frame uniforms whenever the program changes.
+  // The render state of each batch's material is applied through the cache, see batches.
+  // instance_locations holds the location of the InstanceModel input per program, batches of those programs
+  // are drawn with one instanced call.
+  fn draw_entities<F: Fn(&ShaderProgram)>(&self, game_state: &GameState, order: &[(ProgramId, GenerationalIndex)], instance_locations: &[Option<GLuint>],
+    state_cache: &mut RenderStateCache, bind_program: F) -> Result<(), &'static str> {
+    let mut current_program: Option<ProgramId> = None;
+    for batch in batches(game_state, order) {
+      let (program_id, entity_index) = batch[0];
+      let program = game_state.shader_programs.get(program_id.0).ok_or("Material refers to a shader program that is not in GameState")?;
+      if current_program != Some(program_id) {
+        unsafe {
+          gl::UseProgram(program.handle());
+          self.block_bindings.connect(program);
+        }
+        bind_program(program);
+        gl_check!("GameStateRenderer::bind_program");
+        current_program = Some(program_id);
+      }
+      let render_state = game_state.materials.get(entity_index).map_or(&self.default_render_state, |m| &m.render_state);
+      unsafe { state_cache.apply(render_state); }
+      match instance_locations.get(program_id.0).cloned().flatten() {
+        Some(location) => self.draw_instances(game_state, program, batch, location),
+        None => self.draw_batch(game_state, program, batch)
+      };
+    }
+    Ok(())
+  }
+
+  fn count_draw(&self, vertices: usize) {
+    self.count_draws(DrawCount { calls: 1, vertices });
+  }
+
+  // Adds draws of passes outside of the renderer, like post-processing, to the stats of the frame
+  pub fn count_draws(&self, draws: DrawCount) {
+    let mut stats = self.stats.get();
+    stats.draw_calls += draws.calls;
+    stats.vertices += draws.vertices;
+    self.stats.set(stats);
+  }
+
+  // Renders the depth of all opaque triangle meshes into the shadow map of every shadow casting light,
+  // returns the light view-projection matrix per shadow map
+  fn draw_shadow_maps(&self, game_state: &GameState, directional_lights: &[DirectionalLight], state_cache: &mut RenderStateCache) -> Vec<Matrix4<GLfloat>> {
+    let (shadow_pass, cam) = match (&self.shadow_pass, &game_state.camera) {
+      (Some(shadow_pass), Some(cam)) => (shadow_pass, cam),
+      _ => return Vec::new()
+    };
+    let mut shadow_matrices = Vec::new();
+    unsafe {
+      gl::UseProgram(shadow_pass.program.handle());
+      state_cache.apply(&RenderState::new().with_polygon_offset(2.0, 4.0));
+    }
+    for (shadow_map, light_index) in shadow_pass.maps.iter().zip(light::shadow_casters(directional_lights)) {
+      let light_view_projection = directional_light_view_projection(directional_lights[light_index].direction, cam, shadow_pass.distance);
+      unsafe {
+        shadow_map.begin();
+        shadow_pass.program.set_uniform_matrix("LightViewProjection", light_view_projection);
+      }
+      for entity_index in &game_state.entities {
+        self.draw_entity_depth(game_state, &shadow_pass.program, *entity_index);
+      }
+      shadow_matrices.push(light_view_projection);
+    }
+    gl_check!("GameStateRenderer::draw_shadow_maps");
+    shadow_matrices
+  }
+
+  fn draw_entity_depth(&self, game_state: &GameState, program: &ShaderProgram, entity_index: GenerationalIndex) -> Option<()> {
+    let mode = game_state.draw_modes.get(entity_index).map_or(self.mode, |m| *m);
+    if !casts_shadow(mode, blend_mode_of(game_state, entity_index)) { return None; }
+    let mesh = game_state.meshes.get(entity_index)?;
     let model_matrix = *game_state.model_matrices.get(entity_index)?;
     let vertex_count = *game_state.vertex_counts.get(entity_index)?;
     unsafe {
       program.set_uniform_matrix("Model", model_matrix);
-      gl::BindVertexArray(vao);
-      gl::DrawArrays(self.mode, 0, vertex_count);
+      gl::BindVertexArray(mesh.vao());
+      draw_mesh(mesh, mode, vertex_count);
     }
+    self.count_draw(vertex_count as usize);
     Some(())
   }
-}
\ No newline at end of file
+
+  unsafe fn upload_shadow_maps(&self, program: &ShaderProgram, shadow_matrices: &[Matrix4<GLfloat>]) {
+    if let Some(shadow_pass) = &self.shadow_pass {
+      shadow::upload_shadow_maps(program, &shadow_pass.maps, shadow_matrices);
+    }
+  }
+
+  // Draws entities that share the mesh and material of the first one with a single call. Meshes without an
+  // instance buffer of their own (see model_creator::add_instanced_model) read the renderer's, from location on.
+  fn draw_instances(&self, game_state: &GameState, program: &ShaderProgram, batch: &[(ProgramId, GenerationalIndex)], location: GLuint) -> Option<()> {
+    let (_, first) = batch[0];
+    let mesh = game_state.meshes.get(first)?;
+    let vertex_count = *game_state.vertex_counts.get(first)?;
+    let mode = game_state.draw_modes.get(first).map_or(self.mode, |m| *m);
+    let model_matrices: Vec<Matrix4<GLfloat>> = batch.iter()
+      .filter_map(|(_, entity_index)| game_state.model_matrices.get(*entity_index).cloned())
+      .collect();
+    unsafe {
+      if let Some(material) = game_state.materials.get(first) {
+        material.apply(program);
+      }
+      let instance_vbo = match mesh.instance_vbo() {
+        0 => {
+          self.instances.attach(mesh.vao(), location);
+          self.instances.handle()
+        },
+        instance_vbo => instance_vbo
+      };
+      if mesh.ibo() != 0 {
+        instancing::draw_elements_instanced(mesh.vao(), instance_vbo, mode, vertex_count, &model_matrices);
+      } else {
+        instancing::draw_arrays_instanced(mesh.vao(), instance_vbo, mode, vertex_count, &model_matrices);
+      }
+    }
+    gl_check!("GameStateRenderer::draw_instances");
+    self.count_draw(vertex_count as usize * model_matrices.len());
+    Some(())
+  }
+
+  // Draws entities that share the mesh and material of the first one for a program without an InstanceModel input.
+  // The material and the vertex array are bound once, every entity is a draw call with its Model uniform.
+  fn draw_batch(&self, game_state: &GameState, program: &ShaderProgram, batch: &[(ProgramId, GenerationalIndex)]) -> Option<()> {
+    let (_, first) = batch[0];
+    let mesh = game_state.meshes.get(first)?;
+    let vertex_count = *game_state.vertex_counts.get(first)?;
+    let mode = game_state.draw_modes.get(first).map_or(self.mode, |m| *m);
+    unsafe {
+      if let Some(material) = game_state.materials.get(first) {
+        material.apply(program);
+      }
+      gl::BindVertexArray(mesh.vao());
+      for (_, entity_index) in batch {
+        if let Some(model_matrix) = game_state.model_matrices.get(*entity_index) {
+          program.set_uniform_matrix("Model", *model_matrix);
+          draw_mesh(mesh, mode, vertex_count);
+          self.count_draw(vertex_count as usize);
+        }
+      }
+    }
+    gl_check!("GameStateRenderer::draw_batch");
+    Some(())
+  }
+}
+
+/// Draws the bound vertex array of the mesh, through its index buffer when it has one
+///
+/// # Safety
+/// The mesh's vertex array has to be bound, count is the number of indices for an indexed mesh.
+unsafe fn draw_mesh(mesh: &BufferComponent, mode: GLenum, count: GLsizei) {
+  if mesh.ibo() != 0 {
+    gl::DrawElements(mode, count, gl::UNSIGNED_INT, ptr::null());
+  } else {
+    gl::DrawArrays(mode, 0, count);
+  }
+}
+
+// Opaque triangle meshes, transparent entities would throw the shadow of a solid surface
+fn casts_shadow(mode: GLenum, blend_mode: BlendMode) -> bool {
+  (mode == gl::TRIANGLES || mode == gl::TRIANGLE_STRIP || mode == gl::TRIANGLE_FAN) && !blend_mode.is_transparent()
+}
+
+fn blend_mode_of(game_state: &GameState, entity_index: GenerationalIndex) -> BlendMode {
+  game_state.materials.get(entity_index).map_or(BlendMode::Opaque, |m| m.blend_mode())
+}
+
+// Distance of the entity's origin in front of the camera
+fn view_depth(game_state: &GameState, view_matrix: Matrix4<GLfloat>, entity_index: GenerationalIndex) -> GLfloat {
+  let origin = game_state.model_matrices.get(entity_index).map_or(Vector4::new(0.0, 0.0, 0.0, 1.0), |model| model.w);
+  -(view_matrix * origin).z
+}
+
+// Keeps the entities whose bounds intersect the frustum and those without bounds, returns how many were dropped.
+// Points are never culled, a geometry shader may expand them into shapes that reach past their bounds.
+fn frustum_cull(game_state: &GameState, order: DrawOrder, frustum: &Frustum, default_mode: GLenum) -> (DrawOrder, usize) {
+  let count = order.len();
+  let visible: DrawOrder = order.into_iter().filter(|(_, entity_index)| {
+    if game_state.draw_modes.get(*entity_index).map_or(default_mode, |m| *m) == gl::POINTS { return true; }
+    match (game_state.bounds.get(*entity_index), game_state.model_matrices.get(*entity_index)) {
+      (Some(bounds), Some(model)) => frustum.intersects(bounds, *model),
+      _ => true
+    }
+  }).collect();
+  let culled = count - visible.len();
+  (visible, culled)
+}
+
+// Splits the draw order into opaque and transparent entities. Opaque entities stay grouped by program and are
+// sorted front to back within a program, so that hidden fragments fail the depth test early. With an instanced
+// program (see instance_locations) they are grouped by mesh first, so that copies of a mesh make one batch.
+// Transparent entities are sorted back to front, regardless of their program, to blend correctly.
+fn render_queue(game_state: &GameState, view_matrix: Matrix4<GLfloat>, order: DrawOrder, instance_locations: &[Option<GLuint>]) -> (DrawOrder, DrawOrder) {
+  let depth = |entity_index: GenerationalIndex| view_depth(game_state, view_matrix, entity_index);
+  let (mut opaque, mut transparent): (DrawOrder, DrawOrder) = order.into_iter()
+    .partition(|(_, entity_index)| !blend_mode_of(game_state, *entity_index).is_transparent());
+  let instanced_mesh = |program_id: ProgramId, entity_index: GenerationalIndex| match instance_locations.get(program_id.0) {
+    Some(Some(_)) => game_state.meshes.get(entity_index).map(Rc::as_ptr),
+    _ => None
+  };
+  opaque.sort_by(|(program_a, a), (program_b, b)| program_a.cmp(program_b)
+    .then(instanced_mesh(*program_a, *a).cmp(&instanced_mesh(*program_b, *b)))
+    .then(depth(*a).partial_cmp(&depth(*b)).unwrap_or(Ordering::Equal)));
+  transparent.sort_by(|(_, a), (_, b)| depth(*b).partial_cmp(&depth(*a)).unwrap_or(Ordering::Equal));
+  (opaque, transparent)
+}
+
+// Runs of consecutive entities with the same program, the same mesh and an equal material, which share
+// all of their state but the model matrix. With an instanced program a batch is a single draw call.
+fn batches<'a>(game_state: &GameState, order: &'a [(ProgramId, GenerationalIndex)]) -> Vec<&'a [(ProgramId, GenerationalIndex)]> {
+  order.chunk_by(|(program_a, a), (program_b, b)| {
+    let same_mesh = match (game_state.meshes.get(*a), game_state.meshes.get(*b)) {
+      (Some(mesh_a), Some(mesh_b)) => Rc::ptr_eq(mesh_a, mesh_b),
+      _ => false
+    };
+    program_a == program_b && same_mesh && game_state.materials.get(*a) == game_state.materials.get(*b)
+  }).collect()
+}
+
+fn program_of(game_state: &GameState, entity_index: GenerationalIndex) -> ProgramId {
+  game_state.materials.get(entity_index).map_or(ProgramId::default(), |m: &Material| m.program)
+}
+
+// Splits the draw order into the entities for the G-buffer and those that are drawn forward,
+// gbuffer_programs tells per program whether it writes the G-buffer
+fn split_deferred(order: DrawOrder, gbuffer_programs: &[bool]) -> (DrawOrder, DrawOrder) {
+  order.into_iter().partition(|(program_id, _)| gbuffer_programs.get(program_id.0).cloned().unwrap_or(false))
+}
+
+fn collect_lights(game_state: &GameState) -> (Vec<DirectionalLight>, Vec<PointLight>) {
+  let directional_lights = game_state.entities.iter()
+    .filter_map(|entity_index| game_state.directional_lights.get(*entity_index))
+    .cloned()
+    .collect();
+  let point_lights = game_state.entities.iter()
+    .filter_map(|entity_index| game_state.point_lights.get(*entity_index))
+    .cloned()
+    .collect();
+  (directional_lights, point_lights)
+}
+
+fn collect_sprites(game_state: &GameState) -> Vec<Sprite> {
+  game_state.entities.iter()
+    .filter_map(|entity_index| game_state.sprites.get(*entity_index))
+    .cloned()
+    .collect()
+}
+
+// Drawable entities sorted by shader program so that every program is bound only once per frame.
+// The sort is stable, entities that share a program keep their insertion order.
+fn draw_order(game_state: &GameState) -> DrawOrder {
+  let mut order: DrawOrder = game_state.entities.iter()
+    .filter(|entity_index| game_state.meshes.get(**entity_index).is_some())
+    .map(|entity_index| (program_of(game_state, *entity_index), *entity_index))
+    .collect();
+  order.sort_by_key(|(program_id, _)| *program_id);
+  order
+}
+
+#[cfg(test)]
+mod game_state_renderer_tests {
+  use super::*;
+  use cgmath::Vector3;
+  use crate::game_state::GameStateBuilder;
+  use cgmath::Point3;
+  use engine::camera::CameraBuilder;
+  use engine::culling::{ Aabb, BoundingVolume };
+  use engine::texture::Texture;
+  use engine::vao_builder::buffer_component::BufferComponent;
+
+  // no GL in these tests, a component without objects deletes nothing when dropped
+  fn mesh() -> Rc<BufferComponent> {
+    Rc::new(unsafe { BufferComponent::from_raw(0, vec![0], 0, 0) })
+  }
+
+  fn add_entity(game_state: &mut GameState, material: Option<Material>) -> GenerationalIndex {
+    let entity_index = game_state.entity_allocator.allocate();
+    game_state.meshes.set(entity_index, mesh());
+    if let Some(material) = material {
+      game_state.materials.set(entity_index, material);
+    }
+    game_state.entities.push(entity_index);
+    entity_index
+  }
+
+  #[test]
+  fn only_opaque_triangles_cast_shadows() {
+    assert!(casts_shadow(gl::TRIANGLES, BlendMode::Opaque));
+    assert!(!casts_shadow(gl::TRIANGLES, BlendMode::Alpha));
+    assert!(!casts_shadow(gl::POINTS, BlendMode::Opaque));
+  }
+
+  #[test]
+  fn draw_order_groups_entities_by_program() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    let a = add_entity(&mut game_state, Some(Material::new(ProgramId(1))));
+    let b = add_entity(&mut game_state, None);
+    let c = add_entity(&mut game_state, Some(Material::new(ProgramId(1))));
+    let d = add_entity(&mut game_state, Some(Material::new(ProgramId(0))));
+    // act
+    let order = draw_order(&game_state);
+    // assert
+    let programs: Vec<ProgramId> = order.iter().map(|(p, _)| *p).collect();
+    let indices: Vec<usize> = order.iter().map(|(_, e)| e.index()).collect();
+    assert_eq!(vec![ProgramId(0), ProgramId(0), ProgramId(1), ProgramId(1)], programs);
+    assert_eq!(vec![b.index(), d.index(), a.index(), c.index()], indices);
+  }
+
+  #[test]
+  fn render_queue_sorts_opaque_front_to_back_and_transparent_back_to_front() {
+    // arrange: the view is the identity, so the camera looks down -z from the origin
+    let mut game_state = GameStateBuilder::new().build();
+    let at_depth = |game_state: &mut GameState, depth: GLfloat, blend_mode: BlendMode| {
+      let entity_index = add_entity(game_state, Some(Material::new(ProgramId(0)).with_blend_mode(blend_mode)));
+      game_state.model_matrices.set(entity_index, Matrix4::from_translation(Vector3::new(0.0, 0.0, -depth)));
+      entity_index
+    };
+    let far_opaque = at_depth(&mut game_state, 5.0, BlendMode::Opaque);
+    let near_glass = at_depth(&mut game_state, 1.0, BlendMode::Alpha);
+    let near_opaque = at_depth(&mut game_state, 2.0, BlendMode::Opaque);
+    let far_glow = at_depth(&mut game_state, 8.0, BlendMode::Additive);
+    // act
+    let (opaque, transparent) = render_queue(&game_state, Matrix4::identity(), draw_order(&game_state), &[None]);
+    // assert
+    let opaque: Vec<usize> = opaque.iter().map(|(_, e)| e.index()).collect();
+    let transparent: Vec<usize> = transparent.iter().map(|(_, e)| e.index()).collect();
+    assert_eq!(vec![near_opaque.index(), far_opaque.index()], opaque);
+    assert_eq!(vec![far_glow.index(), near_glass.index()], transparent);
+  }
+
+  #[test]
+  fn frustum_cull_drops_entities_outside_of_the_view() {
+    // arrange: the camera looks from z = 5 at the origin
+    let mut game_state = GameStateBuilder::new().build();
+    let camera = CameraBuilder::new().with_eye(Point3::new(0.0, 0.0, 5.0)).build();
+    let frustum = Frustum::from_matrix(camera.projection_matrix * camera.view_matrix);
+    let at = |game_state: &mut GameState, position: Vector3<GLfloat>, bounded: bool| {
+      let entity_index = add_entity(game_state, None);
+      game_state.model_matrices.set(entity_index, Matrix4::from_translation(position));
+      if bounded {
+        game_state.bounds.set(entity_index, BoundingVolume::Box(Aabb::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5))));
+      }
+      entity_index
+    };
+    let in_view = at(&mut game_state, Vector3::new(0.0, 0.0, 0.0), true);
+    at(&mut game_state, Vector3::new(0.0, 0.0, 10.0), true);
+    at(&mut game_state, Vector3::new(50.0, 0.0, 0.0), true);
+    let unbounded = at(&mut game_state, Vector3::new(50.0, 0.0, 0.0), false);
+    let points = at(&mut game_state, Vector3::new(50.0, 0.0, 0.0), true);
+    game_state.draw_modes.set(points, gl::POINTS);
+    // act
+    let (visible, culled) = frustum_cull(&game_state, draw_order(&game_state), &frustum, gl::TRIANGLES);
+    // assert
+    let visible: Vec<usize> = visible.iter().map(|(_, e)| e.index()).collect();
+    assert_eq!(vec![in_view.index(), unbounded.index(), points.index()], visible);
+    assert_eq!(2, culled);
+  }
+
+  #[test]
+  fn batches_join_entities_sharing_a_mesh_and_an_equal_material() {
+    // arrange: program 1 is instanced, none of the meshes has an instance buffer of its own
+    let mut game_state = GameStateBuilder::new().build();
+    let with_mesh = |game_state: &mut GameState, shared: &Rc<BufferComponent>, material: Material| {
+      let entity_index = add_entity(game_state, Some(material));
+      game_state.meshes.set(entity_index, Rc::clone(shared));
+      entity_index
+    };
+    let (first, second) = (mesh(), mesh());
+    let plain = Material::new(ProgramId(1));
+    let tinted = Material::new(ProgramId(1)).with_float("Tint", 0.5);
+    with_mesh(&mut game_state, &first, plain.clone());
+    with_mesh(&mut game_state, &first, plain.clone());
+    with_mesh(&mut game_state, &first, tinted);
+    with_mesh(&mut game_state, &second, plain.clone());
+    with_mesh(&mut game_state, &second, plain);
+    with_mesh(&mut game_state, &first, Material::new(ProgramId(0)));
+    add_entity(&mut game_state, Some(Material::new(ProgramId(0))));
+    with_mesh(&mut game_state, &first, Material::new(ProgramId(0)));
+    let order = draw_order(&game_state);
+    // act
+    let (opaque, _) = render_queue(&game_state, Matrix4::identity(), order, &[None, Some(2)]);
+    let batches = batches(&game_state, &opaque);
+    // assert: the queue brings copies of a mesh together for the instanced program, meshes are in no particular order
+    let sizes = |program_id: ProgramId| {
+      let mut sizes: Vec<usize> = batches.iter().filter(|batch| batch[0].0 == program_id).map(|batch| batch.len()).collect();
+      sizes.sort();
+      sizes
+    };
+    assert_eq!(vec![1, 1, 1], sizes(ProgramId(0)));
+    assert_eq!(vec![1, 2, 2], sizes(ProgramId(1)));
+  }
+
+  #[test]
+  fn split_deferred_keeps_order_within_each_path() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    let forward = add_entity(&mut game_state, Some(Material::new(ProgramId(0))));
+    let first = add_entity(&mut game_state, Some(Material::new(ProgramId(1))));
+    let unknown = add_entity(&mut game_state, Some(Material::new(ProgramId(2))));
+    let second = add_entity(&mut game_state, Some(Material::new(ProgramId(1))));
+    // act
+    let (geometry, forward_order) = split_deferred(draw_order(&game_state), &[false, true]);
+    // assert
+    let geometry: Vec<usize> = geometry.iter().map(|(_, e)| e.index()).collect();
+    let forward_order: Vec<usize> = forward_order.iter().map(|(_, e)| e.index()).collect();
+    assert_eq!(vec![first.index(), second.index()], geometry);
+    assert_eq!(vec![forward.index(), unknown.index()], forward_order);
+  }
+
+  #[test]
+  fn draw_order_skips_entities_without_mesh() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    let light = game_state.add_point_light(PointLight::new(cgmath::Point3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 1.0, 1.0), 1.0, 10.0));
+    let mesh = add_entity(&mut game_state, None);
+    // no GL in these tests, a texture without a handle is never deleted
+    let sprite = game_state.add_sprite(Sprite::new(&Rc::new(Texture { handle: 0, width: 16, height: 16 })));
+    // act
+    let order = draw_order(&game_state);
+    let (directional_lights, point_lights) = collect_lights(&game_state);
+    let sprites = collect_sprites(&game_state);
+    // assert
+    assert_eq!(1, order.len());
+    assert_eq!(mesh.index(), order[0].1.index());
+    assert_ne!(light.index(), order[0].1.index());
+    assert_ne!(sprite.index(), order[0].1.index());
+    assert!(directional_lights.is_empty());
+    assert_eq!(1, point_lights.len());
+    assert_eq!(1, sprites.len());
+  }
+}
@@ -0,0 +1,21 @@
+#version 450
+
+// Writes the material of the lit shader into the G-buffer, used with lit/vertex.glsl.
+// The outputs must match engine::deferred.
+
+uniform vec3 DiffuseColor = vec3(0.8);
+uniform vec3 SpecularColor = vec3(1.0);
+uniform float Shininess = 32.0;
+
+in vec3 WorldPosition;
+in vec3 WorldNormal;
+
+layout (location = 0) out vec4 GAlbedo;
+layout (location = 1) out vec4 GNormal;
+
+void main()
+{
+    float specularIntensity = dot(SpecularColor, vec3(1.0 / 3.0));
+    GAlbedo = vec4(DiffuseColor, specularIntensity);
+    GNormal = vec4(normalize(WorldNormal), Shininess);
+}
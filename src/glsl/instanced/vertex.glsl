@@ -0,0 +1,21 @@
+#version 450
+
+layout (location = 0) in vec3 VertexPosition;
+layout (location = 1) in vec4 VertexColor;
+// one model matrix per instance, takes the locations 2 to 5
+layout (location = 2) in mat4 InstanceModel;
+
+layout (std140) uniform Camera {
+  mat4 View;
+  mat4 Projection;
+  vec3 EyePosition;
+  float Time;
+};
+
+out vec4 Color;
+
+void main()
+{
+    Color = VertexColor;
+    gl_Position = Projection * View * InstanceModel * vec4(VertexPosition, 1.0);
+}
@@ -0,0 +1,93 @@
+#version 450
+
+// must match MAX_DIRECTIONAL_LIGHTS, MAX_POINT_LIGHTS in engine::light and MAX_SHADOW_MAPS in engine::shadow
+#define MAX_DIRECTIONAL_LIGHTS 4
+#define MAX_POINT_LIGHTS 8
+#define MAX_SHADOW_MAPS 2
+
+struct DirectionalLight {
+    vec3 Direction;
+    vec3 Color;
+    int ShadowMap; // index into ShadowMaps, -1 if the light casts no shadows
+};
+
+struct PointLight {
+    vec3 Position;
+    vec3 Color;
+    vec3 Attenuation; // constant, linear, quadratic
+};
+
+uniform DirectionalLight DirectionalLights[MAX_DIRECTIONAL_LIGHTS];
+uniform int DirectionalLightCount;
+uniform PointLight PointLights[MAX_POINT_LIGHTS];
+uniform int PointLightCount;
+layout (std140) uniform Camera {
+  mat4 View;
+  mat4 Projection;
+  vec3 EyePosition;
+  float Time;
+};
+uniform sampler2DShadow ShadowMaps[MAX_SHADOW_MAPS];
+uniform mat4 ShadowMatrices[MAX_SHADOW_MAPS];
+
+uniform vec3 AmbientColor = vec3(0.05);
+uniform vec3 DiffuseColor = vec3(0.8);
+uniform vec3 SpecularColor = vec3(1.0);
+uniform float Shininess = 32.0;
+
+in vec3 WorldPosition;
+in vec3 WorldNormal;
+out vec4 FragmentColor;
+
+vec3 phong(vec3 normal, vec3 toLight, vec3 toEye, vec3 radiance)
+{
+    float diffuse = max(dot(normal, toLight), 0.0);
+    float specular = 0.0;
+    if (diffuse > 0.0) {
+        vec3 reflected = reflect(-toLight, normal);
+        specular = pow(max(dot(reflected, toEye), 0.0), Shininess);
+    }
+    return radiance * (DiffuseColor * diffuse + SpecularColor * specular);
+}
+
+// 3x3 percentage closer filtering, 1.0 is fully lit
+float shadowFactor(int shadowMap, vec3 normal, vec3 toLight)
+{
+    vec4 lightSpace = ShadowMatrices[shadowMap] * vec4(WorldPosition, 1.0);
+    vec3 projected = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (projected.z > 1.0) {
+        return 1.0;
+    }
+    float bias = max(0.004 * (1.0 - dot(normal, toLight)), 0.0005);
+    vec2 texelSize = 1.0 / vec2(textureSize(ShadowMaps[shadowMap], 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(ShadowMaps[shadowMap], vec3(projected.xy + vec2(x, y) * texelSize, projected.z - bias));
+        }
+    }
+    return lit / 9.0;
+}
+
+void main()
+{
+    vec3 normal = normalize(WorldNormal);
+    vec3 toEye = normalize(EyePosition - WorldPosition);
+    vec3 color = AmbientColor * DiffuseColor;
+    for (int i = 0; i < DirectionalLightCount; i++) {
+        vec3 toLight = -DirectionalLights[i].Direction;
+        float shadow = 1.0;
+        if (DirectionalLights[i].ShadowMap >= 0) {
+            shadow = shadowFactor(DirectionalLights[i].ShadowMap, normal, toLight);
+        }
+        color += shadow * phong(normal, toLight, toEye, DirectionalLights[i].Color);
+    }
+    for (int i = 0; i < PointLightCount; i++) {
+        vec3 toLight = PointLights[i].Position - WorldPosition;
+        float distance = length(toLight);
+        vec3 k = PointLights[i].Attenuation;
+        float attenuation = 1.0 / (k.x + k.y * distance + k.z * distance * distance);
+        color += phong(normal, toLight / distance, toEye, PointLights[i].Color * attenuation);
+    }
+    FragmentColor = vec4(color, 1.0);
+}
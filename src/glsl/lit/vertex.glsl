@@ -0,0 +1,23 @@
+#version 450
+
+layout (location = 0) in vec3 VertexPosition;
+layout (location = 1) in vec3 VertexNormal;
+
+uniform mat4 Model;
+layout (std140) uniform Camera {
+  mat4 View;
+  mat4 Projection;
+  vec3 EyePosition;
+  float Time;
+};
+
+out vec3 WorldPosition;
+out vec3 WorldNormal;
+
+void main()
+{
+    vec4 worldPosition = Model * vec4(VertexPosition, 1.0);
+    WorldPosition = worldPosition.xyz;
+    WorldNormal = mat3(transpose(inverse(Model))) * VertexNormal;
+    gl_Position = Projection * View * worldPosition;
+}
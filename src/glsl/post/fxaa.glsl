@@ -0,0 +1,47 @@
+#version 450
+
+// FXAA after the simplified version of Timothy Lottes' FXAA 3.11 console shader
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+uniform vec2 TexelSize;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+
+float luma(vec3 color)
+{
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main()
+{
+    float lumaNW = luma(texture(Source, UV + vec2(-1.0, -1.0) * TexelSize).rgb);
+    float lumaNE = luma(texture(Source, UV + vec2(1.0, -1.0) * TexelSize).rgb);
+    float lumaSW = luma(texture(Source, UV + vec2(-1.0, 1.0) * TexelSize).rgb);
+    float lumaSE = luma(texture(Source, UV + vec2(1.0, 1.0) * TexelSize).rgb);
+    vec4 center = texture(Source, UV);
+    float lumaM = luma(center.rgb);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    // blur along the edge, perpendicular to the luma gradient
+    vec2 direction = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float directionReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float inverseDirectionMin = 1.0 / (min(abs(direction.x), abs(direction.y)) + directionReduce);
+    direction = clamp(direction * inverseDirectionMin, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * TexelSize;
+
+    vec3 rgbA = 0.5 * (
+        texture(Source, UV + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(Source, UV + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (
+        texture(Source, UV + direction * -0.5).rgb +
+        texture(Source, UV + direction * 0.5).rgb);
+    float lumaB = luma(rgbB);
+    // the wider sample crossed another edge, fall back to the narrow one
+    vec3 color = (lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB;
+    FragmentColor = vec4(color, center.a);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+uniform float Gamma = 2.2;
+
+void main()
+{
+    vec4 color = texture(Source, UV);
+    FragmentColor = vec4(pow(color.rgb, vec3(1.0 / Gamma)), color.a);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+// 0 keeps the colors, 1 is fully gray
+uniform float Amount = 1.0;
+
+void main()
+{
+    vec4 color = texture(Source, UV);
+    float luminance = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+    FragmentColor = vec4(mix(color.rgb, vec3(luminance), Amount), color.a);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Source;
+// distance from the center where darkening starts, and how dark the corners get
+uniform float Radius = 0.5;
+uniform float Strength = 0.6;
+
+void main()
+{
+    vec4 color = texture(Source, UV);
+    float distanceFromCenter = length(UV - vec2(0.5)) * 1.41421356;
+    float vignette = 1.0 - Strength * smoothstep(Radius, 1.0, distanceFromCenter);
+    FragmentColor = vec4(color.rgb * vignette, color.a);
+}
@@ -0,0 +1,6 @@
+#version 450
+
+// depth only, the depth buffer is written by the fixed function pipeline
+void main()
+{
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout (location = 0) in vec3 VertexPosition;
+
+uniform mat4 Model;
+uniform mat4 LightViewProjection;
+
+void main()
+{
+    gl_Position = LightViewProjection * Model * vec4(VertexPosition, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+
+in vec2 UV;
+out vec4 FragmentColor;
+
+uniform sampler2D Albedo;
+
+void main()
+{
+    FragmentColor = texture(Albedo, UV);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout (location = 0) in vec3 VertexPosition;
+layout (location = 1) in vec2 VertexUV;
+
+uniform mat4 Model;
+layout (std140) uniform Camera {
+  mat4 View;
+  mat4 Projection;
+  vec3 EyePosition;
+  float Time;
+};
+
+out vec2 UV;
+
+void main()
+{
+    UV = VertexUV;
+    gl_Position = Projection * View * Model * vec4(VertexPosition, 1.0);
+}
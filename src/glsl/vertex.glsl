@@ -4,8 +4,12 @@ layout (location = 0) in vec3 VertexPosition;
 layout (location = 1) in vec4 VertexColor;
 
 uniform mat4 Model;
-uniform mat4 View;
-uniform mat4 Projection;
+layout (std140) uniform Camera {
+  mat4 View;
+  mat4 Projection;
+  vec3 EyePosition;
+  float Time;
+};
 
 out vec4 Color;
 
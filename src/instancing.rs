@@ -0,0 +1,85 @@
+// external crates
+#[macro_use]
+extern crate if_chain;
+#[macro_use]
+extern crate log;
+use gl::types::{ GLfloat, GLuint };
+use cgmath::{ Matrix4, Point3, Vector3 };
+use engine::camera;
+use engine::shader_program;
+use engine::material::Material;
+use camera::CameraBuilder;
+use shader_program::ShaderProgramBuilder;
+// modules
+mod context;
+mod model_creator;
+use model_creator::POSITION_COLOR;
+mod event_handler;
+mod game_state;
+mod game_builder;
+use game_builder::*;
+mod game_state_renderer;
+mod frame_stats;
+
+const GRID_SIZE: usize = 100;
+const SPACING: GLfloat = 0.15;
+// two triangles of the quad
+const QUAD_INDICES: &[GLuint] = &[0, 1, 2, 0, 2, 3];
+
+fn main() -> Result<(), String> {
+  start_game()
+}
+
+// Benchmark: a grid of 10,000 copies of a triangle and a quad. The copies of each mesh share its material,
+// so the renderer draws them with one instanced call per mesh, the quad through its index buffer.
+// Run with --no-instancing to draw them one by one for comparison.
+fn start_game() -> Result<(), String> {
+  let instanced = !std::env::args().any(|arg| arg == "--no-instancing");
+  let vertex_glsl = if instanced { include_str!("../src/glsl/instanced/vertex.glsl") } else { include_str!("../src/glsl/vertex.glsl") };
+  let game_builder = GameBuilder::new()
+    .with_name(if instanced { "Hello Instancing" } else { "Hello Instancing (disabled)" });
+  let mut game = game_builder.build();
+  let program = ShaderProgramBuilder::new()
+    .with_vertex_shader(vertex_glsl)
+    .with_fragment_shader(include_str!("../src/glsl/fragment.glsl"))
+    .build();
+  let program_id = game.add_shader_program(program);
+  // plain models, neither of them has an instance buffer of its own
+  let triangle = game.add_model_with_material(get_triangle_vertices(), Material::new(program_id), gl::TRIANGLES);
+  let quad = game.add_indexed_model(get_quad_vertices(), QUAD_INDICES, POSITION_COLOR, Material::new(program_id), gl::TRIANGLES)?;
+  game.game_state.model_matrices.set(triangle, grid_matrix(0));
+  game.game_state.model_matrices.set(quad, grid_matrix(1));
+  for i in 2..GRID_SIZE * GRID_SIZE {
+    game.add_instance(if i % 2 == 0 { triangle } else { quad }, grid_matrix(i));
+  }
+  game.game_state.camera = Some(CameraBuilder::new()
+    .with_eye(Point3::new(0.0, 0.0, -18.0))
+    .with_target(Point3::new(0.0, 0.0, 0.0))
+    .build());
+  game.run()
+}
+
+fn grid_matrix(i: usize) -> Matrix4<GLfloat> {
+  let offset = (GRID_SIZE - 1) as GLfloat * SPACING / 2.0;
+  let (x, y) = ((i % GRID_SIZE) as GLfloat * SPACING - offset, (i / GRID_SIZE) as GLfloat * SPACING - offset);
+  Matrix4::from_translation(Vector3::new(x, y, 0.0)) * Matrix4::from_scale(0.1)
+}
+
+fn get_quad_vertices() -> Vec<GLfloat> {
+  vec![
+    // X    Y   Z       R     G     B   A
+    -0.4, -0.4, 0.0,    1.0, 1.0, 0.0, 1.0,
+     0.4, -0.4, 0.0,    0.0, 1.0, 1.0, 1.0,
+     0.4,  0.4, 0.0,    1.0, 0.0, 1.0, 1.0,
+    -0.4,  0.4, 0.0,    1.0, 1.0, 1.0, 1.0,
+  ]
+}
+
+fn get_triangle_vertices() -> Vec<GLfloat> {
+  vec![
+    // X    Y   Z       R     G     B   A
+     0.0,  0.5, 0.0,    1.0, 0.0, 0.0, 1.0,
+    -0.5, -0.5, 0.0,    0.0, 1.0, 0.0, 1.0,
+     0.5, -0.5, 0.0,    0.0, 0.0, 1.0, 1.0,
+  ]
+}
@@ -0,0 +1,88 @@
+// external crates
+#[macro_use]
+extern crate if_chain;
+#[macro_use]
+extern crate log;
+use gl::types::GLfloat;
+use cgmath::{ Matrix4, Point3, Vector3 };
+use engine::camera;
+use engine::shader_program;
+use engine::material::{ Material, ProgramId, UniformValue };
+use engine::light::{ DirectionalLight, PointLight };
+use engine::mesh::{ ground_plane, smooth_normals, interleave_position_normal };
+use shader_program::ShaderProgramBuilder;
+// modules
+mod context;
+mod model_creator;
+use model_creator::{ POSITION_COLOR, POSITION_NORMAL };
+mod event_handler;
+mod game_state;
+mod game_builder;
+use game_builder::*;
+mod game_state_renderer;
+mod frame_stats;
+mod fbx_loader;
+use fbx_loader::load_fbx_mesh;
+
+const LIGHT_COUNT: usize = 32;
+
+fn main() -> Result<(), String> {
+  start_game()
+}
+
+// The teapot lit by a ring of colored point lights through deferred shading,
+// with a vertex colored triangle that is drawn forward on top
+fn start_game() -> Result<(), String> {
+  let game_builder = GameBuilder::new()
+    .with_shaders(include_str!("../src/glsl/lit/vertex.glsl"), include_str!("../src/glsl/deferred/fragment.glsl"))
+    .with_deferred_shading()
+    .with_shadows(2048)
+    .with_name("Hello Lights");
+  let mut game = game_builder.build();
+  let mesh = load_fbx_mesh("teapot.fbx")?;
+  let normals = smooth_normals(&mesh.positions, &mesh.indices);
+  let vertices = interleave_position_normal(&mesh.positions, &normals, &mesh.indices);
+  let material = Material::new(ProgramId(0))
+    .with_uniform("DiffuseColor", UniformValue::Vec3(Vector3::new(0.9, 0.9, 0.9)))
+    .with_float("Shininess", 64.0);
+  let teapot = game.add_model_with_layout(vertices, POSITION_NORMAL, material, gl::TRIANGLES);
+  let model_matrix = Matrix4::from_translation(Vector3::new(0.0, -0.35, 0.0)) * Matrix4::from_scale(0.45);
+  game.game_state.model_matrices.set(teapot, model_matrix);
+  let ground_material = Material::new(ProgramId(0))
+    .with_uniform("DiffuseColor", UniformValue::Vec3(Vector3::new(0.6, 0.6, 0.6)))
+    .with_float("Shininess", 8.0);
+  game.add_model_with_layout(ground_plane(1.5, -0.35), POSITION_NORMAL, ground_material, gl::TRIANGLES);
+
+  let forward_program = ShaderProgramBuilder::new()
+    .with_vertex_shader(include_str!("../src/glsl/vertex.glsl"))
+    .with_fragment_shader(include_str!("../src/glsl/fragment.glsl"))
+    .build();
+  let forward_program_id = game.add_shader_program(forward_program);
+  let triangle = game.add_model_with_layout(get_triangle_vertices(), POSITION_COLOR, Material::new(forward_program_id), gl::TRIANGLES);
+  game.game_state.model_matrices.set(triangle, Matrix4::from_translation(Vector3::new(0.0, 0.4, 0.0)) * Matrix4::from_scale(0.3));
+
+  let sun = DirectionalLight::new(Vector3::new(-0.5, -1.0, 0.8), Vector3::new(1.0, 1.0, 0.95), 0.2).with_shadows();
+  game.game_state.add_directional_light(sun);
+  for i in 0..LIGHT_COUNT {
+    let angle = 2.0 * std::f32::consts::PI * i as GLfloat / LIGHT_COUNT as GLfloat;
+    let position = Point3::new(1.1 * angle.cos(), -0.25, 1.1 * angle.sin());
+    game.game_state.add_point_light(PointLight::new(position, hue(angle), 0.6, 0.8));
+  }
+  game.run()
+}
+
+// Fully saturated color for an angle around the color wheel
+fn hue(angle: GLfloat) -> Vector3<GLfloat> {
+  let channel = |offset: GLfloat| 0.5 + 0.5 * (angle + offset).cos();
+  let third = 2.0 * std::f32::consts::PI / 3.0;
+  Vector3::new(channel(0.0), channel(-third), channel(third))
+}
+
+fn get_triangle_vertices() -> Vec<GLfloat> {
+  vec![
+    // X    Y   Z       R     G     B   A
+     0.0,  0.5, 0.0,    1.0, 0.0, 0.0, 1.0,
+    -0.5, -0.5, 0.0,    0.0, 1.0, 0.0, 1.0,
+     0.5, -0.5, 0.0,    0.0, 0.0, 1.0, 1.0,
+  ]
+}
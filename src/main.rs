@@ -1,8 +1,9 @@
 // external crates
 #[macro_use]
 extern crate if_chain;
+#[macro_use]
+extern crate log;
 // use gl::types::*;
-use gl::types::GLfloat;
 use engine::camera;
 use engine::shader_program;
 // modules
@@ -15,6 +16,7 @@ use game_builder::*;
 mod triangle_creator;
 use triangle_creator::*;
 mod game_state_renderer;
+mod frame_stats;
 
 fn main() -> Result<(), String> {
   start_game()
@@ -0,0 +1,57 @@
+// external crates
+#[macro_use]
+extern crate if_chain;
+#[macro_use]
+extern crate log;
+use gl::types::GLfloat;
+use cgmath::{ Matrix4, Vector3 };
+use engine::camera;
+use engine::shader_program;
+use engine::material::Material;
+use shader_program::ShaderProgramBuilder;
+// modules
+mod context;
+mod model_creator;
+mod event_handler;
+mod game_state;
+mod game_builder;
+use game_builder::*;
+mod game_state_renderer;
+mod frame_stats;
+
+fn main() -> Result<(), String> {
+  start_game()
+}
+
+// Renders the same triangle twice, side by side: once with the default triangle shader
+// and once with the point geometry shader, each through its own material.
+fn start_game() -> Result<(), String> {
+  let vertex_glsl: &str = include_str!("../src/glsl/vertex.glsl");
+  let fragment_glsl: &str = include_str!("../src/glsl/fragment.glsl");
+  let game_builder = GameBuilder::new()
+    .with_shaders(vertex_glsl, fragment_glsl)
+    .with_name("Hello Materials");
+  let mut game = game_builder.build();
+  let point_program = ShaderProgramBuilder::new()
+    .with_vertex_shader(include_str!("../src/glsl/point_render/vertex.glsl"))
+    .with_geometry_shader(include_str!("../src/glsl/point_render/geometry.glsl"))
+    .with_fragment_shader(include_str!("../src/glsl/point_render/fragment.glsl"))
+    .build();
+  let point_program_id = game.add_shader_program(point_program);
+  let triangle_material = Material::new(Default::default());
+  let point_material = Material::new(point_program_id).with_float("Size", 0.05);
+  let triangle = game.add_model_with_material(get_triangle_vertices(), triangle_material, gl::TRIANGLES);
+  let points = game.add_model_with_material(get_triangle_vertices(), point_material, gl::POINTS);
+  game.game_state.model_matrices.set(triangle, Matrix4::from_translation(Vector3::new(-0.6, 0.0, 0.0)));
+  game.game_state.model_matrices.set(points, Matrix4::from_translation(Vector3::new(0.6, 0.0, 0.0)));
+  game.run()
+}
+
+fn get_triangle_vertices() -> Vec<GLfloat> {
+  vec![
+    // X    Y   Z       R     G     B   A
+     0.0,  0.5, 0.0,    1.0, 0.0, 0.0, 1.0,
+    -0.5, -0.5, 0.0,    0.0, 1.0, 0.0, 1.0,
+     0.5, -0.5, 0.0,    0.0, 0.0, 1.0, 1.0,
+  ]
+}
@@ -1,57 +1,366 @@
 use engine::vao_builder::buffer_component::BufferComponent;
-use std::mem::size_of;
+use std::mem::{ size_of, size_of_val };
+use std::rc::Rc;
 use gl::types::*;
 use cgmath::{ Matrix4, SquareMatrix };
 use crate::game_state::GameState;
 use engine::vao_builder::VaoBuilder;
 use engine::vao_builder::attrib_parameters::AttribParameters;
+use engine::ecs::generational_index::GenerationalIndex;
+use engine::material::Material;
+use engine::culling::{ Aabb, BoundingVolume };
+use engine::dynamic_buffer::{ BufferUsage, DynamicBuffer };
+use engine::vertex::{ as_bytes, positions, Vertex };
+
+// Vertex layouts as floats per attribute, in order of attribute location, for untyped vertices
+// (see add_vertex_model for vertex structs).
+// The position comes first, the bounds of a model are taken from it.
+pub const POSITION_COLOR: &[GLint] = &[3, 4];
+#[allow(dead_code)]
+pub const POSITION_UV: &[GLint] = &[3, 2];
+#[allow(dead_code)]
+pub const POSITION_NORMAL: &[GLint] = &[3, 3];
 
 pub fn add_model(game_state: &mut GameState, vertices: Vec<GLfloat>) -> Option<()> {
-  let (buffers, floats_per_vertex) = build_buffers(game_state);
-  let vertex_count = unsafe { populate_vbo(buffers.vbo, floats_per_vertex, vertices) };
-  add_to_game(buffers, game_state, vertex_count);
+  let (buffers, floats_per_vertex) = build_buffers(POSITION_COLOR, false);
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  unsafe { populate_vbo(buffers.vbo(), &vertices); }
+  let vertex_count = (vertices.len() / floats_per_vertex) as GLsizei;
+  add_to_game(buffers, game_state, vertex_count, bounds);
   Some(())
 }
 
-fn build_buffers(game_state: &GameState) -> (BufferComponent, usize) {
-  // todo: get attributes from program (floats per attribute, floats per vertex)
-  // game_state.program?.get_active_attributes();
-  let floats_per_vertex: usize = 7;
-  let buffers = VaoBuilder::new()
-    .with_attribute(AttribParameters{ // position
-      floats_per_attribute: 3,
-      floats_per_vertex,
-      offset: 0
-    })
-    .with_attribute(AttribParameters{ // color
-      floats_per_attribute: 4,
-      floats_per_vertex,
-      offset: 3
-    })
-    .build();
-  (buffers, floats_per_vertex)
-}
-
-unsafe fn populate_vbo(vbo: GLuint, floats_per_vertex: usize, vertices: Vec<GLfloat>) -> GLsizei {
+// Adds a model from typed vertices, the attributes follow the fields of the vertex struct
+#[allow(dead_code)]
+pub fn add_vertex_model<V: Vertex>(game_state: &mut GameState, vertices: &[V]) -> GenerationalIndex {
+  let buffers = VaoBuilder::new().with_vertex::<V>().build();
+  let bounds = Aabb::from_positions(positions(vertices));
+  unsafe { populate_vbo(buffers.vbo(), as_bytes(vertices)); }
+  add_to_game(buffers, game_state, vertices.len() as GLsizei, bounds)
+}
+
+// Adds a model that is drawn with the given material and primitive mode (e.g. gl::POINTS)
+#[allow(dead_code)]
+pub fn add_model_with_material(game_state: &mut GameState, vertices: Vec<GLfloat>, material: Material, mode: GLenum) -> GenerationalIndex {
+  add_model_with_layout(game_state, vertices, POSITION_COLOR, material, mode)
+}
+
+#[allow(dead_code)]
+pub fn add_model_with_layout(game_state: &mut GameState, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum) -> GenerationalIndex {
+  let (buffers, floats_per_vertex) = build_buffers(layout, false);
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  unsafe { populate_vbo(buffers.vbo(), &vertices); }
+  let vertex_count = (vertices.len() / floats_per_vertex) as GLsizei;
+  let entity_index = add_to_game(buffers, game_state, vertex_count, bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  entity_index
+}
+
+// Adds a model whose vertex layout is taken from the inputs of the material's vertex shader,
+// fails when the vertices with floats_per_vertex do not fit the inputs
+#[allow(dead_code)]
+pub fn add_model_for_program(game_state: &mut GameState, vertices: Vec<GLfloat>, floats_per_vertex: usize, material: Material, mode: GLenum) -> Result<GenerationalIndex, String> {
+  let program = game_state.shader_programs.get(material.program.0).ok_or("Material refers to a shader program that is not in GameState")?;
+  let builder = unsafe { VaoBuilder::from_program(program)? };
+  builder.check_vertex_stride(floats_per_vertex * size_of::<GLfloat>())?;
+  let vertex_count = builder.float_vertex_count(&vertices)?;
+  let buffers = builder.build();
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  unsafe { populate_vbo(buffers.vbo(), &vertices); }
+  let entity_index = add_to_game(buffers, game_state, vertex_count as GLsizei, bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  Ok(entity_index)
+}
+
+// Adds a model whose copies (see add_instance) are drawn with a single instanced draw call,
+// when their material's program takes the InstanceModel attribute (see engine::instancing)
+#[allow(dead_code)]
+pub fn add_instanced_model(game_state: &mut GameState, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum) -> GenerationalIndex {
+  let (buffers, floats_per_vertex) = build_buffers(layout, true);
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  unsafe { populate_vbo(buffers.vbo(), &vertices); }
+  let vertex_count = (vertices.len() / floats_per_vertex) as GLsizei;
+  let entity_index = add_to_game(buffers, game_state, vertex_count, bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  entity_index
+}
+
+// Adds a model whose vertices are drawn through an index buffer of 32 bit indices
+#[allow(dead_code)]
+pub fn add_indexed_model(game_state: &mut GameState, vertices: Vec<GLfloat>, indices: &[GLuint], layout: &[GLint], material: Material, mode: GLenum) -> Result<GenerationalIndex, String> {
+  let (builder, floats_per_vertex) = with_layout(VaoBuilder::new().with_ibo(), layout)?;
+  let vertex_count = vertices.len() / floats_per_vertex.max(1);
+  if let Some(index) = indices.iter().find(|index| **index as usize >= vertex_count) {
+    return Err(format!("Index {} is out of range for {} vertices", index, vertex_count));
+  }
+  let buffers = builder.build();
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  unsafe {
+    populate_vbo(buffers.vbo(), &vertices);
+    populate_ibo(buffers.vao(), buffers.ibo(), indices);
+  }
+  // the draw count of an indexed mesh is its number of indices
+  let entity_index = add_to_game(buffers, game_state, indices.len() as GLsizei, bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  Ok(entity_index)
+}
+
+// A new entity that shares the mesh, material and draw mode of another entity
+#[allow(dead_code)]
+pub fn add_instance(game_state: &mut GameState, mesh_entity: GenerationalIndex, model_matrix: Matrix4<GLfloat>) -> Option<GenerationalIndex> {
+  let mesh = Rc::clone(game_state.meshes.get(mesh_entity)?);
+  let vertex_count = *game_state.vertex_counts.get(mesh_entity)?;
+  let bounds = game_state.bounds.get(mesh_entity).cloned();
+  let material = game_state.materials.get(mesh_entity).cloned();
+  let draw_mode = game_state.draw_modes.get(mesh_entity).cloned();
+  let entity_index = game_state.entity_allocator.allocate();
+  game_state.meshes.set(entity_index, mesh);
+  game_state.vertex_counts.set(entity_index, vertex_count);
+  game_state.model_matrices.set(entity_index, model_matrix);
+  if let Some(bounds) = bounds { game_state.bounds.set(entity_index, bounds); }
+  if let Some(material) = material { game_state.materials.set(entity_index, material); }
+  if let Some(draw_mode) = draw_mode { game_state.draw_modes.set(entity_index, draw_mode); }
+  game_state.entities.push(entity_index);
+  Some(entity_index)
+}
+
+// Adds a model whose vertices can be replaced or changed later, usage is Dynamic or Stream
+#[allow(dead_code)]
+pub fn add_dynamic_model(game_state: &mut GameState, vertices: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum, usage: BufferUsage) -> GenerationalIndex {
+  let (buffers, floats_per_vertex) = build_buffers(layout, false);
+  let bounds = Aabb::from_vertices(&vertices, floats_per_vertex);
+  let mut dynamic_buffer = DynamicBuffer::new(0, floats_per_vertex, usage);
+  unsafe { dynamic_buffer.upload(&buffers, &vertices); }
+  let entity_index = add_to_game(buffers, game_state, dynamic_buffer.vertex_count(), bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  game_state.dynamic_buffers.set(entity_index, dynamic_buffer);
+  entity_index
+}
+
+// Adds a model whose positions (three floats per vertex, location 0) are in a dynamic buffer of their own
+// and whose other attributes (layout, from location 1 on) are in a static buffer.
+// update_vertices and update_vertex_range then only upload positions, and fail when there would be
+// more positions than attribute vertices.
+#[allow(dead_code)]
+pub fn add_animated_model(game_state: &mut GameState, positions: Vec<GLfloat>, attributes: Vec<GLfloat>, layout: &[GLint], material: Material, mode: GLenum, usage: BufferUsage) -> Result<GenerationalIndex, String> {
+  let builder = VaoBuilder::new().with_attribute(AttribParameters::floats(3, 3, 0)).with_buffer();
+  let (builder, floats_per_vertex) = with_layout(builder, layout)?;
+  let vertex_count = split_vertex_count(&positions, &attributes, floats_per_vertex)?;
+  let buffers = builder.build();
+  let bounds = Aabb::from_vertices(&positions, 3);
+  let mut dynamic_buffer = DynamicBuffer::new(0, 3, usage);
+  unsafe {
+    dynamic_buffer.upload(&buffers, &positions);
+    populate_vbo(buffers.vbo_at(1), &attributes);
+  }
+  let entity_index = add_to_game(buffers, game_state, dynamic_buffer.vertex_count(), bounds);
+  game_state.materials.set(entity_index, material);
+  game_state.draw_modes.set(entity_index, mode);
+  game_state.dynamic_buffers.set(entity_index, dynamic_buffer);
+  game_state.vertex_limits.set(entity_index, vertex_count as usize);
+  Ok(entity_index)
+}
+
+// Replaces all vertices of a dynamic model, the vertex count and bounds follow
+#[allow(dead_code)]
+pub fn update_vertices(game_state: &mut GameState, entity_index: GenerationalIndex, vertices: &[GLfloat]) -> Result<(), String> {
+  let vertex_limit = game_state.vertex_limits.get(entity_index).cloned();
+  let dynamic_buffer = game_state.dynamic_buffers.get_mut(entity_index).ok_or("Entity has no dynamic vertex buffer")?;
+  check_vertex_limit(vertex_limit, vertices.len() / dynamic_buffer.floats_per_vertex())?;
+  let mesh = game_state.meshes.get(entity_index).ok_or("Entity has no mesh")?;
+  unsafe { dynamic_buffer.upload(mesh, vertices); }
+  let bounds = Aabb::from_vertices(vertices, dynamic_buffer.floats_per_vertex());
+  let vertex_count = dynamic_buffer.vertex_count();
+  set_mesh_size(game_state, entity_index, vertex_count, bounds.map(BoundingVolume::Box));
+  Ok(())
+}
+
+// Overwrites the vertices from first_vertex on, vertices past the end are appended.
+// The bounds only grow, so that they keep enclosing the vertices that were not touched.
+#[allow(dead_code)]
+pub fn update_vertex_range(game_state: &mut GameState, entity_index: GenerationalIndex, first_vertex: usize, vertices: &[GLfloat]) -> Result<(), String> {
+  let vertex_limit = game_state.vertex_limits.get(entity_index).cloned();
+  let dynamic_buffer = game_state.dynamic_buffers.get_mut(entity_index).ok_or("Entity has no dynamic vertex buffer")?;
+  let floats_per_vertex = dynamic_buffer.floats_per_vertex();
+  check_vertex_limit(vertex_limit, first_vertex + vertices.len() / floats_per_vertex)?;
+  let mesh = game_state.meshes.get(entity_index).ok_or("Entity has no mesh")?;
+  unsafe { dynamic_buffer.update(mesh, first_vertex * floats_per_vertex, vertices)?; }
+  let vertex_count = dynamic_buffer.vertex_count();
+  let bounds = match (game_state.bounds.get(entity_index), Aabb::from_vertices(vertices, floats_per_vertex)) {
+    (Some(BoundingVolume::Box(aabb)), Some(range)) => Some(BoundingVolume::Box(aabb.union(&range))),
+    (Some(bounds), _) => Some(*bounds),
+    (None, range) => range.map(BoundingVolume::Box)
+  };
+  set_mesh_size(game_state, entity_index, vertex_count, bounds);
+  Ok(())
+}
+
+// The other attributes of an animated model end at its vertex limit, drawing past them would read past their buffer
+fn check_vertex_limit(vertex_limit: Option<usize>, vertex_count: usize) -> Result<(), String> {
+  match vertex_limit {
+    Some(limit) if vertex_count > limit => Err(format!("{} vertices do not fit the attributes of {} vertices", vertex_count, limit)),
+    _ => Ok(())
+  }
+}
+
+// Updates every entity that shares the mesh of the changed one
+fn set_mesh_size(game_state: &mut GameState, entity_index: GenerationalIndex, vertex_count: GLsizei, bounds: Option<BoundingVolume>) {
+  let mesh = match game_state.meshes.get(entity_index) {
+    Some(mesh) => Rc::clone(mesh),
+    None => return
+  };
+  let sharing: Vec<GenerationalIndex> = game_state.entities.iter()
+    .filter(|other| game_state.meshes.get(**other).is_some_and(|other_mesh| Rc::ptr_eq(other_mesh, &mesh)))
+    .cloned()
+    .collect();
+  for other in sharing {
+    game_state.vertex_counts.set(other, vertex_count);
+    if let Some(bounds) = bounds {
+      game_state.bounds.set(other, bounds);
+    }
+  }
+}
+
+// Panics on a layout with attributes of more than four floats
+fn build_buffers(layout: &[GLint], instanced: bool) -> (BufferComponent, usize) {
+  let (mut builder, floats_per_vertex) = with_layout(VaoBuilder::new(), layout).unwrap_or_else(|message| panic!("{}", message));
+  if instanced {
+    builder = builder.with_instance_matrix();
+  }
+  (builder.build(), floats_per_vertex)
+}
+
+// Adds the attributes of the layout, interleaved in the current buffer of the builder
+fn with_layout(mut builder: VaoBuilder, layout: &[GLint]) -> Result<(VaoBuilder, usize), String> {
+  let floats_per_vertex: usize = layout.iter().sum::<GLint>() as usize;
+  let mut offset: usize = 0;
+  for floats_per_attribute in layout {
+    builder = builder.try_with_attribute(AttribParameters::floats(*floats_per_attribute, floats_per_vertex, offset))?;
+    offset += *floats_per_attribute as usize;
+  }
+  Ok((builder, floats_per_vertex))
+}
+
+// The number of vertices when the positions and the other attributes describe the same vertices
+fn split_vertex_count(positions: &[GLfloat], attributes: &[GLfloat], floats_per_vertex: usize) -> Result<GLsizei, String> {
+  let vertex_count = positions.len() / 3;
+  if !positions.len().is_multiple_of(3) || attributes.len() != vertex_count * floats_per_vertex {
+    return Err(format!("{} position floats and {} attribute floats with {} per vertex do not describe the same vertices", positions.len(), attributes.len(), floats_per_vertex));
+  }
+  Ok(vertex_count as GLsizei)
+}
+
+unsafe fn populate_vbo<T>(vbo: GLuint, vertices: &[T]) {
   // ##  Setup vertex data
   gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
   gl::BufferData(
-    gl::ARRAY_BUFFER,                                       // target
-    (vertices.len() * size_of::<GLfloat>()) as GLsizeiptr,  // size in bytes
-    vertices.as_ptr() as *const GLvoid,                     // data
-    gl::STATIC_DRAW                                         // usage
+    gl::ARRAY_BUFFER,                       // target
+    size_of_val(vertices) as GLsizeiptr,    // size in bytes
+    vertices.as_ptr() as *const GLvoid,     // data
+    gl::STATIC_DRAW                         // usage
   );
   gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-  return (vertices.len()/floats_per_vertex) as _;
 }
 
-fn add_to_game(buffers: BufferComponent, game_state: &mut GameState, vertex_count: GLsizei) {
-  let vao = buffers.vao;
+// The index buffer binding is part of the vertex array, so the indices are uploaded while it is bound
+unsafe fn populate_ibo(vao: GLuint, ibo: GLuint, indices: &[GLuint]) {
+  gl::BindVertexArray(vao);
+  gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo);
+  gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, size_of_val(indices) as GLsizeiptr, indices.as_ptr() as *const GLvoid, gl::STATIC_DRAW);
+  gl::BindVertexArray(0);
+  gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+}
+
+// The entity takes ownership of the buffers
+fn add_to_game(buffers: BufferComponent, game_state: &mut GameState, vertex_count: GLsizei, bounds: Option<Aabb>) -> GenerationalIndex {
+  add_mesh_entity(game_state, Rc::new(buffers), vertex_count, bounds)
+}
+
+fn add_mesh_entity(game_state: &mut GameState, mesh: Rc<BufferComponent>, vertex_count: GLsizei, bounds: Option<Aabb>) -> GenerationalIndex {
   let model_matrix: Matrix4<GLfloat> = Matrix4::from_value(1.0);
   // add entity to game; todo: use allocator
   let generational_index = game_state.entity_allocator.allocate();
-  game_state.vaos.set(generational_index, vao);
+  game_state.meshes.set(generational_index, mesh);
   game_state.model_matrices.set(generational_index, model_matrix);
   game_state.vertex_counts.set(generational_index, vertex_count);
+  if let Some(aabb) = bounds {
+    game_state.bounds.set(generational_index, BoundingVolume::Box(aabb));
+  }
   game_state.entities.push(generational_index);
-}
\ No newline at end of file
+  generational_index
+}
+
+#[cfg(test)]
+mod model_creator_tests {
+  use super::*;
+  use crate::game_state::GameStateBuilder;
+
+  // no GL in these tests, a component without objects deletes nothing when dropped
+  fn empty_mesh() -> Rc<BufferComponent> {
+    Rc::new(unsafe { BufferComponent::from_raw(0, vec![0], 0, 0) })
+  }
+
+  #[test]
+  fn mesh_size_follows_to_every_entity_sharing_the_mesh() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    let mesh = add_mesh_entity(&mut game_state, empty_mesh(), 3, None);
+    let copy = add_instance(&mut game_state, mesh, Matrix4::from_value(1.0)).expect("entity should have a mesh");
+    let other = add_mesh_entity(&mut game_state, empty_mesh(), 3, None);
+    let bounds = Aabb::from_vertices(&[0.0, 0.0, 0.0, 1.0, 2.0, 3.0], 3).map(BoundingVolume::Box);
+    // act
+    set_mesh_size(&mut game_state, mesh, 6, bounds);
+    // assert
+    assert_eq!(Some(&6), game_state.vertex_counts.get(copy));
+    assert_eq!(bounds.as_ref(), game_state.bounds.get(copy));
+    assert_eq!(Some(&3), game_state.vertex_counts.get(other));
+    assert_eq!(None, game_state.bounds.get(other));
+  }
+
+  #[test]
+  fn animated_positions_cannot_outgrow_the_attributes() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    let entity = add_mesh_entity(&mut game_state, empty_mesh(), 2, None);
+    game_state.dynamic_buffers.set(entity, DynamicBuffer::new(0, 3, BufferUsage::Dynamic));
+    game_state.vertex_limits.set(entity, 2);
+    // act
+    let replaced = update_vertices(&mut game_state, entity, &[0.0; 9]);
+    let appended = update_vertex_range(&mut game_state, entity, 1, &[0.0; 6]);
+    // assert: nothing was uploaded, there is no GL in this test
+    assert!(replaced.is_err());
+    assert!(appended.is_err());
+    assert_eq!(Some(&2), game_state.vertex_counts.get(entity));
+  }
+
+  #[test]
+  fn layouts_with_oversized_attributes_are_an_error() {
+    // act: the builder makes no GL calls before build
+    let result = with_layout(VaoBuilder::new(), &[3, 5]);
+    // assert
+    assert_eq!(Some("invalid attribute at location 1: An attribute has 1 to 4 components, not 5".to_string()), result.err());
+  }
+
+  #[test]
+  fn indices_have_to_refer_to_vertices() {
+    // arrange
+    let mut game_state = GameStateBuilder::new().build();
+    // act: the indices are checked before any GL object is created
+    let result = add_indexed_model(&mut game_state, vec![0.0; 9], &[0, 1, 3], &[3], Material::new(Default::default()), gl::TRIANGLES);
+    // assert
+    assert_eq!(Some("Index 3 is out of range for 3 vertices".to_string()), result.err());
+    assert!(game_state.entities.is_empty());
+  }
+
+  #[test]
+  fn split_buffers_have_to_describe_the_same_vertices() {
+    assert_eq!(Ok(2), split_vertex_count(&[0.0; 6], &[0.0; 8], 4));
+    assert!(split_vertex_count(&[0.0; 6], &[0.0; 4], 4).is_err());
+    assert!(split_vertex_count(&[0.0; 5], &[0.0; 4], 4).is_err());
+  }
+}
@@ -1,10 +1,12 @@
 // external crates
 #[macro_use]
 extern crate if_chain;
+#[macro_use]
+extern crate log;
 // use gl::types::*;
-use gl::types::GLfloat;
 use engine::camera;
 use engine::shader_program;
+use engine::render_state::RenderState;
 // modules
 mod context;
 mod model_creator;
@@ -15,6 +17,7 @@ use game_builder::*;
 mod triangle_creator;
 use triangle_creator::*;
 mod game_state_renderer;
+mod frame_stats;
 
 fn main() -> Result<(), String> {
   start_game()
@@ -27,9 +30,9 @@ fn start_game() -> Result<(), String> {
   let game_builder = GameBuilder::new()
     .with_geometry_shader(geometry_glsl)
     .with_mode(gl::POINTS)
-    .with_shaders(vertex_glsl, fragment_glsl);
+    .with_shaders(vertex_glsl, fragment_glsl)
+    .with_render_state(RenderState::new().with_point_size(20.0));
   let mut game = game_builder.build();
-  unsafe { gl::PointSize(20.0); }
   add_triangle(&mut game.game_state);
   game.run()
 }
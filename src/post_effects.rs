@@ -0,0 +1,28 @@
+use gl::types::GLfloat;
+use engine::post_process::PostProcessEffect;
+
+// Ready made post-process effects for GameBuilder::with_post_effect
+
+#[allow(dead_code)]
+pub fn grayscale() -> PostProcessEffect {
+  PostProcessEffect::new("grayscale", include_str!("glsl/post/grayscale.glsl"))
+}
+
+#[allow(dead_code)]
+pub fn fxaa() -> PostProcessEffect {
+  PostProcessEffect::new("fxaa", include_str!("glsl/post/fxaa.glsl"))
+}
+
+// Darkens the corners, strength 0 leaves the image unchanged
+#[allow(dead_code)]
+pub fn vignette(strength: GLfloat) -> PostProcessEffect {
+  PostProcessEffect::new("vignette", include_str!("glsl/post/vignette.glsl"))
+    .with_float("Strength", strength)
+}
+
+// Converts linear colors for display, should be the last pass
+#[allow(dead_code)]
+pub fn gamma(gamma: GLfloat) -> PostProcessEffect {
+  PostProcessEffect::new("gamma", include_str!("glsl/post/gamma.glsl"))
+    .with_float("Gamma", gamma)
+}
@@ -0,0 +1,69 @@
+// external crates
+#[macro_use]
+extern crate if_chain;
+#[macro_use]
+extern crate log;
+use cgmath::{ Matrix4, Rad, Vector3 };
+use engine::camera;
+use engine::shader_program;
+use engine::sprite::{ Sprite, SpriteRect };
+use engine::texture::{ Texture, TextureBuilder, Filter, Wrap };
+use std::rc::Rc;
+// modules
+mod context;
+mod model_creator;
+mod event_handler;
+mod game_state;
+mod game_builder;
+use game_builder::*;
+mod game_state_renderer;
+mod frame_stats;
+
+// texels per world unit, the checker texture is a sheet of 16 by 16 cells
+const PIXELS_PER_UNIT: f32 = 16.0;
+
+fn main() -> Result<(), String> {
+  start_game()
+}
+
+// A row of tiles with sprites on top of them, drawn pixel perfect at a multiple of 320 by 180
+fn start_game() -> Result<(), String> {
+  let game_builder = GameBuilder::new()
+    .with_name("Hello Sprites")
+    .with_clear_color([0.1, 0.1, 0.15, 1.0])
+    .with_sprites()
+    .with_pixel_perfect_camera(PIXELS_PER_UNIT, 320, 180);
+  let mut game = game_builder.build();
+  let texture = TextureBuilder::from_file("textures/checker.png")?
+    .with_wrap(Wrap::ClampToEdge)
+    .with_filter(Filter::Nearest, Filter::Nearest)
+    .with_mipmaps(false)
+    .build()?;
+  add_sprites(&mut game, Rc::new(texture));
+  game.run()
+}
+
+// The sprites share the texture, it is deleted with the last of them when the game is done
+fn add_sprites(game: &mut Game, texture: Rc<Texture>) {
+  let tile = Sprite::new(&texture)
+    .with_rect(SpriteRect::cell(0, 0, 16.0, 16.0))
+    .with_pixels_per_unit(PIXELS_PER_UNIT)
+    .with_pivot(0.0, 0.0);
+  for x in -10..10 {
+    game.add_sprite(tile.clone().with_transform(Matrix4::from_translation(Vector3::new(x as f32, -4.0, 0.0))));
+  }
+  // standing on the tiles and drawn over them
+  let character = Sprite::new(&texture)
+    .with_rect(SpriteRect::cell(1, 1, 16.0, 32.0))
+    .with_pixels_per_unit(PIXELS_PER_UNIT)
+    .with_pivot(0.5, 0.0)
+    .with_tint([1.0, 0.6, 0.6, 1.0])
+    .with_layer(1)
+    .with_transform(Matrix4::from_translation(Vector3::new(0.0, -3.0, 0.0)));
+  game.add_sprite(character);
+  game.add_sprite(Sprite::new(&texture)
+    .with_pixels_per_unit(PIXELS_PER_UNIT)
+    .with_tint([0.6, 0.8, 1.0, 0.5])
+    .with_layer(-1)
+    .with_transform(Matrix4::from_translation(Vector3::new(4.0, 2.0, 0.0)) * Matrix4::from_angle_z(Rad(0.3))));
+}
@@ -1,47 +1,59 @@
-use std::fs::File;
 // external crates
 #[macro_use]
 extern crate if_chain;
-// use gl::types::*;
-use gl::types::GLfloat;
+#[macro_use]
+extern crate log;
+use cgmath::{ Matrix4, Point3, Vector3 };
 use engine::camera;
 use engine::shader_program;
-use fbx3d::decode_fbx;
+use engine::material::{ Material, ProgramId, UniformValue };
+use engine::light::{ DirectionalLight, PointLight };
+use engine::mesh::{ ground_plane, smooth_normals, interleave_position_normal };
+use engine::hdr::{ BloomSettings, HdrSettings };
 // modules
 mod context;
 mod model_creator;
+use model_creator::POSITION_NORMAL;
 mod event_handler;
 mod game_state;
 mod game_builder;
 use game_builder::*;
 mod game_state_renderer;
+mod frame_stats;
+mod post_effects;
+mod fbx_loader;
+use fbx_loader::load_fbx_mesh;
 
 fn main() -> Result<(), String> {
   start_game()
 }
 
 fn start_game() -> Result<(), String> {
-  let game_builder = GameBuilder::new().with_name("Hello Teapot");
+  let vertex_glsl: &str = include_str!("../src/glsl/lit/vertex.glsl");
+  let fragment_glsl: &str = include_str!("../src/glsl/lit/fragment.glsl");
+  let game_builder = GameBuilder::new()
+    .with_shaders(vertex_glsl, fragment_glsl)
+    .with_shadows(2048)
+    .with_hdr(HdrSettings::new().with_exposure(1.2).with_bloom(BloomSettings::default()))
+    .with_post_effect(post_effects::gamma(2.2))
+    .with_fps_overlay()
+    .with_name("Hello Teapot");
   let mut game = game_builder.build();
-  let mut f = File::open("teapot.fbx").unwrap();
-  let nodes = decode_fbx(&mut f).unwrap();
-  
-  println!("nodes len {}", nodes.len());
-  for node in nodes {
-    println!("================================================");
-    println!("node name {}", node.name);
-    println!("props.len {}", node.properties.len());
-    println!("subnodes.len {}", node.subnodes.len());
-    for prop in node.properties {
-      println!("prop {:?}", prop);
-    }
-    for subnode in node.subnodes {
-      println!("subnode name {}", subnode.name);
-      println!("subnode props.len {}", subnode.properties.len());
-      println!("subnode subnodes.len {}", subnode.subnodes.len());
-    }
-  }
-  // let vertices: Vec<GLfloat>;
-  // game.add_model(vertices);
+  let mesh = load_fbx_mesh("teapot.fbx")?;
+  let normals = smooth_normals(&mesh.positions, &mesh.indices);
+  let vertices = interleave_position_normal(&mesh.positions, &normals, &mesh.indices);
+  let material = Material::new(ProgramId(0))
+    .with_uniform("DiffuseColor", UniformValue::Vec3(Vector3::new(0.9, 0.45, 0.2)))
+    .with_float("Shininess", 64.0);
+  let teapot = game.add_model_with_layout(vertices, POSITION_NORMAL, material, gl::TRIANGLES);
+  let model_matrix = Matrix4::from_translation(Vector3::new(0.0, -0.35, 0.0)) * Matrix4::from_scale(0.45);
+  game.game_state.model_matrices.set(teapot, model_matrix);
+  let ground_material = Material::new(ProgramId(0))
+    .with_uniform("DiffuseColor", UniformValue::Vec3(Vector3::new(0.6, 0.6, 0.6)))
+    .with_float("Shininess", 8.0);
+  game.add_model_with_layout(ground_plane(1.5, -0.35), POSITION_NORMAL, ground_material, gl::TRIANGLES);
+  let sun = DirectionalLight::new(Vector3::new(-0.5, -1.0, 0.8), Vector3::new(1.0, 1.0, 0.95), 2.0).with_shadows();
+  game.game_state.add_directional_light(sun);
+  game.game_state.add_point_light(PointLight::new(Point3::new(-1.0, 0.5, -1.0), Vector3::new(0.3, 0.5, 1.0), 1.5, 5.0));
   game.run()
 }
@@ -0,0 +1,53 @@
+// external crates
+#[macro_use]
+extern crate if_chain;
+#[macro_use]
+extern crate log;
+use gl::types::GLfloat;
+use engine::camera;
+use engine::shader_program;
+use engine::material::{ Material, ProgramId };
+use engine::texture::{ TextureBuilder, Filter, Wrap };
+// modules
+mod context;
+mod model_creator;
+mod event_handler;
+mod game_state;
+mod game_builder;
+use game_builder::*;
+mod game_state_renderer;
+mod frame_stats;
+
+fn main() -> Result<(), String> {
+  start_game()
+}
+
+fn start_game() -> Result<(), String> {
+  let vertex_glsl: &str = include_str!("../src/glsl/textured/vertex.glsl");
+  let fragment_glsl: &str = include_str!("../src/glsl/textured/fragment.glsl");
+  let game_builder = GameBuilder::new()
+    .with_shaders(vertex_glsl, fragment_glsl)
+    .with_name("Hello Texture");
+  let mut game = game_builder.build();
+  let texture = TextureBuilder::from_file("textures/checker.png")?
+    .with_wrap(Wrap::ClampToEdge)
+    .with_filter(Filter::Linear, Filter::Nearest)
+    .build()?;
+  // the game keeps the texture until it is done with the window
+  let material = Material::new(ProgramId(0)).with_texture("Albedo", 0, game.add_texture(texture));
+  // the layout (position and UV) comes from the inputs of the vertex shader
+  game.add_model_for_program(get_quad_vertices(), 5, material, gl::TRIANGLES)?;
+  game.run()
+}
+
+fn get_quad_vertices() -> Vec<GLfloat> {
+  vec![
+    // X    Y   Z       U    V
+    -0.5, -0.5, 0.0,    0.0, 0.0,
+     0.5, -0.5, 0.0,    1.0, 0.0,
+     0.5,  0.5, 0.0,    1.0, 1.0,
+    -0.5, -0.5, 0.0,    0.0, 0.0,
+     0.5,  0.5, 0.0,    1.0, 1.0,
+    -0.5,  0.5, 0.0,    0.0, 1.0,
+  ]
+}
@@ -1,17 +1,16 @@
-use gl::types::*;
 use crate::game_state::GameState;
-use crate::model_creator::add_model;
+use crate::model_creator::add_vertex_model;
+use engine::vertex::PosColor;
 
 pub fn add_triangle(game_state: &mut GameState) -> Option<()> {
-  add_model(game_state, get_triangle_vertices())
+  add_vertex_model(game_state, &get_triangle_vertices());
+  Some(())
 }
 
-fn get_triangle_vertices() -> Vec<GLfloat> {
-  let vertices: Vec<GLfloat> = vec![
-    // X    Y   Z       R     G     B   A
-     0.0,  0.5, 0.0,    1.0, 0.0, 0.0, 1.0,
-    -0.5, -0.5, 0.0,    0.0, 1.0, 0.0, 1.0,
-     0.5, -0.5, 0.0,    0.0, 0.0, 1.0, 1.0,
-  ];
-  vertices
-}
\ No newline at end of file
+fn get_triangle_vertices() -> [PosColor; 3] {
+  [
+    PosColor { position: [ 0.0,  0.5, 0.0], color: [1.0, 0.0, 0.0, 1.0] },
+    PosColor { position: [-0.5, -0.5, 0.0], color: [0.0, 1.0, 0.0, 1.0] },
+    PosColor { position: [ 0.5, -0.5, 0.0], color: [0.0, 0.0, 1.0, 1.0] }
+  ]
+}